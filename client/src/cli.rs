@@ -0,0 +1,165 @@
+use crate::models::{
+    game::{Difficulty, GameMode},
+    lobby::LobbyRequest,
+};
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+const USAGE: &str = "Usage: client <username> [password] [--register] [--bot <easy|medium|hard>] [--mode <standard|practice|doubles|ffa|multiball|powerups|series>]\n       client <username> [password] [--register] <--lobby | --join <code>>\n       client <username> [password] --tournament\n       client --bracket\n       client --spectate [game_id]\n       client --replay <file>";
+
+#[derive(Debug)]
+pub enum ClientMode {
+    Play {
+        username: String,
+        password: String,
+        register: bool,
+        /// Play against a bot straight away instead of queueing for a person
+        bot: Option<Difficulty>,
+        game_mode: GameMode,
+        /// A private game with a friend instead of the public queue
+        lobby: Option<LobbyRequest>,
+        /// Play the next tournament match instead of the public queue
+        tournament: bool,
+    },
+    /// Show how the server's tournament is going, no login needed
+    Bracket,
+    /// Watch a game without logging in, with no id the running games are listed instead
+    Spectate(Option<u32>),
+    /// Play back a replay file saved by the server
+    Replay(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct ClientArgs {
+    pub mode: ClientMode,
+}
+
+impl ClientArgs {
+    /// Reads the command line, asking for the password on stdin when it isn't given
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut positional: Vec<String> = Vec::new();
+        let mut register = false;
+        let mut spectate = false;
+        let mut replay = false;
+        let mut bot: Option<Difficulty> = None;
+        let mut game_mode = GameMode::Standard;
+        let mut lobby: Option<LobbyRequest> = None;
+        let mut tournament = false;
+        let mut bracket = false;
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--register" => register = true,
+                "--bot" => {
+                    let difficulty = args.next().ok_or(USAGE.to_string())?;
+                    bot = Some(
+                        Difficulty::parse(&difficulty)
+                            .ok_or(format!("Unknown difficulty {}\n{}", difficulty, USAGE))?,
+                    );
+                }
+                "--mode" => {
+                    let mode = args.next().ok_or(USAGE.to_string())?;
+                    game_mode = GameMode::parse(&mode)
+                        .ok_or(format!("Unknown game mode {}\n{}", mode, USAGE))?;
+                }
+                // Short for --mode practice
+                "--practice" => game_mode = GameMode::Practice,
+                "--lobby" => lobby = Some(LobbyRequest::Create),
+                "--join" => {
+                    let code = args.next().ok_or(USAGE.to_string())?;
+                    lobby = Some(LobbyRequest::Join(code));
+                }
+                "--tournament" => tournament = true,
+                "--bracket" => bracket = true,
+                "--spectate" => spectate = true,
+                "--replay" => replay = true,
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown flag {}\n{}", flag, USAGE));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        match (bot, game_mode) {
+            (Some(_), GameMode::Practice) => {
+                return Err(format!("Practice is played alone, drop --bot\n{}", USAGE));
+            }
+            (Some(_), GameMode::FreeForAll) => {
+                return Err(format!(
+                    "Bots can't play free for all, drop --bot\n{}",
+                    USAGE
+                ));
+            }
+            _ => {}
+        }
+        if lobby.is_some() && (bot.is_some() || game_mode != GameMode::Standard) {
+            return Err(format!(
+                "Lobbies are for standard games against a friend, drop --bot and --mode\n{}",
+                USAGE
+            ));
+        }
+
+        if tournament && (lobby.is_some() || bot.is_some() || game_mode != GameMode::Standard) {
+            return Err(format!(
+                "Tournament matches are standard games, drop --bot, --mode and lobbies\n{}",
+                USAGE
+            ));
+        }
+
+        let mut positional = positional.into_iter();
+        if bracket {
+            return Ok(Self {
+                mode: ClientMode::Bracket,
+            });
+        }
+        if replay {
+            let replay_path = positional.next().ok_or(USAGE.to_string())?;
+            return Ok(Self {
+                mode: ClientMode::Replay(PathBuf::from(replay_path)),
+            });
+        }
+        if spectate {
+            let game_id = positional
+                .next()
+                .map(|game_id| {
+                    game_id
+                        .parse::<u32>()
+                        .map_err(|_| format!("Invalid game id {}\n{}", game_id, USAGE))
+                })
+                .transpose()?;
+            return Ok(Self {
+                mode: ClientMode::Spectate(game_id),
+            });
+        }
+
+        let username = positional.next().ok_or(USAGE.to_string())?;
+        let password = match positional.next() {
+            Some(password) => password,
+            None => prompt_password().map_err(|e| e.to_string())?,
+        };
+
+        Ok(Self {
+            mode: ClientMode::Play {
+                username,
+                password,
+                register,
+                bot,
+                game_mode,
+                lobby,
+                tournament,
+            },
+        })
+    }
+}
+
+fn prompt_password() -> io::Result<String> {
+    print!("Password: ");
+    io::stdout().flush()?;
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end().to_string())
+}
@@ -1,18 +1,37 @@
 use std::{
     env,
-    io::{Read, Write},
+    io::ErrorKind,
     net::TcpStream,
+    process,
     sync::mpsc::{self, Sender},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 // use chrono;
+pub mod cli;
 pub mod models;
+pub mod shutdown;
 
-use crossterm::terminal::disable_raw_mode;
+use cli::{ClientArgs, ClientMode};
+use crossterm::event::{self, Event};
 use flexi_logger::{FileSpec, Logger};
 use log::{Level, debug, info, warn};
-use models::game::{Game, GameUpdate};
+use models::{
+    auth::{AuthRequest, AuthStatus, SessionToken, cast_auth_result},
+    game::{
+        Game, GameEvent, GameMode, GameUpdate, MessageType, PlayerUpdate, PowerUps, PracticeScore,
+        SeriesScore,
+    },
+    lobby::{LobbyAction, LobbyRequest, LobbyScreen, LobbyState},
+    packet::{FrameReader, ServerPacket, write_frame},
+    replay::{Replay, ReplayViewer},
+    spectate::{GameSummary, spectate_request},
+    tournament::Bracket,
+};
 const SERVER_ADDRESS: &str = "127.0.0.1:9090";
+const RECONNECT_ATTEMPTS: usize = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 fn init_logger() {
     let file_spec = FileSpec::default()
@@ -64,79 +83,438 @@ fn init_logger() {
         .unwrap();
 }
 fn main() {
-    // println!("Hello, world!");
-    let args: Vec<String> = env::args().collect();
+    let client_args = match ClientArgs::parse(env::args()) {
+        Ok(client_args) => client_args,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
 
-    let username: String;
+    // env_logger::init();
+    init_logger();
+    shutdown::install_handlers();
 
-    if args.len() > 1 {
-        username = args[1].clone();
-    } else {
-        username = "Default Name".to_string();
+    // Replays are played straight from disk, there is no server involved
+    if let ClientMode::Replay(replay_path) = &client_args.mode {
+        match Replay::load(replay_path) {
+            Ok(replay) => ReplayViewer::new(replay).play(),
+            Err(e) => {
+                eprintln!("Failed to open replay {}: {}", replay_path.display(), e);
+                process::exit(1);
+            }
+        }
+        shutdown::restore_terminal();
+        return;
     }
 
-    // env_logger::init();
-    init_logger();
     let tcp_connection = TcpStream::connect(SERVER_ADDRESS).unwrap();
     info!("Connected to server {}", SERVER_ADDRESS);
-    let mut buf: [u8; 1024] = [0; 1024];
+    // Reads give up regularly so a shutdown request is noticed while waiting on the server
+    tcp_connection
+        .set_read_timeout(Some(READ_POLL_INTERVAL))
+        .unwrap();
     let mut reader_stream = tcp_connection.try_clone().unwrap();
     let mut writer_stream = tcp_connection;
-    let _ = writer_stream.write(username.as_bytes());
+    let mut frame_reader = FrameReader::new();
+    let mut lobby_screen: Option<LobbyScreen> = None;
 
-    let mut pipe_sender: Option<Sender<GameUpdate>> = None;
-    let mut _game_thread_handler: Option<JoinHandle<()>> = None;
+    // Spectators never log in, so there is no session to resume if they lose the connection
+    let session_token: Option<SessionToken> = match client_args.mode {
+        ClientMode::Play {
+            username,
+            password,
+            register,
+            bot,
+            game_mode,
+            lobby,
+            tournament,
+        } => {
+            // These have to reach the server before logging in puts us in the queue
+            let mut requests: Vec<Vec<u8>> = Vec::new();
+            if game_mode != GameMode::Standard {
+                requests.push(vec![MessageType::SelectMode as u8, game_mode as u8]);
+            }
+            if let Some(difficulty) = bot {
+                requests.push(vec![MessageType::PlayBot as u8, difficulty as u8]);
+            }
+            if let Some(lobby) = &lobby {
+                requests.push(lobby.as_bytes());
+            }
+            if tournament {
+                requests.push(vec![MessageType::JoinTournament as u8]);
+            }
+            for request in requests {
+                if let Err(e) = write_frame(&mut writer_stream, &request) {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            let auth_request = if register {
+                AuthRequest::Register { username, password }
+            } else {
+                AuthRequest::Login { username, password }
+            };
+            match authenticate(
+                &auth_request,
+                &mut reader_stream,
+                &mut writer_stream,
+                &mut frame_reader,
+            ) {
+                Ok(token) => {
+                    match (&lobby, game_mode, bot) {
+                        // The server says whether there is a match for us
+                        _ if tournament => {}
+                        (Some(lobby), _, _) => {
+                            lobby_screen = Some(LobbyScreen::new(*lobby == LobbyRequest::Create))
+                        }
+                        (_, GameMode::Practice, _) => println!("Logged in, starting practice..."),
+                        (_, _, Some(difficulty)) => {
+                            println!(
+                                "Logged in, starting a game against a {:?} bot...",
+                                difficulty
+                            )
+                        }
+                        (_, GameMode::Series, None) => {
+                            println!("Logged in, waiting for an opponent for a series...")
+                        }
+                        (_, GameMode::Doubles | GameMode::FreeForAll, _) => {
+                            println!("Logged in, waiting for three more players...")
+                        }
+                        _ => println!("Logged in, waiting for an opponent..."),
+                    }
+                    Some(token)
+                }
+                Err(message) => {
+                    eprintln!("{}", message);
+                    process::exit(1);
+                }
+            }
+        }
+        ClientMode::Spectate(None) => {
+            match list_games(&mut reader_stream, &mut writer_stream, &mut frame_reader) {
+                Ok(summaries) if summaries.is_empty() => println!("No games running"),
+                Ok(summaries) => {
+                    println!("{:>6}  Players  Score", "Game");
+                    for summary in summaries {
+                        println!("{}", summary);
+                    }
+                }
+                Err(message) => {
+                    eprintln!("{}", message);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        ClientMode::Bracket => {
+            match show_bracket(&mut reader_stream, &mut writer_stream, &mut frame_reader) {
+                Ok(bracket) => print!("{}", bracket),
+                Err(message) => {
+                    eprintln!("{}", message);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        ClientMode::Spectate(Some(game_id)) => {
+            if let Err(message) = join_game(
+                game_id,
+                &mut reader_stream,
+                &mut writer_stream,
+                &mut frame_reader,
+            ) {
+                eprintln!("{}", message);
+                process::exit(1);
+            }
+            println!("Watching game {}", game_id);
+            None
+        }
+        ClientMode::Replay(_) => unreachable!("Replays are played before connecting"),
+    };
+
+    let mut pipe_sender: Option<Sender<GameEvent>> = None;
+    let mut game_thread_handler: Option<JoinHandle<()>> = None;
+    let mut game_over = false;
     loop {
-        debug!("Reading from stream");
-        match reader_stream.read(&mut buf) {
-            Ok(0) => {
-                // 0 bytes read = EOF, connection closed
-                debug!("Connection closed.");
+        if shutdown::shutdown_requested() {
+            let game_running = game_thread_handler
+                .as_ref()
+                .is_some_and(|game_thread| !game_thread.is_finished());
+            // A running game sends the Shutdown message itself and waits for the reply
+            if !game_running {
+                let shutdown_packet = PlayerUpdate::command(MessageType::Shutdown);
+                let _ = write_frame(&mut writer_stream, &shutdown_packet.as_bytes());
+                info!("Shutting down");
                 break;
             }
-            Ok(n) => {
-                debug!("Received: {:?}", &buf[..n]);
-                if buf[0] == 0 {
-                    // println!("{} ", n);
-                    let (tx, rx) = mpsc::channel::<GameUpdate>();
-                    pipe_sender = Some(tx);
-
-                    let mut game = Game::new(rx, writer_stream.try_clone().unwrap());
-                    game.initialize_game(&buf[..n]);
-                    // game.draw_matrix();
-
-                    let game_thread = thread::Builder::new()
-                        .name("Game Thread".to_string())
-                        .spawn(move || {
-                            game.start_game();
-                        })
-                        .unwrap();
-
-                    debug!("Game thread started :: {:?}", game_thread);
-
-                    _game_thread_handler = Some(game_thread);
-
-                    // }
-                } else if buf[0] == 1 {
-                    let game_update = GameUpdate::cast_packet(&buf[..n]);
-                    if let Some(pipe) = &pipe_sender {
-                        match pipe.send(game_update) {
-                            Ok(_) => debug!("Pipe sent successfully"),
-                            Err(r) => {
-                                warn!("Error sending game update {:?}", r);
-                            }
+        }
+        if let Some(lobby) = &lobby_screen
+            && let Ok(true) = event::poll(Duration::ZERO)
+            && let Ok(Event::Key(key_event)) = event::read()
+        {
+            match lobby.key_stroke(key_event) {
+                LobbyAction::Send(message) => {
+                    let _ = write_frame(&mut writer_stream, &message);
+                }
+                LobbyAction::Quit => shutdown::request_shutdown(),
+                LobbyAction::None => {}
+            }
+        }
+        debug!("Reading from stream");
+        match frame_reader.read_frame(&mut reader_stream) {
+            Ok(Some(buf)) => {
+                debug!("Received: {:?}", buf);
+                match ServerPacket::from(buf[0]) {
+                    ServerPacket::Snapshot => {
+                        // The game takes over the screen and keyboard from the lobby
+                        lobby_screen = None;
+                        // A game that is already running rebuilds itself from the snapshot
+                        if let Some(pipe) = &pipe_sender
+                            && pipe.send(GameEvent::Snapshot(buf.clone())).is_ok()
+                        {
+                            continue;
+                        }
+                        let (tx, rx) = mpsc::channel::<GameEvent>();
+                        pipe_sender = Some(tx);
+
+                        let mut game = Game::new(rx, writer_stream.try_clone().unwrap());
+                        game.initialize_game(&buf);
+                        // game.draw_matrix();
+
+                        let game_thread = thread::Builder::new()
+                            .name("Game Thread".to_string())
+                            .spawn(move || {
+                                game.start_game();
+                            })
+                            .unwrap();
+
+                        debug!("Game thread started :: {:?}", game_thread);
+
+                        game_thread_handler = Some(game_thread);
+                    }
+                    ServerPacket::Update => forward_event(
+                        &pipe_sender,
+                        GameEvent::Update(GameUpdate::cast_packet(&buf)),
+                    ),
+                    ServerPacket::Status => {
+                        forward_event(&pipe_sender, GameEvent::cast_status(&buf))
+                    }
+                    ServerPacket::GameOver => {
+                        game_over = true;
+                        forward_event(&pipe_sender, GameEvent::cast_game_over(&buf));
+                    }
+                    ServerPacket::PracticeScore => {
+                        if let Some(practice_score) = PracticeScore::cast_bytes(&buf[1..]) {
+                            forward_event(&pipe_sender, GameEvent::PracticeScore(practice_score))
+                        }
+                    }
+                    ServerPacket::PowerUps => {
+                        if let Some(power_ups) = PowerUps::cast_bytes(&buf[1..]) {
+                            forward_event(&pipe_sender, GameEvent::PowerUps(power_ups))
+                        }
+                    }
+                    ServerPacket::Series => {
+                        if let Some(series_score) = SeriesScore::cast_bytes(&buf[1..]) {
+                            forward_event(&pipe_sender, GameEvent::Series(series_score))
                         }
-                    } else {
-                        warn!("Trying to send on non existing pipe");
                     }
+                    ServerPacket::ShutdownAck => {
+                        forward_event(&pipe_sender, GameEvent::ShutdownAck)
+                    }
+                    // Shown by whatever has the screen, the game, the lobby or the plain terminal
+                    ServerPacket::Message => {
+                        let text = String::from_utf8_lossy(&buf[1..]).to_string();
+                        match (&pipe_sender, &mut lobby_screen) {
+                            (Some(_), _) => forward_event(&pipe_sender, GameEvent::Message(text)),
+                            (None, Some(lobby)) => lobby.show_message(text),
+                            (None, None) => println!("Server: {}", text),
+                        }
+                    }
+                    ServerPacket::Lobby => match (&mut lobby_screen, LobbyState::cast_packet(&buf))
+                    {
+                        (Some(lobby), Ok(state)) => lobby.update(state),
+                        (_, Err(message)) => {
+                            shutdown::restore_terminal();
+                            eprintln!("{}", message);
+                            process::exit(1);
+                        }
+                        (None, Ok(_)) => warn!("Lobby update outside of a lobby"),
+                    },
+                    ServerPacket::Tournament => match Bracket::cast_packet(&buf) {
+                        Ok(bracket) if bracket.waiting => {
+                            print!("{}", bracket);
+                            println!("\nWaiting for your tournament match...");
+                        }
+                        Ok(_) => warn!("Bracket sent while playing"),
+                        Err(message) => {
+                            eprintln!("{}", message);
+                            process::exit(1);
+                        }
+                    },
+                    packet => warn!("Unexpected packet {:?}", packet),
                 }
             }
+            Ok(None) => {}
             Err(e) => {
-                warn!("Error occurred {:?}", e);
-                // disable_raw_mode().unwrap();
-                break;
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    debug!("Connection closed.");
+                } else {
+                    warn!("Error occurred {:?}", e);
+                }
+                let Some(session_token) = &session_token else {
+                    break;
+                };
+                if pipe_sender.is_none() || game_over || shutdown::shutdown_requested() {
+                    break;
+                }
+
+                forward_event(&pipe_sender, GameEvent::ConnectionLost);
+                match reconnect(session_token) {
+                    Some((new_reader, new_writer, new_frame_reader)) => {
+                        reader_stream = new_reader;
+                        writer_stream = new_writer;
+                        frame_reader = new_frame_reader;
+                        forward_event(
+                            &pipe_sender,
+                            GameEvent::NewConnection(writer_stream.try_clone().unwrap()),
+                        );
+                    }
+                    None => {
+                        warn!("Giving up on reconnecting");
+                        break;
+                    }
+                }
             }
         }
     }
-    disable_raw_mode().unwrap();
+    // Dropping the pipe lets the game thread finish if it hasn't already
+    drop(pipe_sender);
+    if let Some(game_thread) = game_thread_handler {
+        let _ = game_thread.join();
+    }
+    shutdown::restore_terminal();
+}
+
+fn forward_event(pipe_sender: &Option<Sender<GameEvent>>, game_event: GameEvent) {
+    if let Some(pipe) = pipe_sender {
+        match pipe.send(game_event) {
+            Ok(_) => debug!("Pipe sent successfully"),
+            Err(r) => {
+                warn!("Error sending game update {:?}", r);
+            }
+        }
+    } else {
+        warn!("Trying to send on non existing pipe");
+    }
+}
+
+/// Tries to get back into the game with the session token from the original login
+fn reconnect(session_token: &SessionToken) -> Option<(TcpStream, TcpStream, FrameReader)> {
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        thread::sleep(RECONNECT_DELAY);
+        if shutdown::shutdown_requested() {
+            return None;
+        }
+        info!("Reconnect attempt {}/{}", attempt, RECONNECT_ATTEMPTS);
+        let Ok(tcp_connection) = TcpStream::connect(SERVER_ADDRESS) else {
+            continue;
+        };
+        tcp_connection
+            .set_read_timeout(Some(READ_POLL_INTERVAL))
+            .ok()?;
+        let mut reader_stream = tcp_connection.try_clone().ok()?;
+        let mut writer_stream = tcp_connection;
+        let mut frame_reader = FrameReader::new();
+
+        match authenticate(
+            &AuthRequest::Resume(*session_token),
+            &mut reader_stream,
+            &mut writer_stream,
+            &mut frame_reader,
+        ) {
+            Ok(_) => return Some((reader_stream, writer_stream, frame_reader)),
+            Err(message) => warn!("Reconnect failed: {}", message),
+        }
+    }
+    None
+}
+
+/// Sends the login or register request and waits for the server to answer it
+fn authenticate(
+    auth_request: &AuthRequest,
+    reader_stream: &mut TcpStream,
+    writer_stream: &mut TcpStream,
+    frame_reader: &mut FrameReader,
+) -> Result<SessionToken, String> {
+    write_frame(writer_stream, &auth_request.as_bytes()).map_err(|e| e.to_string())?;
+    let buf = wait_for_packet(ServerPacket::AuthResult, reader_stream, frame_reader)?;
+
+    let (status, token) = cast_auth_result(&buf);
+    info!("Authentication result: {:?}", status);
+    match (status, token) {
+        (AuthStatus::Ok, Some(token)) => Ok(token),
+        (status, _) => Err(status.to_string()),
+    }
+}
+
+/// Waits for the next packet of the given type, anything else is logged and skipped
+fn wait_for_packet(
+    packet_type: ServerPacket,
+    reader_stream: &mut TcpStream,
+    frame_reader: &mut FrameReader,
+) -> Result<Vec<u8>, String> {
+    loop {
+        let buf = match frame_reader.read_frame(reader_stream) {
+            Ok(Some(buf)) => buf,
+            Ok(None) if shutdown::shutdown_requested() => return Err("Interrupted".to_string()),
+            Ok(None) => continue,
+            Err(e) => return Err(format!("Lost connection to the server: {}", e)),
+        };
+        if ServerPacket::from(buf[0]) == packet_type {
+            return Ok(buf);
+        }
+        warn!(
+            "Ignoring packet {:?} while waiting for {:?}",
+            buf, packet_type
+        );
+    }
+}
+
+/// Asks the server which games are running and can be watched
+fn list_games(
+    reader_stream: &mut TcpStream,
+    writer_stream: &mut TcpStream,
+    frame_reader: &mut FrameReader,
+) -> Result<Vec<GameSummary>, String> {
+    let list_packet = PlayerUpdate::command(MessageType::ListGames);
+    write_frame(writer_stream, &list_packet.as_bytes()).map_err(|e| e.to_string())?;
+    let buf = wait_for_packet(ServerPacket::GameList, reader_stream, frame_reader)?;
+    Ok(GameSummary::cast_game_list(&buf))
+}
+
+/// Asks the server for the tournament's bracket
+fn show_bracket(
+    reader_stream: &mut TcpStream,
+    writer_stream: &mut TcpStream,
+    frame_reader: &mut FrameReader,
+) -> Result<Bracket, String> {
+    write_frame(writer_stream, &[MessageType::Bracket as u8]).map_err(|e| e.to_string())?;
+    let buf = wait_for_packet(ServerPacket::Tournament, reader_stream, frame_reader)?;
+    Bracket::cast_packet(&buf)
+}
+
+/// Asks to watch a game, the server starts sending it straight after agreeing
+fn join_game(
+    game_id: u32,
+    reader_stream: &mut TcpStream,
+    writer_stream: &mut TcpStream,
+    frame_reader: &mut FrameReader,
+) -> Result<(), String> {
+    write_frame(writer_stream, &spectate_request(game_id)).map_err(|e| e.to_string())?;
+    let buf = wait_for_packet(ServerPacket::SpectateResult, reader_stream, frame_reader)?;
+    match buf.get(1) {
+        Some(0) => Ok(()),
+        _ => Err(format!("No running game with id {}", game_id)),
+    }
 }
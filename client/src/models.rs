@@ -1,2 +1,8 @@
+pub mod auth;
 pub mod game;
 pub mod game_drawer;
+pub mod lobby;
+pub mod packet;
+pub mod replay;
+pub mod spectate;
+pub mod tournament;
@@ -0,0 +1,94 @@
+use std::fmt::Display;
+
+use super::game::MessageType;
+
+pub const TOKEN_LENGTH: usize = 16;
+
+pub type SessionToken = [u8; TOKEN_LENGTH];
+
+pub enum AuthRequest {
+    Login { username: String, password: String },
+    Register { username: String, password: String },
+    Resume(SessionToken),
+}
+
+impl AuthRequest {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut v_data: Vec<u8> = Vec::new();
+        match self {
+            AuthRequest::Login { username, password }
+            | AuthRequest::Register { username, password } => {
+                let message_type = if let AuthRequest::Login { .. } = self {
+                    MessageType::Login
+                } else {
+                    MessageType::Register
+                };
+                v_data.push(message_type as u8);
+                v_data.push(username.len() as u8);
+                v_data.extend_from_slice(username.as_bytes());
+                v_data.extend_from_slice(password.as_bytes());
+            }
+            AuthRequest::Resume(token) => {
+                v_data.push(MessageType::Resume as u8);
+                v_data.extend_from_slice(token);
+            }
+        }
+        v_data
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuthStatus {
+    Ok = 0,
+    InvalidCredentials = 1,
+    NameTaken = 2,
+    InvalidName = 3,
+    WeakPassword = 4,
+    SessionExpired = 5,
+    MalformedRequest = 6,
+    ServerError = 7,
+    UnknownMode = 8,
+}
+
+impl From<u8> for AuthStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            0 => AuthStatus::Ok,
+            1 => AuthStatus::InvalidCredentials,
+            2 => AuthStatus::NameTaken,
+            3 => AuthStatus::InvalidName,
+            4 => AuthStatus::WeakPassword,
+            5 => AuthStatus::SessionExpired,
+            6 => AuthStatus::MalformedRequest,
+            8 => AuthStatus::UnknownMode,
+            _ => AuthStatus::ServerError,
+        }
+    }
+}
+
+impl Display for AuthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            AuthStatus::Ok => "Logged in",
+            AuthStatus::InvalidCredentials => "Invalid username or password",
+            AuthStatus::NameTaken => "Username already registered",
+            AuthStatus::InvalidName => "Username must be 1-16 letters, digits, '_' or '-'",
+            AuthStatus::WeakPassword => "Password is too short",
+            AuthStatus::SessionExpired => "Session expired, log in again",
+            AuthStatus::MalformedRequest => "Server did not understand the request",
+            AuthStatus::ServerError => "Server error",
+            AuthStatus::UnknownMode => "The server doesn't have that game mode",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// Splits an auth result packet into its status and, when successful, the session token
+pub fn cast_auth_result(buf: &[u8]) -> (AuthStatus, Option<SessionToken>) {
+    let status = AuthStatus::from(buf.get(1).copied().unwrap_or(u8::MAX));
+    let token = buf
+        .get(2..2 + TOKEN_LENGTH)
+        .map(|token| token.try_into().unwrap());
+    (status, token)
+}
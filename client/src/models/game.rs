@@ -1,44 +1,344 @@
 use std::{
-    io::Write,
     net::TcpStream,
-    sync::mpsc::Receiver,
+    sync::mpsc::{Receiver, RecvTimeoutError},
     thread::{self},
-    time::Duration,
+    time::{Duration, Instant},
     vec,
 };
 
-use super::game_drawer::{GameRender, Tile};
-use crossterm::{
-    event::{Event, poll, read},
-    terminal::disable_raw_mode,
+use super::{
+    game_drawer::{GameRender, Tile},
+    packet::{GameOverReason, GameStatus, write_frame},
 };
-use log::{debug, warn};
+use crate::shutdown;
+use crossterm::event::{Event, KeyModifiers, poll, read};
+use log::{debug, info, warn};
 #[derive(Debug)]
 pub struct GameUpdate {
     pub packet_id: u8,
     pub player_id: u8,
-    pub player_1_pos: u8,
-    pub player_2_pos: u8,
-    pub ball_x: u8,
-    pub ball_y: u8,
+    /// Every paddle's position, player 1 first
+    pub positions: Vec<u8>,
+    /// Every ball as `(x, y)`, there is always at least one
+    pub balls: Vec<(u8, u8)>,
 }
 
 impl GameUpdate {
+    /// `[id, player id, paddle 1, paddle 2, ball x, ball y, more paddles count, paddle 3..,
+    /// more balls count, (ball x, ball y)..]`
     pub fn cast_packet(buf: &[u8]) -> Self {
         let packet_id = buf[0];
         let player_id = buf[1];
-        let player_1 = buf[2];
-        let player_2 = buf[3];
-        let ball_pos_x = buf[4];
-        let ball_pos_y = buf[5];
+        let more_paddles = buf.get(6).copied().unwrap_or(0) as usize;
+        let end = (7 + more_paddles).min(buf.len());
+        let mut positions = vec![buf[2], buf[3]];
+        positions.extend_from_slice(buf.get(7..end).unwrap_or_default());
+        let (balls, _) = cast_balls((buf[4], buf[5]), &buf[end..]);
 
         Self {
             packet_id,
             player_id,
-            player_1_pos: player_1,
-            player_2_pos: player_2,
-            ball_x: ball_pos_x,
-            ball_y: ball_pos_y,
+            positions,
+            balls,
+        }
+    }
+}
+
+/// The first ball plus `[count, (x, y)..]` for the rest, returns the balls and whatever
+/// comes after them
+pub fn cast_balls(first: (u8, u8), buf: &[u8]) -> (Vec<(u8, u8)>, &[u8]) {
+    let count = buf.first().copied().unwrap_or(0) as usize;
+    let end = (1 + count * 2).min(buf.len());
+    let mut balls = vec![first];
+    balls.extend(
+        buf[1.min(end)..end]
+            .chunks_exact(2)
+            .map(|ball| (ball[0], ball[1])),
+    );
+    (balls, &buf[end..])
+}
+
+/// Which wall a paddle guards
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Side {
+    Left = 0,
+    Right = 1,
+    Top = 2,
+    Bottom = 3,
+}
+
+impl From<u8> for Side {
+    fn from(side: u8) -> Self {
+        match side {
+            1 => Side::Right,
+            2 => Side::Top,
+            3 => Side::Bottom,
+            _ => Side::Left,
+        }
+    }
+}
+
+impl Side {
+    /// Left and right paddles move up and down, top and bottom ones sideways
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Side::Left | Side::Right)
+    }
+}
+
+/// One paddle as the server drew it for us, already flipped if we see the map that way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Paddle {
+    pub player_id: u8,
+    pub team: u8,
+    pub side: Side,
+    /// The column of a vertical paddle, or the row of a horizontal one
+    pub line: i32,
+    pub position: i32,
+    /// How far it reaches either side of `position`
+    pub size: i32,
+}
+
+impl Paddle {
+    /// `[count, (player id, team, side, line, position)..]`, returns the paddles and
+    /// whatever comes after them. They all start out `size`.
+    pub fn cast_list(buf: &[u8], size: i32) -> (Vec<Self>, &[u8]) {
+        let count = buf.first().copied().unwrap_or(0) as usize;
+        let end = (1 + count * 5).min(buf.len());
+        let paddles = buf[1.min(end)..end]
+            .chunks_exact(5)
+            .map(|paddle| Self {
+                player_id: paddle[0],
+                team: paddle[1],
+                side: Side::from(paddle[2]),
+                line: paddle[3].into(),
+                position: paddle[4].into(),
+                size,
+            })
+            .collect();
+        (paddles, &buf[end..])
+    }
+}
+
+/// Everything the network thread forwards to the game thread
+#[derive(Debug)]
+pub enum GameEvent {
+    Snapshot(Vec<u8>),
+    Update(GameUpdate),
+    Status {
+        status: GameStatus,
+        seconds_left: u8,
+        player_idx: u8,
+    },
+    GameOver {
+        /// The winning team, 0 if nobody won
+        winner: u8,
+        /// One per team
+        scores: Vec<u8>,
+        reason: GameOverReason,
+    },
+    PracticeScore(PracticeScore),
+    PowerUps(PowerUps),
+    Series(SeriesScore),
+    /// Something the server's admin wants everyone to read
+    Message(String),
+    /// The connection dropped and was re-established, positions go out on this stream now
+    NewConnection(TcpStream),
+    ConnectionLost,
+    /// The server has seen our Shutdown message and let go of us
+    ShutdownAck,
+}
+
+impl GameEvent {
+    pub fn cast_status(buf: &[u8]) -> Self {
+        GameEvent::Status {
+            status: GameStatus::from(buf[1]),
+            seconds_left: buf[2],
+            player_idx: buf[3],
+        }
+    }
+
+    /// `[id, winner, score 1, score 2, reason, score 3..]`
+    pub fn cast_game_over(buf: &[u8]) -> Self {
+        let mut scores = vec![buf[2], buf[3]];
+        scores.extend_from_slice(buf.get(5..).unwrap_or_default());
+        GameEvent::GameOver {
+            winner: buf[1],
+            scores,
+            reason: GameOverReason::from(buf[4]),
+        }
+    }
+}
+
+/// What is in the way on the map, as sent after the balls in the snapshot
+#[derive(Clone, Debug, Default)]
+pub struct Arena {
+    /// Rows in the middle of each end wall that let the ball through
+    pub goal_width: i32,
+    /// `(x, y, width, height)`
+    pub obstacles: Vec<(i32, i32, i32, i32)>,
+}
+
+impl Arena {
+    /// `[goal width, obstacle count, (x, y, width, height)..]`, returns the arena and
+    /// whatever comes after it. Left out, it is an empty map with goals all the way along.
+    pub fn cast_bytes(buf: &[u8], map_height: i32) -> (Self, &[u8]) {
+        let Some(&[goal_width, count]) = buf.get(..2) else {
+            return (
+                Self {
+                    goal_width: map_height - 2,
+                    obstacles: vec![],
+                },
+                buf,
+            );
+        };
+        let end = (2 + count as usize * 4).min(buf.len());
+        let obstacles = buf[2..end]
+            .chunks_exact(4)
+            .map(|obstacle| {
+                (
+                    obstacle[0].into(),
+                    obstacle[1].into(),
+                    obstacle[2].into(),
+                    obstacle[3].into(),
+                )
+            })
+            .collect();
+        let arena = Self {
+            goal_width: goal_width.into(),
+            obstacles,
+        };
+        (arena, &buf[end..])
+    }
+
+    /// Goals are centred on the end walls
+    pub fn in_goal(&self, y: i32, map_height: i32) -> bool {
+        let start = (map_height - self.goal_width) / 2;
+        (start..start + self.goal_width).contains(&y)
+    }
+
+    /// The walls with the goals cut out of them and the obstacles, everything that
+    /// doesn't move
+    pub fn build_map(&self, map_width: i32, map_height: i32) -> Vec<Tile> {
+        let mut map = vec![Tile::Empty; (map_height * map_width) as usize];
+        for y in 0..map_height {
+            for x in 0..map_width {
+                if x == 0 || x == map_width - 1 {
+                    map[(y * map_width + x) as usize] = Tile::VerticalWall;
+                } else if y == 0 || y == map_height - 1 {
+                    map[(y * map_width + x) as usize] = Tile::HorizontalWall;
+                }
+            }
+        }
+        // The end walls are only open where the goals are
+        for y in 1..map_height - 1 {
+            if !self.in_goal(y, map_height) {
+                map[(y * map_width) as usize] = Tile::Obstacle;
+                map[(y * map_width + map_width - 1) as usize] = Tile::Obstacle;
+            }
+        }
+        for &(x, y, width, height) in &self.obstacles {
+            for obstacle_y in y..y + height {
+                for obstacle_x in x..x + width {
+                    map[(obstacle_y * map_width + obstacle_x) as usize] = Tile::Obstacle;
+                }
+            }
+        }
+        map[0] = Tile::Corner;
+        map[((map_height - 1) * map_width) as usize] = Tile::Corner;
+        map[(map_width - 1) as usize] = Tile::Corner;
+        map[((map_height - 1) * map_width + (map_width - 1)) as usize] = Tile::Corner;
+        map
+    }
+}
+
+/// Pickups on the map and what every player has going, sent whenever either changes
+#[derive(Clone, Debug, Default)]
+pub struct PowerUps {
+    /// `(power up, x, y)`
+    pub pickups: Vec<(u8, u8, u8)>,
+    /// `(paddle size, effects)` for every player, player 1 first. Effects has bit
+    /// `1 << power up` set for each one the player has going.
+    pub players: Vec<(u8, u8)>,
+}
+
+impl PowerUps {
+    /// Names for the effects bits, in the order the server numbers them
+    const NAMES: [&str; 5] = ["Big paddle", "Shrink", "Fast ball", "Sticky", "Shield"];
+
+    /// `[pickup count, (power up, x, y).., player count, (paddle size, effects)..]`, without
+    /// the packet id in front
+    pub fn cast_bytes(buf: &[u8]) -> Option<Self> {
+        let pickup_count = *buf.first()? as usize;
+        let pickups = buf
+            .get(1..1 + pickup_count * 3)?
+            .chunks_exact(3)
+            .map(|pickup| (pickup[0], pickup[1], pickup[2]))
+            .collect();
+        let rest = &buf[1 + pickup_count * 3..];
+        let player_count = *rest.first()? as usize;
+        let players = rest
+            .get(1..1 + player_count * 2)?
+            .chunks_exact(2)
+            .map(|player| (player[0], player[1]))
+            .collect();
+        Some(Self { pickups, players })
+    }
+
+    /// What the effects bits stand for
+    pub fn effect_names(effects: u8) -> Vec<&'static str> {
+        (0..Self::NAMES.len())
+            .filter(|bit| effects & (1 << bit) != 0)
+            .map(|bit| Self::NAMES[bit])
+            .collect()
+    }
+}
+
+/// Where a practice game is at, sent whenever the streak changes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PracticeScore {
+    pub streak: u8,
+    pub best_streak: u8,
+    pub personal_best: u8,
+}
+
+impl PracticeScore {
+    /// `[streak, best this game, personal best]`, with or without the packet id in front
+    pub fn cast_bytes(buf: &[u8]) -> Option<Self> {
+        match buf {
+            [streak, best_streak, personal_best, ..] => Some(Self {
+                streak: *streak,
+                best_streak: *best_streak,
+                personal_best: *personal_best,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Where a series is at, sent with every goal and at the start of every game
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeriesScore {
+    pub best_of: u8,
+    pub game_points: u8,
+    /// Games won, team 1 first
+    pub wins: [u8; 2],
+    /// Goals in the game being played
+    pub points: [u8; 2],
+}
+
+impl SeriesScore {
+    /// `[best of, points to win a game, games won 1, games won 2, points 1, points 2]`,
+    /// with or without the packet id in front
+    pub fn cast_bytes(buf: &[u8]) -> Option<Self> {
+        match buf {
+            [best_of, game_points, wins_1, wins_2, points_1, points_2, ..] => Some(Self {
+                best_of: *best_of,
+                game_points: *game_points,
+                wins: [*wins_1, *wins_2],
+                points: [*points_1, *points_2],
+            }),
+            _ => None,
         }
     }
 }
@@ -56,47 +356,173 @@ impl PlayerUpdate {
             data,
         }
     }
+    pub fn command(message_type: MessageType) -> Self {
+        Self {
+            packet_type: message_type,
+            data: 0,
+        }
+    }
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut v_data: Vec<u8> = Vec::new();
-        v_data.push(self.packet_type.clone() as u8);
-        v_data.push(self.data);
-        v_data
+        vec![self.packet_type.clone() as u8, self.data]
     }
 }
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum MessageType {
     PlayerPos = 0,
     Shutdown = 1,
+    Login = 2,
+    Register = 3,
+    Resume = 4,
+    Pause = 5,
+    Unpause = 6,
+    Forfeit = 7,
+    ListGames = 8,
+    Spectate = 9,
+    PlayBot = 10,
+    SelectMode = 11,
+    CreateLobby = 12,
+    JoinLobby = 13,
+    LobbyRules = 14,
+    Ready = 15,
+    Bracket = 16,
+    JoinTournament = 17,
+}
+
+/// What kind of game to ask the server for, also sent back in the snapshot
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(u8)]
+pub enum GameMode {
+    #[default]
+    Standard = 0,
+    /// Alone against a wall, counting returns in a row
+    Practice = 1,
+    /// Two against two, a keeper and a forward each side
+    Doubles = 2,
+    /// Four players, one on each wall
+    FreeForAll = 3,
+    /// Two players, more balls join as the game goes on
+    MultiBall = 4,
+    /// Two players, with pickups to hit the ball through
+    PowerUps = 5,
+    /// Standard games back to back, whoever wins most of them wins
+    Series = 6,
+}
+
+impl From<u8> for GameMode {
+    fn from(mode: u8) -> Self {
+        match mode {
+            1 => GameMode::Practice,
+            2 => GameMode::Doubles,
+            3 => GameMode::FreeForAll,
+            4 => GameMode::MultiBall,
+            5 => GameMode::PowerUps,
+            6 => GameMode::Series,
+            _ => GameMode::Standard,
+        }
+    }
+}
+
+impl GameMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "standard" => Some(GameMode::Standard),
+            "practice" => Some(GameMode::Practice),
+            "doubles" => Some(GameMode::Doubles),
+            "ffa" | "free-for-all" => Some(GameMode::FreeForAll),
+            "multiball" | "multi-ball" => Some(GameMode::MultiBall),
+            "powerups" | "power-ups" => Some(GameMode::PowerUps),
+            "series" => Some(GameMode::Series),
+            _ => None,
+        }
+    }
+}
+
+/// How good the bot opponent is
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Difficulty {
+    Easy = 0,
+    Medium = 1,
+    Hard = 2,
+}
+
+impl Difficulty {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+/// How long the game waits on the server before polling input and redrawing anyway
+const UPDATE_TIMEOUT: Duration = Duration::from_millis(50);
+/// How long to wait for the server to acknowledge a shutdown before leaving anyway
+const SHUTDOWN_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a message from the server stays on the status line
+const MESSAGE_TIME: Duration = Duration::from_secs(8);
+
+#[derive(Debug, PartialEq)]
+pub enum InputAction {
+    Continue,
+    Quit,
+    /// Something the server has to act on, like pausing or forfeiting
+    Command(MessageType),
 }
 
 #[derive(Debug)]
 pub struct Game {
-    reader_pipe: Receiver<GameUpdate>,
+    reader_pipe: Receiver<GameEvent>,
     writer_stream: TcpStream,
+    connected: bool,
+    status_message: Option<String>,
+    /// From the server's admin, along with when it stops being shown
+    server_message: Option<(String, Instant)>,
+    result_message: Option<String>,
+    forfeit_armed: bool,
+    shutdown_deadline: Option<Instant>,
+    game_mode: GameMode,
+    practice_score: PracticeScore,
+    series_score: SeriesScore,
     packet_id: u8,
     player_id: u8,
-    player_1_y: i32,
-    player_2_y: i32,
-    ball_pos_x: u8,
-    ball_pos_y: u8,
-    paddle_size: i32,
+    paddles: Vec<Paddle>,
+    /// Where our own paddle is in `paddles`, spectators don't have one
+    own_paddle: Option<usize>,
+    /// Every ball as `(x, y)`
+    balls: Vec<(u8, u8)>,
+    /// Pickups waiting on the map as `(power up, x, y)`
+    pickups: Vec<(u8, u8, u8)>,
+    /// The effects bits of whatever we have going
+    effects: u8,
     map_width: i32,
     map_height: i32,
     map: Vec<Tile>,
 }
 impl Game {
-    pub fn new(reader_pipe: Receiver<GameUpdate>, writer_stream: TcpStream) -> Self {
+    pub fn new(reader_pipe: Receiver<GameEvent>, writer_stream: TcpStream) -> Self {
         Self {
-            reader_pipe: reader_pipe,
-            writer_stream: writer_stream,
+            reader_pipe,
+            writer_stream,
+            connected: true,
+            status_message: None,
+            server_message: None,
+            result_message: None,
+            forfeit_armed: false,
+            shutdown_deadline: None,
+            game_mode: GameMode::Standard,
+            practice_score: PracticeScore::default(),
+            series_score: SeriesScore::default(),
             packet_id: 0,
             player_id: 0,
-            player_1_y: 0,
-            player_2_y: 0,
-            ball_pos_x: 0,
-            ball_pos_y: 0,
-            paddle_size: 0,
+            paddles: vec![],
+            own_paddle: None,
+            balls: vec![],
+            pickups: vec![],
+            effects: 0,
             map_width: 0,
             map_height: 0,
             map: vec![],
@@ -106,204 +532,281 @@ impl Game {
         debug!("Init packet: {:?}", buf);
         let packet_id = buf[0];
         let player_id = buf[1];
-        let player_1: i32 = buf[2].into();
-        let player_2: i32 = buf[3].into();
 
-        let ball_pos_x = buf[4];
-        let ball_pos_y = buf[5];
         let map_width: i32 = buf[6].into();
         let map_height: i32 = buf[7].into();
         let paddle_size = buf[8].into();
-        let mut map = vec![Tile::Empty; (map_height * map_width) as usize];
-
-        for y in 0..map_height {
-            for x in 0..map_width {
-                if x == 0 || x == map_width - 1 {
-                    map[(y * map_width + x) as usize] = Tile::VerticalWall;
-                } else if y == 0 || y == map_height - 1 {
-                    map[(y * map_width + x) as usize] = Tile::HorizontalWall;
+        if let Some(seed) = buf.get(9..17) {
+            info!("Game seed {}", u64::from_le_bytes(seed.try_into().unwrap()));
+        }
+        let game_mode = buf.get(17).copied().map(GameMode::from).unwrap_or_default();
+        let (paddles, rest) = Paddle::cast_list(buf.get(18..).unwrap_or_default(), paddle_size);
+        let (balls, rest) = cast_balls((buf[4], buf[5]), rest);
+        let (arena, rest) = Arena::cast_bytes(rest, map_height);
+        // The ball's speed, only worth knowing when something looks off
+        let mode_extras = match rest.split_first() {
+            Some((ball_speed, mode_extras)) => {
+                info!("Ball speed {}", ball_speed);
+                mode_extras
+            }
+            None => rest,
+        };
+        let power_ups = match game_mode {
+            GameMode::Practice => {
+                if let Some(practice_score) = PracticeScore::cast_bytes(mode_extras) {
+                    self.practice_score = practice_score;
                 }
-
-                if y == 5 {
-                    // map[(y * map_width + x) as usize] = Tile::Debug
+                None
+            }
+            GameMode::PowerUps => PowerUps::cast_bytes(mode_extras),
+            GameMode::Series => {
+                if let Some(series_score) = SeriesScore::cast_bytes(mode_extras) {
+                    self.series_score = series_score;
                 }
+                None
             }
-        }
-        debug!("Building paddles");
-        debug!(
-            "{} {} {} {:?} {:?}",
-            player_1,
-            player_2,
-            paddle_size,
-            (player_1 - paddle_size)..(player_1 + paddle_size),
-            (player_2 - paddle_size)..(player_2 + paddle_size)
-        );
+            _ => None,
+        };
+        let mut map = arena.build_map(map_width, map_height);
         debug!(
             "Map Bounds: {} {} Max Array: {}",
             map_width,
             map_height,
             map_width * map_height
         );
-        for paddle_pos in (player_2 - paddle_size)..(player_2 + paddle_size) {
-            debug!("Player 2 Paddle {}", paddle_pos);
-            map[(paddle_pos * map_width + (map_width - 3)) as usize] = Tile::Player;
-            // map[(paddle_pos * map_width + 3) as usize] = Tile::Player;
-        }
-        for paddle_pos in (player_1 - paddle_size)..(player_1 + paddle_size + 1) {
-            debug!(
-                "Player 1 Paddle {} {:?}",
-                paddle_pos,
-                (paddle_pos, map_height - 2)
-            );
-            map[(paddle_pos * map_width + 2) as usize] = Tile::Player;
-        }
-        // debug!("")
-        // let index =
-        //     ((self.map_height - 3) * self.map_width + self.player_1_x - paddle_size) as usize;
-        // map[index] = Tile::Player;
-        map[(0 * map_width + 0) as usize] = Tile::Corner;
-        map[((map_height - 1) * map_width + 0) as usize] = Tile::Corner;
-        map[(0 * map_width + (map_width - 1)) as usize] = Tile::Corner;
-        map[((map_height - 1) * map_width + (map_width - 1)) as usize] = Tile::Corner;
-
-        // debug!("Le number {}", 0 * map_width + 3);
-        // debug!("Le number{}", 1 * map_width + 3);
-        // map[(1 * map_width + 3) as usize] = Tile::Debug;
+        if game_mode == GameMode::Practice {
+            // The wall stands where the other paddle would be, all the way down
+            for wall_y in 1..map_height - 1 {
+                map[(wall_y * map_width + (map_width - 3)) as usize] = Tile::VerticalWall;
+            }
+        }
 
         self.packet_id = packet_id;
+        self.game_mode = game_mode;
         self.player_id = player_id;
-        self.player_1_y = player_1.into();
-        self.player_2_y = player_2.into();
-        self.ball_pos_x = ball_pos_x;
-        self.ball_pos_y = ball_pos_y;
-        self.map_width = map_width.into();
-        self.map_height = map_height.into();
-        self.paddle_size = paddle_size;
+        self.own_paddle = paddles
+            .iter()
+            .position(|paddle| paddle.player_id == player_id);
+        self.balls = balls;
+        self.map_width = map_width;
+        self.map_height = map_height;
         self.map = map;
-        // debug!("Setup complete {:?}", self)
-    }
-    pub fn key_stroke_move(
-        &mut self,
-        event: crossterm::event::KeyEvent,
-    ) -> std::result::Result<(), ()> {
-        match event.code {
-            crossterm::event::KeyCode::Backspace => {
-                disable_raw_mode().unwrap();
-                Err(())
-            }
-            // crossterm::event::KeyCode::Left => {
-            //     // debug!("Left arrow pressed, going right");
-            //     if self.player_1_y - PADDLE_SIZE - 1 > 0 {
-            //         self.player_1_y -= 1;
-            //         self.map[((self.map_height - 3) * self.map_width
-            //             + (self.player_1_y + self.paddle_size))
-            //             as usize] = Tile::Empty;
-
-            //         self.map[((self.map_height - 3) * self.map_width
-            //             + (self.player_1_y - self.paddle_size))
-            //             as usize] = Tile::Player;
-            //     }
-            //     debug!("Left key input");
-            //     Ok(())
-            // }
-            // crossterm::event::KeyCode::Right => {
-            //     debug!("Right arrow pressed, going right");
-            //     if self.player_1_y + PADDLE_SIZE - 1 < self.map_width - 1 {
-            //         self.player_1_y += 1;
-            //         self.map[((self.map_height - 3) * self.map_width
-            //             + (self.player_1_y + self.paddle_size))
-            //             as usize] = Tile::Player;
-
-            //         self.map[((self.map_height - 3) * self.map_width
-            //             + (self.player_1_y - self.paddle_size))
-            //             as usize] = Tile::Empty;
-            //     }
-            //     debug!("Right key input");
-            //     Ok(())
-            // }
-            crossterm::event::KeyCode::Up => {
-                debug!("Up arrow pressed, going up");
-                if self.player_1_y - self.paddle_size - 1 > 0 {
-                    self.player_1_y -= 1;
-                    self.map
-                        [((self.player_1_y - self.paddle_size) * self.map_width + 2) as usize] =
-                        Tile::Player;
-
-                    self.map[((self.player_1_y + self.paddle_size + 1) * self.map_width + 2)
-                        as usize] = Tile::Empty;
-                    // self.map[((self.map_height - 3) * self.map_width
-                    //     + (self.player_1_y - self.paddle_size))
-                    //     as usize] = Tile::Empty;
-                }
 
-                Ok(())
-            }
-            crossterm::event::KeyCode::Down => {
-                debug!("Down arrow pressed, going down");
-                if self.player_1_y + self.paddle_size + 1 < self.map_height - 1 {
-                    self.player_1_y += 1;
-                    self.map[((self.player_1_y - self.paddle_size - 1) * self.map_width + 2)
-                        as usize] = Tile::Empty;
-
-                    self.map
-                        [((self.player_1_y + self.paddle_size) * self.map_width + 2) as usize] =
-                        Tile::Player;
-                    // self.map[((self.map_height - 3) * self.map_width
-                    //     + (self.player_1_y - self.paddle_size))
-                    //     as usize] = Tile::Empty;
+        debug!("Building paddles {:?}", paddles);
+        self.paddles = paddles;
+        for paddle_idx in 0..self.paddles.len() {
+            if self.shows_paddle(paddle_idx) {
+                for index in self.paddle_tiles(&self.paddles[paddle_idx]) {
+                    self.map[index] = Tile::Player;
                 }
+            }
+        }
+        if let Some(power_ups) = power_ups {
+            self.apply_power_ups(power_ups);
+        }
+    }
 
-                Ok(())
+    /// Practice only draws the player's own paddle, the wall takes the other one's place
+    fn shows_paddle(&self, paddle_idx: usize) -> bool {
+        self.game_mode != GameMode::Practice || self.own_paddle == Some(paddle_idx)
+    }
+
+    /// Where on the map the paddle is drawn
+    fn paddle_tiles(&self, paddle: &Paddle) -> Vec<usize> {
+        ((paddle.position - paddle.size)..=(paddle.position + paddle.size))
+            .map(|along| {
+                let (x, y) = if paddle.side.is_vertical() {
+                    (paddle.line, along)
+                } else {
+                    (along, paddle.line)
+                };
+                (y * self.map_width + x) as usize
+            })
+            .collect()
+    }
+
+    /// Moves our own paddle a step along its wall, as long as it stays clear of the walls
+    fn nudge_own_paddle(&mut self, step: i32) {
+        let Some(own_paddle) = self.own_paddle else {
+            return;
+        };
+        let paddle = self.paddles[own_paddle];
+        let length = if paddle.side.is_vertical() {
+            self.map_height
+        } else {
+            self.map_width
+        };
+        let new_pos = paddle.position + step;
+        if new_pos - paddle.size > 0 && new_pos + paddle.size < length - 1 {
+            self.move_paddle(own_paddle, new_pos);
+        }
+    }
+
+    pub fn key_stroke_move(&mut self, event: crossterm::event::KeyEvent) -> InputAction {
+        // Forfeiting needs a second press of F, anything else backs out of it
+        let forfeit_armed = std::mem::take(&mut self.forfeit_armed);
+        // Spectators have no paddle, all they can do is leave
+        if self.is_spectator() {
+            return match event.code {
+                crossterm::event::KeyCode::Backspace => InputAction::Quit,
+                crossterm::event::KeyCode::Char('c')
+                    if event.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    InputAction::Quit
+                }
+                _ => InputAction::Continue,
+            };
+        }
+        match event.code {
+            crossterm::event::KeyCode::Backspace => InputAction::Quit,
+            // Raw mode swallows the signal, so Ctrl-C arrives here as a key press
+            crossterm::event::KeyCode::Char('c')
+                if event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                InputAction::Quit
             }
-            _ => Ok(()),
+            crossterm::event::KeyCode::Char('p' | 'P') => InputAction::Command(MessageType::Pause),
+            crossterm::event::KeyCode::Char('r' | 'R') => {
+                InputAction::Command(MessageType::Unpause)
+            }
+            crossterm::event::KeyCode::Char('f' | 'F') => {
+                if forfeit_armed {
+                    InputAction::Command(MessageType::Forfeit)
+                } else {
+                    self.forfeit_armed = true;
+                    InputAction::Continue
+                }
+            }
+            crossterm::event::KeyCode::Up | crossterm::event::KeyCode::Down
+                if self.own_side().is_some_and(Side::is_vertical) =>
+            {
+                debug!("{:?} arrow pressed", event.code);
+                let step = if event.code == crossterm::event::KeyCode::Up {
+                    -1
+                } else {
+                    1
+                };
+                self.nudge_own_paddle(step);
+                InputAction::Continue
+            }
+            crossterm::event::KeyCode::Left | crossterm::event::KeyCode::Right
+                if self.own_side().is_some_and(|side| !side.is_vertical()) =>
+            {
+                debug!("{:?} arrow pressed", event.code);
+                let step = if event.code == crossterm::event::KeyCode::Left {
+                    -1
+                } else {
+                    1
+                };
+                self.nudge_own_paddle(step);
+                InputAction::Continue
+            }
+            _ => InputAction::Continue,
         }
     }
     pub fn update_game_state(&mut self, game_update: GameUpdate) {
-        let opponent_prev_pos: i32;
-        let opponent_new_pos: i32;
+        // Our own paddle is moved by us, spectators get every one from the server
+        for paddle_idx in 0..self.paddles.len() {
+            if self.own_paddle == Some(paddle_idx) || !self.shows_paddle(paddle_idx) {
+                continue;
+            }
+            let player_id = self.paddles[paddle_idx].player_id;
+            if let Some(position) = game_update.positions.get(player_id as usize - 1) {
+                self.move_paddle(paddle_idx, (*position).into());
+            }
+        }
 
-        if self.player_id == 1 {
-            opponent_prev_pos = self.player_2_y;
-            opponent_new_pos = game_update.player_2_pos.into();
-            self.player_2_y = game_update.player_2_pos.into();
-        } else {
-            opponent_prev_pos = self.player_2_y;
-            opponent_new_pos = game_update.player_1_pos.into();
-            self.player_2_y = game_update.player_1_pos.into();
+        // Balls come and go, so all the old ones are cleared before the new ones are drawn
+        for (x, y) in std::mem::take(&mut self.balls) {
+            self.map[(y as i32 * self.map_width + x as i32) as usize] = Tile::Empty;
         }
+        // A ball rolling over a pickup doesn't always take it
+        self.draw_pickups();
+        self.balls = game_update.balls;
+        for (x, y) in &self.balls {
+            self.map[(*y as i32 * self.map_width + *x as i32) as usize] = Tile::Ball;
+        }
+    }
 
-        self.map[(self.ball_pos_y as i32 * self.map_width + self.ball_pos_x as i32) as usize] =
-            Tile::Empty;
-        self.ball_pos_x = game_update.ball_x;
-        self.ball_pos_y = game_update.ball_y;
-        self.map[(self.ball_pos_y as i32 * self.map_width + self.ball_pos_x as i32) as usize] =
-            Tile::Ball;
+    /// Swaps in the new pickups and paddle sizes
+    fn apply_power_ups(&mut self, power_ups: PowerUps) {
+        for (power_up, x, y) in std::mem::take(&mut self.pickups) {
+            let index = (y as i32 * self.map_width + x as i32) as usize;
+            if self.map.get(index).cloned() == Tile::pickup(power_up) {
+                self.map[index] = Tile::Empty;
+            }
+        }
+        self.pickups = power_ups.pickups;
+        self.draw_pickups();
+
+        for (player_id, (size, effects)) in (1..).zip(power_ups.players) {
+            if player_id == self.player_id {
+                self.effects = effects;
+            }
+            let Some(paddle_idx) = self
+                .paddles
+                .iter()
+                .position(|paddle| paddle.player_id == player_id)
+            else {
+                continue;
+            };
+            if self.shows_paddle(paddle_idx) {
+                self.resize_paddle(paddle_idx, size.into());
+            }
+        }
+    }
 
-        let new_paddle_range =
-            (opponent_new_pos - self.paddle_size)..=(opponent_new_pos + self.paddle_size);
+    /// Pickups only go on empty tiles, paddles and balls are drawn over them
+    fn draw_pickups(&mut self) {
+        for (power_up, x, y) in &self.pickups {
+            let index = (*y as i32 * self.map_width + *x as i32) as usize;
+            if let (Some(tile), Some(pickup)) = (self.map.get_mut(index), Tile::pickup(*power_up))
+                && *tile == Tile::Empty
+            {
+                *tile = pickup;
+            }
+        }
+    }
 
-        let old_paddle_range =
-            (opponent_prev_pos - self.paddle_size)..=(opponent_prev_pos + self.paddle_size);
+    /// Our own paddle might not fit where it is any more, it is pulled back from the walls
+    fn resize_paddle(&mut self, paddle_idx: usize, size: i32) {
+        let mut paddle = self.paddles[paddle_idx];
+        if paddle.size == size {
+            return;
+        }
+        paddle.size = size;
+        if self.own_paddle == Some(paddle_idx) {
+            let length = if paddle.side.is_vertical() {
+                self.map_height
+            } else {
+                self.map_width
+            };
+            paddle.position = paddle.position.clamp(size + 1, length - size - 2);
+        }
+        self.redraw_paddle(paddle_idx, paddle);
+    }
 
-        // map[(paddle_pos * map_width + map_width - 3) as usize] = Tile::Player;
+    fn move_paddle(&mut self, paddle_idx: usize, new_pos: i32) {
+        let paddle = Paddle {
+            position: new_pos,
+            ..self.paddles[paddle_idx]
+        };
+        self.redraw_paddle(paddle_idx, paddle);
+    }
 
-        for paddle_tile in old_paddle_range {
-            if !new_paddle_range.contains(&paddle_tile) {
-                self.map[(paddle_tile * self.map_width + self.map_width - 3) as usize] =
-                    Tile::Empty;
-                // let index = (paddle_tile * self.map_width + self.map_width - 3) as usize;
-                // let tile = self.map.get_mut(index);
+    /// Clears the tiles the paddle no longer covers and draws the ones it does now
+    fn redraw_paddle(&mut self, paddle_idx: usize, paddle: Paddle) {
+        let old_tiles = self.paddle_tiles(&self.paddles[paddle_idx]);
+        self.paddles[paddle_idx] = paddle;
+        let new_tiles = self.paddle_tiles(&self.paddles[paddle_idx]);
 
-                // if let Some(tile) = tile {
-                //     if *tile == Tile::Player {
-                //         *tile = Tile::Empty;
-                //     }
-                // }
+        for index in old_tiles {
+            if !new_tiles.contains(&index) {
+                self.map[index] = Tile::Empty;
             }
         }
 
-        for tile_y in new_paddle_range {
-            let index = (tile_y * self.map_width + self.map_width - 3) as usize;
+        for index in new_tiles {
             let tile = self.map.get_mut(index);
             if let Some(tile) = tile {
                 if *tile == Tile::Empty {
@@ -315,6 +818,229 @@ impl Game {
         }
     }
 
+    fn own_side(&self) -> Option<Side> {
+        self.own_paddle
+            .map(|own_paddle| self.paddles[own_paddle].side)
+    }
+
+    /// The team we play for, spectators are on none
+    fn own_team(&self) -> u8 {
+        self.own_paddle
+            .map_or(0, |own_paddle| self.paddles[own_paddle].team)
+    }
+
+    /// Spectators are sent a player id of 0
+    pub fn is_spectator(&self) -> bool {
+        self.player_id == 0
+    }
+
+    /// Our own effects, shown while nothing more pressing is going on
+    fn power_up_line(&self) -> Option<String> {
+        if self.game_mode != GameMode::PowerUps || self.effects == 0 {
+            return None;
+        }
+        Some(format!(
+            "Power-ups: {}",
+            PowerUps::effect_names(self.effects).join(", ")
+        ))
+    }
+
+    /// Shown while nothing more pressing is going on
+    fn practice_line(&self) -> Option<String> {
+        if self.game_mode != GameMode::Practice {
+            return None;
+        }
+        let score = self.practice_score;
+        Some(format!(
+            "Returns: {}  Best: {}  Personal best: {}",
+            score.streak, score.best_streak, score.personal_best
+        ))
+    }
+
+    /// Games and points with our own first, shown the whole way through a series
+    fn series_line(&self) -> Option<String> {
+        if self.game_mode != GameMode::Series {
+            return None;
+        }
+        let score = self.series_score;
+        Some(format!(
+            "Game {} of {}, first to {}  Games {}  Points {}",
+            score.wins[0] + score.wins[1] + 1,
+            score.best_of,
+            score.game_points,
+            self.score_line(&score.wins),
+            self.score_line(&score.points)
+        ))
+    }
+
+    /// Scores with our own first when it is us against one other team, in team order otherwise
+    fn score_line(&self, scores: &[u8]) -> String {
+        match scores {
+            [score_1, score_2] if self.own_team() == 2 => format!("{}:{}", score_2, score_1),
+            scores => scores
+                .iter()
+                .map(|score| score.to_string())
+                .collect::<Vec<String>>()
+                .join(":"),
+        }
+    }
+
+    /// Applies an event from the server, returns false once the game is over
+    pub fn handle_event(&mut self, game_event: GameEvent) -> bool {
+        match game_event {
+            GameEvent::Snapshot(buf) => {
+                // Sent again after a reconnect, the whole map is rebuilt from it
+                self.initialize_game(&buf);
+                self.status_message = None;
+            }
+            GameEvent::Update(game_update) => self.update_game_state(game_update),
+            GameEvent::Status {
+                status,
+                seconds_left,
+                player_idx,
+            } => {
+                let by_self = player_idx == self.player_id;
+                self.status_message = match status {
+                    GameStatus::Running => None,
+                    GameStatus::Paused if self.is_spectator() => Some(format!(
+                        "Player {} paused, {}s left",
+                        player_idx, seconds_left
+                    )),
+                    GameStatus::PausedAgreed if self.is_spectator() => {
+                        Some(format!("Paused by agreement, {}s left", seconds_left))
+                    }
+                    GameStatus::WaitingForReconnect => Some(format!(
+                        "Waiting for a player to reconnect... {}s",
+                        seconds_left
+                    )),
+                    GameStatus::Paused if by_self => {
+                        Some(format!("Paused, {}s left. R to resume", seconds_left))
+                    }
+                    GameStatus::Paused if self.paddles.len() <= 2 => Some(format!(
+                        "Opponent paused, {}s left. P to agree to a longer pause",
+                        seconds_left
+                    )),
+                    GameStatus::Paused => Some(format!(
+                        "Player {} paused, {}s left. P to agree to a longer pause",
+                        player_idx, seconds_left
+                    )),
+                    GameStatus::PausedAgreed => Some(format!(
+                        "Paused by agreement, {}s left. R to resume",
+                        seconds_left
+                    )),
+                    GameStatus::Resuming => Some(format!("Resuming in {}...", seconds_left)),
+                };
+            }
+            GameEvent::GameOver {
+                winner,
+                scores,
+                reason,
+            } => {
+                // Practice has no winner, the scores are the best streak and personal best
+                if self.game_mode == GameMode::Practice {
+                    self.result_message = Some(format!(
+                        "Practice over, best streak {} (personal best {})",
+                        scores[0], scores[1]
+                    ));
+                    return false;
+                }
+                let mut score_line = self.score_line(&scores);
+                // A series reports the games won instead of the points
+                if self.game_mode == GameMode::Series {
+                    score_line.push_str(" in games");
+                }
+                // With more than one other player there is nobody in particular to blame
+                let two_players = self.paddles.len() <= 2;
+                if self.is_spectator() {
+                    let outcome = match winner {
+                        0 => "Game over".to_string(),
+                        winner if self.game_mode == GameMode::Doubles => {
+                            format!("Team {} wins", winner)
+                        }
+                        winner => format!("Player {} wins", winner),
+                    };
+                    let cause = match reason {
+                        GameOverReason::Finished => "",
+                        GameOverReason::Kicked => " A player was kicked by the server.",
+                        GameOverReason::Ended => " The server ended the game.",
+                        GameOverReason::Crashed => " The game crashed on the server.",
+                        GameOverReason::Forfeit if two_players => " The other player forfeited.",
+                        GameOverReason::Abandoned if two_players => {
+                            " The other player left the game."
+                        }
+                        GameOverReason::Forfeit => " A player forfeited.",
+                        GameOverReason::Abandoned => " A player left the game.",
+                    };
+                    self.result_message = Some(format!("{} {}{}", outcome, score_line, cause));
+                    return false;
+                }
+                let own_team = self.own_team();
+                let outcome = match winner {
+                    0 => "Game over",
+                    winner if winner == own_team => "You win!",
+                    _ => "You lose",
+                };
+                let cause = match reason {
+                    GameOverReason::Finished => "",
+                    GameOverReason::Ended => " The server ended the game.",
+                    GameOverReason::Crashed => " The game crashed on the server.",
+                    GameOverReason::Kicked if !two_players => " A player was kicked by the server.",
+                    GameOverReason::Kicked if winner == own_team => {
+                        " Opponent was kicked by the server."
+                    }
+                    GameOverReason::Kicked => " You were kicked by the server.",
+                    GameOverReason::Forfeit if !two_players => " A player forfeited.",
+                    GameOverReason::Abandoned if !two_players => " A player left the game.",
+                    GameOverReason::Forfeit if winner == own_team => " Opponent forfeited.",
+                    GameOverReason::Forfeit => " You forfeited.",
+                    GameOverReason::Abandoned => " Opponent left the game.",
+                };
+                self.result_message = Some(format!("{} {}{}", outcome, score_line, cause));
+                return false;
+            }
+            GameEvent::PracticeScore(practice_score) => self.practice_score = practice_score,
+            GameEvent::PowerUps(power_ups) => self.apply_power_ups(power_ups),
+            GameEvent::Series(series_score) => self.series_score = series_score,
+            GameEvent::Message(text) => {
+                self.server_message =
+                    Some((format!("Server: {}", text), Instant::now() + MESSAGE_TIME))
+            }
+            GameEvent::NewConnection(writer_stream) => {
+                self.writer_stream = writer_stream;
+                self.connected = true;
+            }
+            GameEvent::ConnectionLost => {
+                self.connected = false;
+                self.status_message = Some("Connection lost, reconnecting...".to_string());
+            }
+            GameEvent::ShutdownAck => {
+                debug!("Server acknowledged shutdown");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Tells the server we are leaving, the game loop then waits a moment for it to agree
+    fn begin_shutdown(&mut self) {
+        if self.shutdown_deadline.is_some() {
+            return;
+        }
+        shutdown::request_shutdown();
+        self.status_message = Some("Leaving the game...".to_string());
+        self.shutdown_deadline = Some(Instant::now() + SHUTDOWN_ACK_TIMEOUT);
+
+        if self.connected {
+            let shutdown_packet = PlayerUpdate::command(MessageType::Shutdown);
+            if let Err(e) = write_frame(&mut self.writer_stream, &shutdown_packet.as_bytes()) {
+                warn!("Failed to send shutdown to server: {:?}", e);
+                self.shutdown_deadline = Some(Instant::now());
+            }
+        } else {
+            self.shutdown_deadline = Some(Instant::now());
+        }
+    }
+
     pub fn draw_matrix(&mut self) {
         println!("{}", self.map.len());
         for y in 0..self.map_height {
@@ -326,31 +1052,44 @@ impl Game {
             }
             println!("{}", matrix_string)
         }
-        println!("")
+        println!()
     }
     pub fn start_game(&mut self) {
         let mut game_render = GameRender::setup_renderer(self.map_height, self.map_width);
         let delay = Duration::from_millis(10);
         'main_loop: loop {
-            let game_update = self.reader_pipe.recv().unwrap();
-            debug!("Game Update recved{:?}", game_update);
-            self.update_game_state(game_update);
+            match self.reader_pipe.recv_timeout(UPDATE_TIMEOUT) {
+                Ok(game_event) => {
+                    debug!("Game Update recved{:?}", game_event);
+                    if !self.handle_event(game_event) {
+                        break 'main_loop;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break 'main_loop,
+            }
             if let Ok(key_pressed) = poll(Duration::from_millis(1)) {
                 if key_pressed {
-                    match read().unwrap() {
-                        Event::Key(event) => {
-                            let player_move = self.key_stroke_move(event);
-                            match player_move {
-                                Ok(_) => {
-                                    debug!("Received input :: {:?}", event);
-                                }
-                                Err(e) => {
-                                    warn!("Error occurred getting input: {:?}. Exiting loop.", e);
-                                    break 'main_loop;
+                    if let Event::Key(event) = read().unwrap() {
+                        let player_move = self.key_stroke_move(event);
+                        match player_move {
+                            InputAction::Continue => {
+                                debug!("Received input :: {:?}", event);
+                            }
+                            InputAction::Quit => {
+                                info!("Quit key pressed, shutting down");
+                                self.begin_shutdown();
+                            }
+                            InputAction::Command(message_type) => {
+                                debug!("Sending command {:?}", message_type);
+                                let command = PlayerUpdate::command(message_type);
+                                if let Err(e) =
+                                    write_frame(&mut self.writer_stream, &command.as_bytes())
+                                {
+                                    warn!("Failed to send command to server: {:?}", e);
                                 }
                             }
                         }
-                        _ => {}
                     };
                 } else {
                     // No key pressed
@@ -360,29 +1099,60 @@ impl Game {
             // disable_raw_mode();
             // self.draw_matrix();
             // enable_raw_mode();
-            game_render.render_game(&self.map, self.player_id);
-
-            let bytes = self.player_1_y.to_le_bytes();
-            debug!("Bytes {:?}", bytes);
-            let pos_update_packet: PlayerUpdate = PlayerUpdate::position_update(self.player_1_y);
-            let writer_result = self.writer_stream.write(&pos_update_packet.as_bytes());
-            match writer_result {
-                Ok(n) => {
-                    if n == 0 {
-                        break;
-                    }
+            let practice_line = self
+                .practice_line()
+                .or_else(|| self.power_up_line())
+                .or_else(|| self.series_line());
+            let server_message = self
+                .server_message
+                .as_ref()
+                .filter(|(_, until)| Instant::now() < *until)
+                .map(|(text, _)| text.as_str());
+            let status_message = if self.forfeit_armed {
+                Some("Press F again to forfeit, any other key to cancel")
+            } else {
+                self.status_message
+                    .as_deref()
+                    .or(server_message)
+                    .or(practice_line.as_deref())
+            };
+            let vertical = self.own_side().is_none_or(Side::is_vertical);
+            game_render.render_game(&self.map, self.player_id, vertical, status_message);
+
+            if shutdown::shutdown_requested() {
+                self.begin_shutdown();
+            }
+            if let Some(shutdown_deadline) = self.shutdown_deadline {
+                if Instant::now() >= shutdown_deadline {
+                    warn!("No shutdown acknowledgement from the server, leaving anyway");
+                    break 'main_loop;
+                }
+            } else if let Some(own_paddle) = self.own_paddle
+                && self.connected
+            {
+                let position = self.paddles[own_paddle].position;
+                debug!("Bytes {:?}", position.to_le_bytes());
+                let pos_update_packet: PlayerUpdate = PlayerUpdate::position_update(position);
+                let writer_result =
+                    write_frame(&mut self.writer_stream, &pos_update_packet.as_bytes());
+                if let Err(e) = writer_result {
+                    // The network thread notices too and takes care of reconnecting
+                    warn!("Failed to send position to server: {:?}", e);
+                    self.connected = false;
                 }
-                Err(_) => todo!(),
             }
             thread::sleep(delay);
         }
         debug!("Stopping game");
         drop(game_render);
+        if let Some(result_message) = &self.result_message {
+            println!("{}", result_message);
+        }
     }
 }
 
 impl Drop for Game {
     fn drop(&mut self) {
-        disable_raw_mode().unwrap();
+        shutdown::restore_terminal();
     }
 }
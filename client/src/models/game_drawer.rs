@@ -1,14 +1,24 @@
 use crossterm::{
     ExecutableCommand, QueueableCommand, cursor,
     style::{self, Stylize},
-    terminal::{self, disable_raw_mode, enable_raw_mode},
+    terminal::{self, enable_raw_mode},
 };
 use log::{debug, info};
 use std::io::{Stdout, Write, stdout};
 
+use crate::shutdown;
+
 #[repr(u8)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Tile {
+    /// Pickups, in the order the server numbers them
+    BigPaddle = 7,
+    ShrinkOpponent = 8,
+    FastBall = 9,
+    StickyPaddle = 10,
+    Shield = 11,
+    /// Solid parts of the arena, inside the map or along the end walls
+    Obstacle = 12,
     Debug = 6,
     Corner = 5,
     HorizontalWall = 4,
@@ -17,6 +27,20 @@ pub enum Tile {
     Player = 2,
     Empty = 0,
 }
+impl Tile {
+    /// The tile for a pickup the server sent, unknown ones are left out
+    pub fn pickup(power_up: u8) -> Option<Self> {
+        match power_up {
+            0 => Some(Tile::BigPaddle),
+            1 => Some(Tile::ShrinkOpponent),
+            2 => Some(Tile::FastBall),
+            3 => Some(Tile::StickyPaddle),
+            4 => Some(Tile::Shield),
+            _ => None,
+        }
+    }
+}
+
 pub struct GameRender {
     cursor_y: u16,
     cursor_x: i32,
@@ -40,7 +64,37 @@ impl GameRender {
         }
     }
 
-    pub fn render_game(&mut self, game_map: &Vec<Tile>, player_id: u8) {
+    /// `vertical` is whether our paddle moves up and down, rather than sideways
+    pub fn render_game(
+        &mut self,
+        game_map: &[Tile],
+        player_id: u8,
+        vertical: bool,
+        status: Option<&str>,
+    ) {
+        // Spectators are player 0 and only get to leave
+        let (title, hints) = match player_id {
+            0 => ("Spectating".to_string(), "Backspace quit"),
+            player_id if vertical => (
+                format!("Player {}", player_id),
+                "Up/Down move  P pause  R resume  F forfeit  Backspace quit",
+            ),
+            player_id => (
+                format!("Player {}", player_id),
+                "Left/Right move  P pause  R resume  F forfeit  Backspace quit",
+            ),
+        };
+        self.render_frame(game_map, &title, hints, status);
+    }
+
+    /// Draws the map with a title, the key hints and an optional status line underneath
+    pub fn render_frame(
+        &mut self,
+        game_map: &[Tile],
+        title: &str,
+        hints: &str,
+        status: Option<&str>,
+    ) {
         info!("Render Game");
         self.stdout
             .execute(terminal::Clear(terminal::ClearType::All))
@@ -56,6 +110,12 @@ impl GameRender {
                     Tile::VerticalWall => "|".white(),
                     Tile::HorizontalWall => "-".white(),
                     Tile::Debug => "█".dark_magenta(),
+                    Tile::BigPaddle => "+".green(),
+                    Tile::ShrinkOpponent => "-".red(),
+                    Tile::FastBall => ">".yellow(),
+                    Tile::StickyPaddle => "S".magenta(),
+                    Tile::Shield => "O".cyan(),
+                    Tile::Obstacle => "#".white(),
                     _ => " ".dark_grey(),
                 };
                 self.stdout
@@ -67,9 +127,17 @@ impl GameRender {
         self.cursor_newline();
         self.cursor_newline();
 
-        let _ = self.stdout.queue(style::PrintStyledContent(
-            format!("Player {}", player_id).as_str().cyan(),
-        ));
+        let _ = self.stdout.queue(style::PrintStyledContent(title.cyan()));
+        self.cursor_newline();
+        let _ = self
+            .stdout
+            .queue(style::PrintStyledContent(hints.dark_grey()));
+        self.cursor_newline();
+        if let Some(status) = status {
+            let _ = self
+                .stdout
+                .queue(style::PrintStyledContent(status.yellow()));
+        }
         self.cursor_newline();
         self.cursor_newline();
         self.stdout.flush().unwrap();
@@ -89,7 +157,6 @@ impl GameRender {
 impl Drop for GameRender {
     fn drop(&mut self) {
         debug!("Gamerender being dropped");
-        disable_raw_mode().unwrap();
-        // Perform cleanup here
+        shutdown::restore_terminal();
     }
 }
@@ -0,0 +1,271 @@
+use std::io::{Write, stdout};
+
+use crossterm::{
+    cursor,
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{self, enable_raw_mode},
+};
+use log::warn;
+
+use super::game::MessageType;
+
+/// Map sizes the host goes through with M, the server's usual one is the second
+const MAP_SIZES: [(u8, u8); 4] = [(60, 20), (80, 30), (100, 40), (120, 50)];
+/// Series lengths the host goes through with B, one is a single game
+const BEST_OF: [u8; 5] = [1, 3, 5, 7, 9];
+
+/// Asked for before logging in, instead of joining the public queue
+#[derive(Clone, Debug, PartialEq)]
+pub enum LobbyRequest {
+    Create,
+    Join(String),
+}
+
+impl LobbyRequest {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            LobbyRequest::Create => vec![MessageType::CreateLobby as u8],
+            LobbyRequest::Join(code) => {
+                let mut v_data: Vec<u8> = vec![MessageType::JoinLobby as u8];
+                v_data.extend_from_slice(code.as_bytes());
+                v_data
+            }
+        }
+    }
+}
+
+/// What the game will be played with, the host can change all of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LobbyRules {
+    pub map_width: u8,
+    pub map_height: u8,
+    pub paddle_size: u8,
+    pub ball_speed: u8,
+    pub best_of: u8,
+}
+
+impl LobbyRules {
+    fn as_bytes(&self) -> Vec<u8> {
+        vec![
+            MessageType::LobbyRules as u8,
+            self.map_width,
+            self.map_height,
+            self.paddle_size,
+            self.ball_speed,
+            self.best_of,
+        ]
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LobbyState {
+    pub code: String,
+    pub rules: LobbyRules,
+    /// Everyone's name and whether they are ready, the host first
+    pub members: Vec<(String, bool)>,
+    /// The server turned down the last change to the rules
+    pub rejected: bool,
+}
+
+impl LobbyState {
+    /// `[id, status, code length, code.., width, height, paddle size, ball speed, best of,
+    /// member count, (ready, name length, name..)..]`. Anything but an open lobby comes
+    /// back as the reason we aren't in one.
+    pub fn cast_packet(buf: &[u8]) -> Result<Self, String> {
+        let status = buf.get(1).copied().unwrap_or(u8::MAX);
+        match status {
+            0 | 4 => {}
+            1 => return Err("No lobby with that code".to_string()),
+            2 => return Err("That lobby is full".to_string()),
+            3 => return Err("The host left, the lobby is closed".to_string()),
+            _ => return Err("The server sent a lobby we can't read".to_string()),
+        }
+        let malformed = || "The server sent a lobby we can't read".to_string();
+
+        let code_end = 3 + *buf.get(2).ok_or_else(malformed)? as usize;
+        let code = String::from_utf8_lossy(buf.get(3..code_end).ok_or_else(malformed)?);
+        let [
+            map_width,
+            map_height,
+            paddle_size,
+            ball_speed,
+            best_of,
+            member_count,
+        ] = *buf.get(code_end..code_end + 6).ok_or_else(malformed)?
+        else {
+            return Err(malformed());
+        };
+        let mut members: Vec<(String, bool)> = Vec::new();
+        let mut rest = &buf[code_end + 6..];
+        for _ in 0..member_count {
+            let [ready, name_length, ref tail @ ..] = *rest else {
+                return Err(malformed());
+            };
+            let name = tail.get(..name_length as usize).ok_or_else(malformed)?;
+            members.push((String::from_utf8_lossy(name).to_string(), ready != 0));
+            rest = &tail[name_length as usize..];
+        }
+
+        Ok(Self {
+            code: code.to_string(),
+            rules: LobbyRules {
+                map_width,
+                map_height,
+                paddle_size,
+                ball_speed,
+                best_of,
+            },
+            members,
+            rejected: status == 4,
+        })
+    }
+}
+
+/// What a key press in the lobby comes to
+#[derive(Debug, PartialEq)]
+pub enum LobbyAction {
+    Send(Vec<u8>),
+    Quit,
+    None,
+}
+
+/// Shows the lobby until the game starts, the host gets to change the rules from here
+#[derive(Debug)]
+pub struct LobbyScreen {
+    host: bool,
+    state: Option<LobbyState>,
+    /// The last thing the server's admin said
+    message: Option<String>,
+}
+
+impl LobbyScreen {
+    pub fn new(host: bool) -> Self {
+        if let Err(e) = enable_raw_mode() {
+            warn!("Failed to take over the terminal: {:?}", e);
+        }
+        Self {
+            host,
+            state: None,
+            message: None,
+        }
+    }
+
+    pub fn update(&mut self, state: LobbyState) {
+        self.state = Some(state);
+        self.render();
+    }
+
+    pub fn show_message(&mut self, text: String) {
+        self.message = Some(text);
+        self.render();
+    }
+
+    /// The host is always first, and lobbies only have room for one more
+    fn own_ready(&self) -> bool {
+        let own_idx = if self.host { 0 } else { 1 };
+        self.state
+            .as_ref()
+            .and_then(|state| state.members.get(own_idx))
+            .is_some_and(|(_, ready)| *ready)
+    }
+
+    pub fn key_stroke(&self, event: KeyEvent) -> LobbyAction {
+        match event.code {
+            KeyCode::Backspace => return LobbyAction::Quit,
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                return LobbyAction::Quit;
+            }
+            KeyCode::Char(' ') | KeyCode::Enter => {
+                return LobbyAction::Send(vec![MessageType::Ready as u8, !self.own_ready() as u8]);
+            }
+            _ => {}
+        }
+        let Some(state) = &self.state else {
+            return LobbyAction::None;
+        };
+        if !self.host {
+            return LobbyAction::None;
+        }
+
+        // The server checks the limits, and says so if we go past them
+        let mut rules = state.rules;
+        match event.code {
+            KeyCode::Up => rules.paddle_size = rules.paddle_size.saturating_add(1),
+            KeyCode::Down => rules.paddle_size = rules.paddle_size.saturating_sub(1),
+            KeyCode::Right => rules.ball_speed = rules.ball_speed.saturating_add(1),
+            KeyCode::Left => rules.ball_speed = rules.ball_speed.saturating_sub(1),
+            KeyCode::Char('m' | 'M') => {
+                let size = (rules.map_width, rules.map_height);
+                let next = MAP_SIZES
+                    .iter()
+                    .position(|map_size| *map_size == size)
+                    .map_or(1, |idx| (idx + 1) % MAP_SIZES.len());
+                (rules.map_width, rules.map_height) = MAP_SIZES[next];
+            }
+            KeyCode::Char('b' | 'B') => {
+                let next = BEST_OF
+                    .iter()
+                    .position(|best_of| *best_of == rules.best_of)
+                    .map_or(0, |idx| (idx + 1) % BEST_OF.len());
+                rules.best_of = BEST_OF[next];
+            }
+            _ => return LobbyAction::None,
+        }
+        LobbyAction::Send(rules.as_bytes())
+    }
+
+    fn render(&self) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let rules = state.rules;
+        let mut lines = vec![
+            format!(
+                "Lobby {}, give this code to a friend to play them",
+                state.code
+            ),
+            String::new(),
+            format!(
+                "Map {}x{}  Paddle {}  Ball speed {}  Best of {}",
+                rules.map_width,
+                rules.map_height,
+                rules.paddle_size,
+                rules.ball_speed,
+                rules.best_of
+            ),
+            String::new(),
+        ];
+        for (idx, (name, ready)) in state.members.iter().enumerate() {
+            let role = if idx == 0 { "Host " } else { "Guest" };
+            let ready = if *ready { "ready" } else { "not ready" };
+            lines.push(format!("{}  {:<16} {}", role, name, ready));
+        }
+        if state.members.len() < 2 {
+            lines.push("Guest  waiting...".to_string());
+        }
+        lines.push(String::new());
+        if state.rejected {
+            lines.push("The server doesn't allow those rules".to_string());
+        }
+        if let Some(message) = &self.message {
+            lines.push(format!("Server: {}", message));
+        }
+        if self.host {
+            lines.push("Up/Down paddle  Left/Right speed  M map size  B best of".to_string());
+        }
+        lines.push("Space ready  Backspace leave".to_string());
+
+        let mut stdout = stdout();
+        let _ = execute!(
+            stdout,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 0),
+            cursor::Hide
+        );
+        for line in lines {
+            let _ = write!(stdout, "{}\r\n", line);
+        }
+        let _ = stdout.flush();
+    }
+}
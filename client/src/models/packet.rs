@@ -0,0 +1,149 @@
+use std::io::{self, ErrorKind, Read, Write};
+
+/// First byte of every packet the server sends
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServerPacket {
+    Snapshot = 0,
+    Update = 1,
+    AuthResult = 2,
+    Status = 3,
+    GameOver = 4,
+    ShutdownAck = 5,
+    GameList = 6,
+    SpectateResult = 7,
+    PracticeScore = 8,
+    PowerUps = 9,
+    Lobby = 10,
+    Tournament = 11,
+    Series = 12,
+    /// A line of text from whoever runs the server
+    Message = 13,
+    Undefined = 255,
+}
+
+/// Carried by the status packet, what the game is currently waiting on
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameStatus {
+    Running = 0,
+    WaitingForReconnect = 1,
+    Paused = 2,
+    PausedAgreed = 3,
+    Resuming = 4,
+}
+
+impl From<u8> for GameStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            1 => GameStatus::WaitingForReconnect,
+            2 => GameStatus::Paused,
+            3 => GameStatus::PausedAgreed,
+            4 => GameStatus::Resuming,
+            _ => GameStatus::Running,
+        }
+    }
+}
+
+/// Why a game ended, sent along with the winner
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOverReason {
+    Finished = 0,
+    Forfeit = 1,
+    Abandoned = 2,
+    /// The server's admin took a player out of the game
+    Kicked = 3,
+    /// The server's admin stopped the game
+    Ended = 4,
+    /// Something went wrong on the server
+    Crashed = 5,
+}
+
+impl From<u8> for GameOverReason {
+    fn from(reason: u8) -> Self {
+        match reason {
+            1 => GameOverReason::Forfeit,
+            2 => GameOverReason::Abandoned,
+            3 => GameOverReason::Kicked,
+            4 => GameOverReason::Ended,
+            5 => GameOverReason::Crashed,
+            _ => GameOverReason::Finished,
+        }
+    }
+}
+
+impl From<u8> for ServerPacket {
+    fn from(packet_id: u8) -> Self {
+        match packet_id {
+            0 => ServerPacket::Snapshot,
+            1 => ServerPacket::Update,
+            2 => ServerPacket::AuthResult,
+            3 => ServerPacket::Status,
+            4 => ServerPacket::GameOver,
+            5 => ServerPacket::ShutdownAck,
+            6 => ServerPacket::GameList,
+            7 => ServerPacket::SpectateResult,
+            8 => ServerPacket::PracticeScore,
+            9 => ServerPacket::PowerUps,
+            10 => ServerPacket::Lobby,
+            11 => ServerPacket::Tournament,
+            12 => ServerPacket::Series,
+            13 => ServerPacket::Message,
+            _ => ServerPacket::Undefined,
+        }
+    }
+}
+
+/// Every message on the wire is prefixed with its length as a little endian u16,
+/// so several packets arriving in one read can be split apart again.
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut frame: Vec<u8> = Vec::with_capacity(payload.len() + 2);
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// Returns the next complete frame, `Ok(None)` if the stream timed out before one
+    /// arrived, and `UnexpectedEof` once the server has closed the connection.
+    pub fn read_frame(&mut self, stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+        let mut buf: [u8; 1024] = [0; 1024];
+        loop {
+            if let Some(frame) = self.take_frame() {
+                return Ok(Some(frame));
+            }
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let length = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+        if self.buffer.len() < length + 2 {
+            return None;
+        }
+        let frame = self.buffer[2..length + 2].to_vec();
+        self.buffer.drain(..length + 2);
+        Some(frame)
+    }
+}
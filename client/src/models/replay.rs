@@ -0,0 +1,313 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, poll, read};
+use log::{debug, info};
+
+use super::{
+    game::Arena,
+    game_drawer::{GameRender, Tile},
+};
+use crate::shutdown;
+
+const REPLAY_MAGIC: &[u8; 4] = b"PPRP";
+const REPLAY_VERSION: u8 = 4;
+const FRAME_LENGTH: usize = 7;
+/// How far Left and Right jump through the replay
+const SEEK_STEP: Duration = Duration::from_secs(5);
+const PLAYBACK_SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+/// How long to wait on a key press while paused, so a shutdown is still noticed
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayFrame {
+    pub player_1_pos: u8,
+    pub player_2_pos: u8,
+    pub ball_x: u8,
+    pub ball_y: u8,
+    pub score_1: u8,
+    pub score_2: u8,
+    /// Player 1 is on the right for every second game of a series
+    pub ends_swapped: bool,
+}
+
+impl ReplayFrame {
+    pub fn cast_frame(buf: &[u8]) -> Self {
+        Self {
+            player_1_pos: buf[0],
+            player_2_pos: buf[1],
+            ball_x: buf[2],
+            ball_y: buf[3],
+            score_1: buf[4],
+            score_2: buf[5],
+            ends_swapped: buf[6] != 0,
+        }
+    }
+}
+
+/// A whole game saved by the server, read into memory so it can be seeked freely
+#[derive(Debug)]
+pub struct Replay {
+    pub tick_interval: Duration,
+    pub seed: u64,
+    pub map_width: i32,
+    pub map_height: i32,
+    pub paddle_size: i32,
+    pub arena: Arena,
+    pub player_names: [String; 2],
+    pub frames: Vec<ReplayFrame>,
+    /// Where each game of a series starts, just `[0]` for anything else
+    pub game_starts: Vec<usize>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::cast_buffer(&fs::read(path)?)
+    }
+
+    pub fn cast_buffer(buf: &[u8]) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+        if buf.get(0..4) != Some(REPLAY_MAGIC.as_slice()) {
+            return Err(invalid("Not a replay file"));
+        }
+        let header = buf
+            .get(4..18)
+            .ok_or(invalid("Replay header is cut short"))?;
+        if header[0] != REPLAY_VERSION {
+            return Err(invalid("Replay was saved by a different version"));
+        }
+        let tick_interval =
+            Duration::from_millis(u16::from_le_bytes([header[1], header[2]]).into());
+        let seed = u64::from_le_bytes(header[3..11].try_into().unwrap());
+        let (map_width, map_height, paddle_size) = (header[11], header[12], header[13]);
+
+        let (arena, mut rest) = Arena::cast_bytes(&buf[18..], map_height.into());
+        let mut player_names: [String; 2] = Default::default();
+        for player_name in player_names.iter_mut() {
+            let (&name_length, after_length) = rest
+                .split_first()
+                .ok_or(invalid("Replay header is cut short"))?;
+            let name = after_length
+                .get(..name_length as usize)
+                .ok_or(invalid("Replay header is cut short"))?;
+            *player_name = String::from_utf8_lossy(name).to_string();
+            rest = &after_length[name_length as usize..];
+        }
+
+        // A replay from a server that died mid game can end part way through a frame
+        let frames: Vec<ReplayFrame> = rest
+            .chunks_exact(FRAME_LENGTH)
+            .map(ReplayFrame::cast_frame)
+            .collect();
+        if frames.is_empty() {
+            return Err(invalid("Replay has no frames"));
+        }
+        // The ends only ever swap when the next game of a series starts
+        let game_starts = (0..frames.len())
+            .filter(|&idx| idx == 0 || frames[idx].ends_swapped != frames[idx - 1].ends_swapped)
+            .collect();
+
+        Ok(Self {
+            tick_interval,
+            seed,
+            map_width: map_width.into(),
+            map_height: map_height.into(),
+            paddle_size: paddle_size.into(),
+            arena,
+            player_names,
+            frames,
+            game_starts,
+        })
+    }
+
+    /// Builds the map for a single frame from scratch, seeking makes patching it up pointless
+    pub fn build_map(&self, frame: &ReplayFrame) -> Vec<Tile> {
+        let map_width = self.map_width;
+        let mut map = self.arena.build_map(map_width, self.map_height);
+        let (column_1, column_2) = if frame.ends_swapped {
+            (map_width - 3, 2)
+        } else {
+            (2, map_width - 3)
+        };
+        for (column, paddle_pos) in [
+            (column_1, frame.player_1_pos),
+            (column_2, frame.player_2_pos),
+        ] {
+            let paddle_pos: i32 = paddle_pos.into();
+            for tile_y in (paddle_pos - self.paddle_size)..=(paddle_pos + self.paddle_size) {
+                if let Some(tile) = map.get_mut((tile_y * map_width + column) as usize) {
+                    *tile = Tile::Player;
+                }
+            }
+        }
+        if let Some(tile) =
+            map.get_mut((frame.ball_y as i32 * map_width + frame.ball_x as i32) as usize)
+        {
+            *tile = Tile::Ball;
+        }
+        map
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ReplayAction {
+    Continue,
+    Quit,
+}
+
+/// Plays a replay back through the normal renderer, with pause, seek and speed controls
+pub struct ReplayViewer {
+    replay: Replay,
+    position: usize,
+    paused: bool,
+    speed_idx: usize,
+}
+
+impl ReplayViewer {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            position: 0,
+            paused: false,
+            speed_idx: PLAYBACK_SPEEDS
+                .iter()
+                .position(|&speed| speed == 1.0)
+                .unwrap(),
+        }
+    }
+
+    fn frame_interval(&self) -> Duration {
+        self.replay
+            .tick_interval
+            .div_f32(PLAYBACK_SPEEDS[self.speed_idx])
+    }
+
+    fn seek_frames(&self) -> usize {
+        (SEEK_STEP.as_millis() / self.replay.tick_interval.as_millis().max(1)) as usize
+    }
+
+    fn last_frame(&self) -> usize {
+        self.replay.frames.len() - 1
+    }
+
+    fn key_stroke(&mut self, event: KeyEvent) -> ReplayAction {
+        match event.code {
+            KeyCode::Backspace | KeyCode::Char('q' | 'Q') => return ReplayAction::Quit,
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                return ReplayAction::Quit;
+            }
+            KeyCode::Char(' ') => {
+                // Playing again from the end starts over
+                if self.paused && self.position == self.last_frame() {
+                    self.position = 0;
+                }
+                self.paused = !self.paused;
+            }
+            KeyCode::Left => self.position = self.position.saturating_sub(self.seek_frames()),
+            KeyCode::Right => {
+                self.position = (self.position + self.seek_frames()).min(self.last_frame())
+            }
+            KeyCode::Home => self.position = 0,
+            KeyCode::End => self.position = self.last_frame(),
+            KeyCode::Up | KeyCode::Char('+') => {
+                self.speed_idx = (self.speed_idx + 1).min(PLAYBACK_SPEEDS.len() - 1)
+            }
+            KeyCode::Down | KeyCode::Char('-') => self.speed_idx = self.speed_idx.saturating_sub(1),
+            _ => {}
+        }
+        ReplayAction::Continue
+    }
+
+    fn status_line(&self) -> String {
+        let frame = &self.replay.frames[self.position];
+        let elapsed = self.replay.tick_interval * self.position as u32;
+        let total = self.replay.tick_interval * self.last_frame() as u32;
+        let game = match self.replay.game_starts.len() {
+            1 => String::new(),
+            _ => {
+                let game = self
+                    .replay
+                    .game_starts
+                    .partition_point(|&start| start <= self.position);
+                format!("Game {}  ", game)
+            }
+        };
+        format!(
+            "{}{} {}:{} {}  {}/{}  {}x{}",
+            game,
+            self.replay.player_names[0],
+            frame.score_1,
+            frame.score_2,
+            self.replay.player_names[1],
+            format_time(elapsed),
+            format_time(total),
+            PLAYBACK_SPEEDS[self.speed_idx],
+            if self.paused { "  Paused" } else { "" }
+        )
+    }
+
+    pub fn play(&mut self) {
+        info!(
+            "Playing replay of {} vs {}, {} frames, seed {}",
+            self.replay.player_names[0],
+            self.replay.player_names[1],
+            self.replay.frames.len(),
+            self.replay.seed
+        );
+        let mut game_render =
+            GameRender::setup_renderer(self.replay.map_height, self.replay.map_width);
+        let mut next_frame = Instant::now();
+        let mut redraw = true;
+
+        while !shutdown::shutdown_requested() {
+            if redraw {
+                let map = self.replay.build_map(&self.replay.frames[self.position]);
+                game_render.render_frame(
+                    &map,
+                    "Replay",
+                    "Space pause  Left/Right seek  Up/Down speed  Home/End jump  Backspace quit",
+                    Some(&self.status_line()),
+                );
+                redraw = false;
+            }
+
+            let wait = if self.paused {
+                IDLE_POLL_INTERVAL
+            } else {
+                next_frame.saturating_duration_since(Instant::now())
+            };
+            if let Ok(true) = poll(wait) {
+                if let Ok(Event::Key(event)) = read() {
+                    debug!("Replay input {:?}", event);
+                    if self.key_stroke(event) == ReplayAction::Quit {
+                        break;
+                    }
+                    next_frame = Instant::now() + self.frame_interval();
+                    redraw = true;
+                }
+                continue;
+            }
+
+            if !self.paused && Instant::now() >= next_frame {
+                if self.position < self.last_frame() {
+                    self.position += 1;
+                } else {
+                    self.paused = true;
+                }
+                next_frame += self.frame_interval();
+                redraw = true;
+            }
+        }
+        debug!("Stopping replay");
+    }
+}
+
+fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
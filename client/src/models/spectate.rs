@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+use super::game::MessageType;
+
+/// One running game, as listed by the server
+#[derive(Debug)]
+pub struct GameSummary {
+    pub game_id: u32,
+    /// One per team
+    pub scores: Vec<u8>,
+    pub player_names: Vec<String>,
+}
+
+impl GameSummary {
+    /// Reads the games out of a game list packet, stopping early if it is cut short
+    pub fn cast_game_list(buf: &[u8]) -> Vec<Self> {
+        let mut summaries: Vec<Self> = Vec::new();
+        let count = buf.get(1).copied().unwrap_or(0);
+        let mut offset = 2;
+
+        for _ in 0..count {
+            let Some(header) = buf.get(offset..offset + 5) else {
+                break;
+            };
+            let game_id = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let score_count = header[4] as usize;
+            offset += 5;
+            let Some(scores) = buf.get(offset..offset + score_count) else {
+                break;
+            };
+            let scores = scores.to_vec();
+            offset += score_count;
+            let Some(&name_count) = buf.get(offset) else {
+                break;
+            };
+            offset += 1;
+
+            let mut player_names: Vec<String> = Vec::new();
+            for _ in 0..name_count {
+                let Some(&name_length) = buf.get(offset) else {
+                    return summaries;
+                };
+                let Some(name) = buf.get(offset + 1..offset + 1 + name_length as usize) else {
+                    return summaries;
+                };
+                player_names.push(String::from_utf8_lossy(name).to_string());
+                offset += 1 + name_length as usize;
+            }
+
+            summaries.push(Self {
+                game_id,
+                scores,
+                player_names,
+            });
+        }
+        summaries
+    }
+}
+
+impl Display for GameSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scores: Vec<String> = self.scores.iter().map(|score| score.to_string()).collect();
+        write!(
+            f,
+            "{:>6}  {}  {}",
+            self.game_id,
+            self.player_names.join(" vs "),
+            scores.join(":")
+        )
+    }
+}
+
+pub fn spectate_request(game_id: u32) -> Vec<u8> {
+    let mut v_data: Vec<u8> = vec![MessageType::Spectate as u8];
+    v_data.extend_from_slice(&game_id.to_le_bytes());
+    v_data
+}
@@ -0,0 +1,139 @@
+use std::fmt::Display;
+
+/// Stand in for a player in the bracket packet
+const BYE: u8 = 0xFE;
+const UNDECIDED: u8 = 0xFF;
+
+#[derive(Debug)]
+struct BracketMatch {
+    /// 0 winners' bracket, 1 losers' bracket, 2 the final
+    side: u8,
+    round: u8,
+    players: [u8; 2],
+    winner: u8,
+}
+
+/// The server's tournament, as far as it has got
+#[derive(Debug)]
+pub struct Bracket {
+    pub double_elimination: bool,
+    /// Top seed first, matches refer to players by their place in here
+    players: Vec<String>,
+    matches: Vec<BracketMatch>,
+    /// Sent to players who are waiting for their next match
+    pub waiting: bool,
+}
+
+impl Bracket {
+    /// `[id, status, format, player count, (name length, name)..,
+    /// match count, (side, round, first, second, winner)..]`. Statuses without a bracket come
+    /// back as the reason there isn't one.
+    pub fn cast_packet(buf: &[u8]) -> Result<Self, String> {
+        let status = buf.get(1).copied().unwrap_or(u8::MAX);
+        match status {
+            0 | 1 => {}
+            2 => return Err("You have no tournament match left to play".to_string()),
+            3 => return Err("There is no tournament running".to_string()),
+            _ => return Err("The server sent a bracket we can't read".to_string()),
+        }
+        let malformed = || "The server sent a bracket we can't read".to_string();
+
+        let [format, player_count] = *buf.get(2..4).ok_or_else(malformed)? else {
+            return Err(malformed());
+        };
+        let mut players: Vec<String> = Vec::new();
+        let mut rest = &buf[4..];
+        for _ in 0..player_count {
+            let [name_length, ref tail @ ..] = *rest else {
+                return Err(malformed());
+            };
+            let name = tail.get(..name_length as usize).ok_or_else(malformed)?;
+            players.push(String::from_utf8_lossy(name).to_string());
+            rest = &tail[name_length as usize..];
+        }
+        let [match_count, ref rest @ ..] = *rest else {
+            return Err(malformed());
+        };
+        let matches: Vec<BracketMatch> = rest
+            .chunks_exact(5)
+            .take(match_count as usize)
+            .map(|entry| BracketMatch {
+                side: entry[0],
+                round: entry[1],
+                players: [entry[2], entry[3]],
+                winner: entry[4],
+            })
+            .collect();
+        if matches.len() != match_count as usize {
+            return Err(malformed());
+        }
+
+        Ok(Self {
+            double_elimination: format == 1,
+            players,
+            matches,
+            waiting: status == 1,
+        })
+    }
+
+    fn name(&self, entrant: u8) -> &str {
+        match entrant {
+            BYE => "bye",
+            UNDECIDED => "?",
+            seed => self.players.get(seed as usize).map_or("?", String::as_str),
+        }
+    }
+}
+
+impl Display for Bracket {
+    /// Round by round, with the winner of every match that has been played
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = if self.double_elimination {
+            "Double"
+        } else {
+            "Single"
+        };
+        writeln!(f, "{} elimination, {} players", format, self.players.len())?;
+        let mut heading: Option<(u8, u8)> = None;
+        for bracket_match in &self.matches {
+            // Matches a bye decided without either player are left out
+            if bracket_match.players.contains(&BYE) && bracket_match.winner == BYE {
+                continue;
+            }
+            if heading != Some((bracket_match.side, bracket_match.round)) {
+                let side = match bracket_match.side {
+                    0 => "Winners",
+                    1 => "Losers",
+                    _ => "Final",
+                };
+                match (bracket_match.side, bracket_match.round) {
+                    (2, 1) => writeln!(f, "\nFinal")?,
+                    (2, _) => writeln!(f, "\nFinal rematch")?,
+                    (_, round) => writeln!(f, "\n{} round {}", side, round)?,
+                }
+                heading = Some((bracket_match.side, bracket_match.round));
+            }
+            let [first, second] = bracket_match.players;
+            let result = match bracket_match.winner {
+                UNDECIDED => String::new(),
+                winner => format!("  won by {}", self.name(winner)),
+            };
+            let line = format!(
+                "  {:<16} vs  {:<16}{}",
+                self.name(first),
+                self.name(second),
+                result
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        if let Some(champion) = self
+            .matches
+            .last()
+            .filter(|last| last.winner < BYE)
+            .map(|last| self.name(last.winner))
+        {
+            writeln!(f, "\nChampion: {}", champion)?;
+        }
+        Ok(())
+    }
+}
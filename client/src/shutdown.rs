@@ -0,0 +1,39 @@
+use std::{
+    io::stdout,
+    panic,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crossterm::{cursor, execute, terminal::disable_raw_mode};
+use log::{error, warn};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ctrl-C / SIGTERM ask for an orderly shutdown instead of killing the process, and a panic
+/// puts the terminal back the way it was before the default hook prints the message.
+pub fn install_handlers() {
+    if let Err(e) = ctrlc::set_handler(request_shutdown) {
+        warn!("Failed to install signal handler: {:?}", e);
+    }
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        error!("Client panicked: {}", info);
+        default_hook(info);
+    }));
+}
+
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Safe to call any number of times, and from a panic, so nothing in here unwraps
+pub fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(stdout(), cursor::Show);
+}
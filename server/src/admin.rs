@@ -0,0 +1,275 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{Sender, channel},
+    },
+    time::Duration,
+};
+
+use log::info;
+use thiserror::Error;
+
+use crate::{
+    gamemode::modes::ModeRegistry,
+    matchmaking::AdminRequest,
+    models::{
+        arena::Arena,
+        rules::{RulesError, RulesFile},
+    },
+    registry::GameRegistry,
+    shutdown,
+};
+
+/// The matchmaker only looks at requests between polls of its queue
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const HELP: &str = "\
+queue            players waiting for a game
+games            running games and their scores
+kick <name>      take a player out of their game or the queue
+end <game id>    stop a game, nobody wins
+say <message>    show a message to everyone
+reload           read rules.txt and the arenas again, for games that start from now on
+shutdown         stop taking players and wind down the games, same as Ctrl-C";
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Unknown command {0}, try help")]
+    Unknown(String),
+
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+
+    #[error("Nobody called {0} is playing or waiting")]
+    NoSuchPlayer(String),
+
+    #[error("No game {0}")]
+    NoSuchGame(u32),
+
+    #[error("The matchmaker didn't answer")]
+    NoAnswer,
+
+    #[error("Couldn't reload the rules: {0}")]
+    Rules(#[from] RulesError),
+
+    #[error("Couldn't reload the arenas: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Queue,
+    Games,
+    Kick(String),
+    End(u32),
+    Say(String),
+    Reload,
+    Shutdown,
+}
+
+impl Command {
+    /// Blank lines are `None`
+    pub fn parse(line: &str) -> Result<Option<Self>, CommandError> {
+        let line = line.trim();
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        let command = match name {
+            "" => return Ok(None),
+            "help" => Command::Help,
+            "queue" => Command::Queue,
+            "games" => Command::Games,
+            "kick" if argument.is_empty() => return Err(CommandError::Usage("kick <name>")),
+            "kick" => Command::Kick(argument.to_string()),
+            "end" => match argument.parse() {
+                Ok(game_id) => Command::End(game_id),
+                Err(_) => return Err(CommandError::Usage("end <game id>")),
+            },
+            "say" if argument.is_empty() => return Err(CommandError::Usage("say <message>")),
+            "say" => Command::Say(argument.to_string()),
+            "reload" => Command::Reload,
+            "shutdown" => Command::Shutdown,
+            name => return Err(CommandError::Unknown(name.to_string())),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Reads commands from whoever runs the server, one per line, and answers each one
+pub struct AdminConsole {
+    game_registry: Arc<GameRegistry>,
+    admin_tx: Sender<AdminRequest>,
+    rules_path: PathBuf,
+    arena_directory: PathBuf,
+}
+
+impl AdminConsole {
+    pub fn new(
+        game_registry: Arc<GameRegistry>,
+        admin_tx: Sender<AdminRequest>,
+        rules_path: PathBuf,
+        arena_directory: PathBuf,
+    ) -> Self {
+        Self {
+            game_registry,
+            admin_tx,
+            rules_path,
+            arena_directory,
+        }
+    }
+
+    /// Runs until the input is closed, a server started without a terminal just never
+    /// gets any commands
+    pub fn run(&self, input: impl BufRead, mut output: impl Write) {
+        for line in input.lines().map_while(Result::ok) {
+            let answer = Command::parse(&line).and_then(|command| match command {
+                Some(command) => self.execute(command),
+                None => Ok(String::new()),
+            });
+            let answer = answer.unwrap_or_else(|e| e.to_string());
+            if !answer.is_empty() {
+                let _ = writeln!(output, "{}", answer);
+                let _ = output.flush();
+            }
+        }
+        info!("Admin console closed");
+    }
+
+    pub fn execute(&self, command: Command) -> Result<String, CommandError> {
+        match command {
+            Command::Help => Ok(HELP.to_string()),
+            Command::Queue => self.queue(),
+            Command::Games => Ok(self.games()),
+            Command::Kick(player_name) => self.kick(player_name),
+            Command::End(game_id) => {
+                if !self.game_registry.end_game(game_id) {
+                    return Err(CommandError::NoSuchGame(game_id));
+                }
+                Ok(format!("Ending game {}", game_id))
+            }
+            Command::Say(text) => {
+                let games = self.game_registry.send_message(&text);
+                let waiting = self.ask(|reply_tx| AdminRequest::Message(text, reply_tx))?;
+                Ok(format!(
+                    "Sent to {} games and {} waiting players",
+                    games, waiting
+                ))
+            }
+            Command::Reload => self.reload(),
+            Command::Shutdown => {
+                shutdown::request_shutdown();
+                Ok("Shutting down".to_string())
+            }
+        }
+    }
+
+    /// Sends the matchmaker a request and waits for its answer
+    fn ask<T>(&self, request: impl FnOnce(Sender<T>) -> AdminRequest) -> Result<T, CommandError> {
+        let (reply_tx, reply_rx) = channel();
+        self.admin_tx
+            .send(request(reply_tx))
+            .map_err(|_| CommandError::NoAnswer)?;
+        reply_rx
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| CommandError::NoAnswer)
+    }
+
+    fn queue(&self) -> Result<String, CommandError> {
+        let waiting = self.ask(AdminRequest::ListWaiting)?;
+        if waiting.is_empty() {
+            return Ok("Nobody is waiting".to_string());
+        }
+        let lines: Vec<String> = waiting
+            .iter()
+            .map(|player| format!("{:<16} {}", player.player_name, player.waiting_for))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn games(&self) -> String {
+        let summaries = self.game_registry.list_games();
+        if summaries.is_empty() {
+            return "No games running".to_string();
+        }
+        let lines: Vec<String> = summaries
+            .iter()
+            .map(|summary| {
+                let scores: Vec<String> = summary
+                    .scores
+                    .iter()
+                    .map(|score| score.to_string())
+                    .collect();
+                format!(
+                    "Game {:<4} {:<40} {}",
+                    summary.game_id,
+                    summary.player_names.join(" vs "),
+                    scores.join(":")
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Someone waiting for a game can't be in one as well, so whoever has them goes first
+    fn kick(&self, player_name: String) -> Result<String, CommandError> {
+        let name = player_name.clone();
+        if self.ask(|reply_tx| AdminRequest::Kick(name, reply_tx))? {
+            return Ok(format!("Kicked {} while they were waiting", player_name));
+        }
+        match self.game_registry.kick(&player_name) {
+            Some(game_id) => Ok(format!("Kicked {} from game {}", player_name, game_id)),
+            None => Err(CommandError::NoSuchPlayer(player_name)),
+        }
+    }
+
+    /// Loads everything before handing it over, bad files leave the old rules in place
+    fn reload(&self) -> Result<String, CommandError> {
+        let rules_file = RulesFile::load(&self.rules_path)?;
+        let mut modes = ModeRegistry::with_default_modes();
+        modes.apply_rules(&rules_file)?;
+        let arenas = Arena::load_dir(&self.arena_directory)?;
+        let answer = format!("Reloaded the rules and {} arenas", arenas.len());
+        self.admin_tx
+            .send(AdminRequest::Reload(modes, arenas))
+            .map_err(|_| CommandError::NoAnswer)?;
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_with_their_arguments() {
+        assert_eq!(Command::parse("  ").unwrap(), None);
+        assert_eq!(Command::parse("games").unwrap(), Some(Command::Games));
+        assert_eq!(
+            Command::parse("kick alice").unwrap(),
+            Some(Command::Kick("alice".to_string()))
+        );
+        assert_eq!(Command::parse("end 12").unwrap(), Some(Command::End(12)));
+        assert_eq!(
+            Command::parse("say  back in five minutes ").unwrap(),
+            Some(Command::Say("back in five minutes".to_string()))
+        );
+    }
+
+    #[test]
+    fn bad_commands_say_what_is_wrong() {
+        assert!(matches!(
+            Command::parse("kick"),
+            Err(CommandError::Usage("kick <name>"))
+        ));
+        assert!(matches!(
+            Command::parse("end soon"),
+            Err(CommandError::Usage("end <game id>"))
+        ));
+        assert!(matches!(
+            Command::parse("restart"),
+            Err(CommandError::Unknown(name)) if name == "restart"
+        ));
+    }
+}
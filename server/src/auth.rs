@@ -0,0 +1,179 @@
+pub mod accounts;
+pub mod sessions;
+
+use std::{io, path::Path, sync::Mutex};
+
+use accounts::{Account, AccountStore};
+use log::info;
+use sessions::{SessionStore, SessionToken, TOKEN_LENGTH};
+use thiserror::Error;
+
+use crate::models::player::{MessageType, PlayerMessage};
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+
+    #[error("Username already registered")]
+    NameTaken,
+
+    #[error("Username must be 1-16 letters, digits, '_' or '-'")]
+    InvalidName,
+
+    #[error(
+        "Password must be at least {} characters",
+        accounts::MIN_PASSWORD_LENGTH
+    )]
+    WeakPassword,
+
+    #[error("Session token expired or unknown")]
+    SessionExpired,
+
+    #[error("Malformed authentication request")]
+    MalformedRequest,
+
+    #[error("No game mode {0}")]
+    UnknownMode(u8),
+}
+
+/// Status byte sent back to the client in the auth result packet
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuthStatus {
+    Ok = 0,
+    InvalidCredentials = 1,
+    NameTaken = 2,
+    InvalidName = 3,
+    WeakPassword = 4,
+    SessionExpired = 5,
+    MalformedRequest = 6,
+    ServerError = 7,
+    UnknownMode = 8,
+}
+
+impl From<&AuthError> for AuthStatus {
+    fn from(error: &AuthError) -> Self {
+        match error {
+            AuthError::Io(_) => AuthStatus::ServerError,
+            AuthError::InvalidCredentials => AuthStatus::InvalidCredentials,
+            AuthError::NameTaken => AuthStatus::NameTaken,
+            AuthError::InvalidName => AuthStatus::InvalidName,
+            AuthError::WeakPassword => AuthStatus::WeakPassword,
+            AuthError::SessionExpired => AuthStatus::SessionExpired,
+            AuthError::MalformedRequest => AuthStatus::MalformedRequest,
+            AuthError::UnknownMode(_) => AuthStatus::UnknownMode,
+        }
+    }
+}
+
+/// The first packet a client sends, before it is allowed into the queue
+#[derive(Debug)]
+pub enum AuthRequest {
+    Login { username: String, password: String },
+    Register { username: String, password: String },
+    Resume(SessionToken),
+}
+
+impl AuthRequest {
+    /// Login and register are laid out as `[type, name length, name.., password..]`,
+    /// resume as `[type, token..]`
+    pub fn cast_buffer(buff: &[u8]) -> Result<Self, AuthError> {
+        let message_type =
+            PlayerMessage::decode_message(*buff.first().ok_or(AuthError::MalformedRequest)?);
+
+        match message_type {
+            MessageType::Login | MessageType::Register => {
+                let name_length = *buff.get(1).ok_or(AuthError::MalformedRequest)? as usize;
+                let name_bytes = buff
+                    .get(2..2 + name_length)
+                    .ok_or(AuthError::MalformedRequest)?;
+                let username = String::from_utf8(name_bytes.to_vec())
+                    .map_err(|_| AuthError::MalformedRequest)?;
+                let password = String::from_utf8(buff[2 + name_length..].to_vec())
+                    .map_err(|_| AuthError::MalformedRequest)?;
+
+                if let MessageType::Login = message_type {
+                    Ok(Self::Login { username, password })
+                } else {
+                    Ok(Self::Register { username, password })
+                }
+            }
+            MessageType::Resume => {
+                let token = buff
+                    .get(1..1 + TOKEN_LENGTH)
+                    .ok_or(AuthError::MalformedRequest)?;
+                Ok(Self::Resume(token.try_into().unwrap()))
+            }
+            _ => Err(AuthError::MalformedRequest),
+        }
+    }
+}
+
+/// Shared between every connection thread, guards the account file and live sessions
+pub struct Authenticator {
+    accounts: Mutex<AccountStore>,
+    sessions: Mutex<SessionStore>,
+}
+
+impl Authenticator {
+    pub fn load(accounts_path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let accounts = AccountStore::load(accounts_path)?;
+        info!("Loaded {} registered accounts", accounts.len());
+        Ok(Self {
+            accounts: Mutex::new(accounts),
+            sessions: Mutex::new(SessionStore::new()),
+        })
+    }
+
+    pub fn register(&self, username: &str, password: &str) -> Result<SessionToken, AuthError> {
+        // The store is only locked around the hashing, so logins aren't held up behind it
+        self.accounts
+            .lock()
+            .unwrap()
+            .check_new(username, password)?;
+        let account = Account::new(password);
+        self.accounts.lock().unwrap().insert(username, account)?;
+        info!("Registered new account {}", username);
+        Ok(self.sessions.lock().unwrap().create(username))
+    }
+
+    pub fn login(&self, username: &str, password: &str) -> Result<SessionToken, AuthError> {
+        let account = self.accounts.lock().unwrap().get(username);
+        account
+            .ok_or(AuthError::InvalidCredentials)?
+            .verify(password)?;
+        Ok(self.sessions.lock().unwrap().create(username))
+    }
+
+    pub fn is_registered(&self, username: &str) -> bool {
+        self.accounts.lock().unwrap().contains(username)
+    }
+
+    /// Resumes a session issued by an earlier login, returning the account it belongs to
+    pub fn resume(&self, token: &SessionToken) -> Result<String, AuthError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .resume(token)
+            .ok_or(AuthError::SessionExpired)
+    }
+
+    /// Carries out a request, returning the account name and a token for the session
+    pub fn authenticate(&self, request: AuthRequest) -> Result<(String, SessionToken), AuthError> {
+        match request {
+            AuthRequest::Login { username, password } => {
+                let token = self.login(&username, &password)?;
+                Ok((username, token))
+            }
+            AuthRequest::Register { username, password } => {
+                let token = self.register(&username, &password)?;
+                Ok((username, token))
+            }
+            AuthRequest::Resume(token) => Ok((self.resume(&token)?, token)),
+        }
+    }
+}
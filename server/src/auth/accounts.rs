@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use rand::Rng;
+use sha2::Sha256;
+
+use super::AuthError;
+
+pub const MIN_PASSWORD_LENGTH: usize = 6;
+pub const MAX_USERNAME_LENGTH: usize = 16;
+const HASH_ROUNDS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Account {
+    salt: [u8; SALT_LENGTH],
+    hash: [u8; HASH_LENGTH],
+}
+
+/// Hashing is slow on purpose, so none of this should run with the store locked
+impl Account {
+    /// A fresh salt, and the password hashed with it
+    pub fn new(password: &str) -> Self {
+        let mut salt = [0; SALT_LENGTH];
+        rand::rng().fill(&mut salt);
+        Self {
+            salt,
+            hash: hash_password(password, &salt),
+        }
+    }
+
+    pub fn verify(&self, password: &str) -> Result<(), AuthError> {
+        let hash = hash_password(password, &self.salt);
+
+        // Compare every byte so the time taken doesn't leak how much matched
+        let difference = hash
+            .iter()
+            .zip(self.hash.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if difference == 0 {
+            Ok(())
+        } else {
+            Err(AuthError::InvalidCredentials)
+        }
+    }
+}
+
+/// Registered accounts, persisted one per line as `username salt hash` in hex
+#[derive(Debug)]
+pub struct AccountStore {
+    path: PathBuf,
+    accounts: HashMap<String, Account>,
+}
+
+impl AccountStore {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut accounts = HashMap::new();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match Self::parse_line(&line) {
+                        Some((username, account)) => {
+                            accounts.insert(username, account);
+                        }
+                        None => warn!("Skipping malformed account entry: {:?}", line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(Self { path, accounts })
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.accounts.contains_key(username)
+    }
+
+    pub fn get(&self, username: &str) -> Option<Account> {
+        self.accounts.get(username).copied()
+    }
+
+    /// Whether an account could be made with these, before going to the trouble of hashing
+    pub fn check_new(&self, username: &str, password: &str) -> Result<(), AuthError> {
+        if !valid_username(username) {
+            return Err(AuthError::InvalidName);
+        }
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(AuthError::WeakPassword);
+        }
+        if self.accounts.contains_key(username) {
+            return Err(AuthError::NameTaken);
+        }
+        Ok(())
+    }
+
+    /// Saves a new account. The name is checked again, someone else may have taken it
+    /// while the password was being hashed.
+    pub fn insert(&mut self, username: &str, account: Account) -> Result<(), AuthError> {
+        if self.accounts.contains_key(username) {
+            return Err(AuthError::NameTaken);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(
+            file,
+            "{} {} {}",
+            username,
+            to_hex(&account.salt),
+            to_hex(&account.hash)
+        )?;
+
+        self.accounts.insert(username.to_string(), account);
+        Ok(())
+    }
+
+    fn parse_line(line: &str) -> Option<(String, Account)> {
+        let mut parts = line.split_whitespace();
+        let username = parts.next()?.to_string();
+        let salt = from_hex(parts.next()?)?.try_into().ok()?;
+        let hash = from_hex(parts.next()?)?.try_into().ok()?;
+        Some((username, Account { salt, hash }))
+    }
+}
+
+pub fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LENGTH
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn hash_password(password: &str, salt: &[u8]) -> [u8; HASH_LENGTH] {
+    let mut hash = [0; HASH_LENGTH];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, HASH_ROUNDS, &mut hash);
+    hash
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
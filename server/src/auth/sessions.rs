@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+pub const TOKEN_LENGTH: usize = 16;
+const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 30);
+
+pub type SessionToken = [u8; TOKEN_LENGTH];
+
+#[derive(Debug)]
+struct Session {
+    username: String,
+    expires_at: Instant,
+}
+
+/// Tokens handed out on login, so a client can come back without resending its password
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    sessions: HashMap<SessionToken, Session>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn create(&mut self, username: &str) -> SessionToken {
+        self.remove_expired();
+        let mut token = [0; TOKEN_LENGTH];
+        rand::rng().fill(&mut token);
+        self.sessions.insert(
+            token,
+            Session {
+                username: username.to_string(),
+                expires_at: Instant::now() + SESSION_LIFETIME,
+            },
+        );
+        token
+    }
+
+    /// Looks up the session and pushes its expiry back, since the token is still in use
+    pub fn resume(&mut self, token: &SessionToken) -> Option<String> {
+        self.remove_expired();
+        let session = self.sessions.get_mut(token)?;
+        session.expires_at = Instant::now() + SESSION_LIFETIME;
+        Some(session.username.clone())
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| session.expires_at > now);
+    }
+}
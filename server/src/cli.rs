@@ -0,0 +1,51 @@
+use std::{path::PathBuf, time::Duration};
+
+const USAGE: &str = "Usage: server [--bind <address>] [--data-dir <directory>] \
+                     [--grace-period <seconds>] [--metrics <address>]";
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:9090";
+
+#[derive(Debug)]
+pub struct ServerArgs {
+    /// Port 0 lets the OS pick one, the address actually bound is printed on startup
+    pub bind_address: String,
+    /// Holds the accounts file, the rules, the saved replays and any arenas
+    pub data_dir: PathBuf,
+    /// How long running games get to finish once the server is asked to shut down
+    pub grace_period: Duration,
+    /// Where Prometheus can scrape `/metrics` from, nothing is served without it. Keep it
+    /// on a local address, anyone who can reach it sees the numbers.
+    pub metrics_address: Option<String>,
+}
+
+impl ServerArgs {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut server_args = Self {
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            data_dir: PathBuf::from("."),
+            grace_period: Duration::ZERO,
+            metrics_address: None,
+        };
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(format!("Missing value for {}\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--bind" => server_args.bind_address = value()?,
+                "--data-dir" => server_args.data_dir = PathBuf::from(value()?),
+                "--grace-period" => {
+                    let seconds = value()?
+                        .parse()
+                        .map_err(|_| format!("--grace-period takes seconds\n{}", USAGE))?;
+                    server_args.grace_period = Duration::from_secs(seconds);
+                }
+                "--metrics" => server_args.metrics_address = Some(value()?),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                arg => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+            }
+        }
+        Ok(server_args)
+    }
+}
@@ -1,2 +1,13 @@
+pub mod doubles;
+pub mod free_for_all;
+#[allow(clippy::module_inception)]
 pub mod gamemode;
+#[cfg(test)]
+pub mod harness;
+pub mod modes;
+pub mod multi_ball;
+pub mod power_ups;
+pub mod practice;
+pub mod runner;
+pub mod series;
 pub mod standard;
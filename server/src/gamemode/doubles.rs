@@ -0,0 +1,109 @@
+use log::info;
+
+use crate::{
+    gamemode::{
+        gamemode::{GameState, Gamemode, Gamemodes, Seat, Side},
+        modes::GameContext,
+    },
+    models::{player::NewPlayer, rules::MatchRules},
+    registry::GameSession,
+};
+
+/// Two against two on an empty map. Each side has a keeper in front of the goal and a
+/// forward further up, the standard rules cover the rest.
+#[derive(Debug)]
+pub struct DoublesGame;
+
+impl DoublesGame {
+    pub fn launch(players: Vec<NewPlayer>, rules: &MatchRules, context: &GameContext) {
+        info!("Starting doubles game");
+        let player_refs: Vec<&NewPlayer> = players.iter().collect();
+        let session = context.game_registry.create_session(&player_refs);
+        let gamestate = Self::setup_game(players, rules, session, rand::random());
+        context.run_game(Box::new(DoublesGame), gamestate);
+    }
+
+    /// Players 1 and 3 play on the left, 2 and 4 on the right, the first of each pair
+    /// keeps goal
+    pub fn setup_game(
+        players: Vec<NewPlayer>,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> GameState {
+        let map_width = rules.map_width;
+        // The forwards stand a quarter of the way up the map
+        let forward_column = map_width / 4;
+        let seats = [
+            (1, Side::Left, 2),
+            (2, Side::Right, map_width - 3),
+            (1, Side::Left, forward_column),
+            (2, Side::Right, map_width - forward_column - 1),
+        ]
+        .map(|(team, side, line)| Seat { team, side, line });
+        GameState::seated(
+            players.into_iter().zip(seats).collect(),
+            rules,
+            session,
+            seed,
+        )
+    }
+}
+
+impl Gamemode for DoublesGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::Doubles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::gamemode::harness::{Harness, assert_invariants, random_inputs};
+
+    #[test]
+    fn keeps_the_ball_in_play_and_scores_by_team() {
+        for seed in 0..10 {
+            let mut harness = Harness::doubles(seed);
+            let mut input_rng = StdRng::seed_from_u64(seed);
+            for tick in 0..5_000 {
+                random_inputs(&mut harness, &mut input_rng);
+                let before = harness.frame();
+                assert_eq!(harness.tick(), None, "seed {}: game ended by itself", seed);
+                // The frame holds the first two players' paddles and both team scores
+                assert_invariants(&before, &harness.frame(), harness.gamestate(), tick);
+                for player_idx in 1..=4 {
+                    harness.received(player_idx);
+                }
+            }
+            assert_eq!(harness.gamestate().scores.len(), 2);
+        }
+    }
+
+    #[test]
+    fn players_on_the_right_see_themselves_on_the_left() {
+        let harness = Harness::doubles(1);
+        let render = |player_idx| {
+            harness
+                .runner
+                .mode
+                .render_snapshot(harness.gamestate(), player_idx)
+        };
+        let snapshot = render(4);
+        assert_eq!(snapshot[1], 4);
+        assert_eq!(snapshot[18], 4);
+        let paddles: Vec<&[u8]> = snapshot[19..].chunks_exact(5).collect();
+        // Player 4 is the right hand forward, flipped onto the left
+        assert_eq!(paddles[3][..4], [4, 2, Side::Left as u8, 20]);
+        // with their keeper behind them and the other team's keeper on the far right
+        assert_eq!(paddles[1][..4], [2, 2, Side::Left as u8, 2]);
+        assert_eq!(paddles[0][..4], [1, 1, Side::Right as u8, 77]);
+
+        // The left team sees the map as it is
+        let snapshot = render(3);
+        let paddles: Vec<&[u8]> = snapshot[19..].chunks_exact(5).collect();
+        assert_eq!(paddles[2][..4], [3, 1, Side::Left as u8, 20]);
+    }
+}
@@ -0,0 +1,194 @@
+use log::info;
+use rand::{Rng, seq::IndexedRandom};
+
+use crate::{
+    gamemode::{
+        gamemode::{Ball, GameResult, GameState, Gamemode, Gamemodes, Seat, Side},
+        modes::GameContext,
+    },
+    models::{packet::GameOverReason, player::NewPlayer, rules::MatchRules},
+    registry::GameSession,
+};
+
+/// Four players, one guarding each wall. Whoever touched the ball last scores when it
+/// goes into someone else's goal, an own goal or an untouched ball scores nothing.
+#[derive(Debug, Default)]
+pub struct FreeForAllGame {
+    /// The team of the last paddle the ball came off
+    last_hit: Option<u8>,
+}
+
+impl FreeForAllGame {
+    pub fn launch(players: Vec<NewPlayer>, rules: &MatchRules, context: &GameContext) {
+        info!("Starting free for all game");
+        let player_refs: Vec<&NewPlayer> = players.iter().collect();
+        let session = context.game_registry.create_session(&player_refs);
+        let gamestate = Self::setup_game(players, rules, session, rand::random());
+        context.run_game(Box::new(FreeForAllGame::default()), gamestate);
+    }
+
+    /// A squarer map than the standard one, so the top and bottom aren't too long
+    pub fn default_rules() -> MatchRules {
+        MatchRules::sized(60, 30)
+    }
+
+    /// Left, right, top and bottom in that order, everyone on a team of their own
+    pub fn setup_game(
+        players: Vec<NewPlayer>,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> GameState {
+        let seats = [
+            (Side::Left, 2),
+            (Side::Right, rules.map_width - 3),
+            (Side::Top, 2),
+            (Side::Bottom, rules.map_height - 3),
+        ];
+        let seats = (1..)
+            .zip(seats)
+            .map(|(team, (side, line))| Seat { team, side, line });
+        GameState::seated(
+            players.into_iter().zip(seats).collect(),
+            rules,
+            session,
+            seed,
+        )
+    }
+
+    /// The squares where two paddles' walls meet are solid, so the ball can't sneak
+    /// between two goals
+    fn in_corner(gamestate: &GameState, x: u8, y: u8) -> bool {
+        (x <= 2 || x >= gamestate.map_width - 3) && (y <= 2 || y >= gamestate.map_height - 3)
+    }
+}
+
+impl Gamemode for FreeForAllGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::FreeForAll
+    }
+
+    /// Every wall is a goal, the ball is in one once it is past the paddle in front of it
+    fn goal_scored(&self, gamestate: &GameState, ball: &Ball) -> Option<Side> {
+        match (ball.x, ball.y) {
+            (x, _) if x <= 1 => Some(Side::Left),
+            (x, _) if x >= gamestate.map_width - 2 => Some(Side::Right),
+            (_, y) if y <= 1 => Some(Side::Top),
+            (_, y) if y >= gamestate.map_height - 2 => Some(Side::Bottom),
+            _ => None,
+        }
+    }
+
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, goal: Side) {
+        let conceded = gamestate.defending_team(goal);
+        if let Some(scorer) = self.last_hit.take().filter(|scorer| *scorer != conceded) {
+            info!("Player {} scored on player {}", scorer, conceded);
+            gamestate.increment_score(scorer);
+            gamestate.session.report_scores(&gamestate.scores);
+        }
+        let mut ball = gamestate.center_ball();
+        // Half the serves go to the top and bottom instead
+        if gamestate.rng.random_bool(0.5) {
+            (ball.dx, ball.dy) = (ball.dy, ball.dx);
+        }
+        gamestate.balls[ball_idx] = ball;
+    }
+
+    /// No walls to bounce off apart from the corners, paddles send the ball back the way
+    /// it came with a random change to the other direction
+    fn step_ball(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        let mut ball = gamestate.balls[ball_idx];
+        let (mut new_x, mut new_y) = ball.next_pos();
+
+        let mut collision_detected = false;
+        if Self::in_corner(gamestate, new_x, new_y) {
+            ball.dx = -ball.dx;
+            ball.dy = -ball.dy;
+            collision_detected = true;
+        } else if let Some(player_idx) = gamestate.paddle_hit(&ball, new_x, new_y) {
+            let seat = gamestate.player(player_idx).seat;
+            let sideways = *[-1, 0, 1].choose(&mut gamestate.rng).unwrap();
+            if seat.side.is_vertical() {
+                ball.dx = -ball.dx;
+                ball.dy = sideways;
+            } else {
+                ball.dy = -ball.dy;
+                ball.dx = sideways;
+            }
+            self.last_hit = Some(seat.team);
+            collision_detected = true;
+        }
+        if collision_detected {
+            (new_x, new_y) = ball.next_pos();
+        }
+
+        ball.x = new_x;
+        ball.y = new_y;
+        gamestate.balls[ball_idx] = ball;
+    }
+
+    /// There is no score limit, so whoever is ahead when the game stops wins, a tie
+    /// for the lead means nobody does
+    fn on_end(
+        &mut self,
+        gamestate: &mut GameState,
+        _winner: u8,
+        reason: GameOverReason,
+    ) -> GameResult {
+        let best = gamestate.scores.iter().max().copied().unwrap_or(0);
+        let leaders: Vec<usize> = (0..gamestate.scores.len())
+            .filter(|team| gamestate.scores[*team] == best)
+            .collect();
+        let winner = match leaders.as_slice() {
+            [team] if best > 0 => *team as u8 + 1,
+            _ => 0,
+        };
+        GameResult {
+            winner,
+            scores: gamestate.scores.clone(),
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gamemode::harness::Harness, models::player::MessageType};
+
+    #[test]
+    fn goes_to_whoever_hit_it_last() {
+        let mut harness = Harness::free_for_all(5);
+        for tick in 0..5_000 {
+            let ball = harness.gamestate().balls[0];
+            // Everyone follows the ball apart from the bottom player, who never moves
+            harness.move_paddle(1, ball.y);
+            harness.move_paddle(2, ball.y);
+            harness.move_paddle(3, ball.x);
+            assert_eq!(harness.tick(), None);
+
+            let gamestate = harness.gamestate();
+            let ball = gamestate.balls[0];
+            assert!(
+                (1..gamestate.map_width - 1).contains(&ball.x)
+                    && (1..gamestate.map_height - 1).contains(&ball.y),
+                "tick {}: ball left the map at {:?}",
+                tick,
+                (ball.x, ball.y)
+            );
+            for player_idx in 1..=4 {
+                harness.received(player_idx);
+            }
+        }
+        let scores = &harness.gamestate().scores;
+        assert_eq!(scores.len(), 4);
+        assert_eq!(scores[3], 0, "the bottom player never touched the ball");
+        assert!(scores[..3].iter().sum::<u8>() > 0);
+
+        harness.send(4, &[MessageType::Forfeit as u8, 0]);
+        assert!(harness.tick().is_some());
+        let game_over = harness.game_over(1);
+        // Scores 3 and 4 come after the reason
+        assert_eq!(game_over.len(), 7);
+        assert_eq!(game_over[6], 0);
+    }
+}
@@ -1,138 +1,740 @@
-use std::time::Duration;
+use std::{fmt::Debug, ops::RangeInclusive, time::Duration};
 
-use log::{debug, info, warn};
-use rand::{Rng, seq::IndexedRandom};
+use log::info;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
 
-use crate::models::player::{NewPlayer, Player, PlayerError};
+use crate::{
+    models::{
+        arena::{Arena, Obstacle},
+        packet::{GameOverReason, ServerPacket},
+        player::{NewPlayer, Player, PlayerCommand},
+        replay::ReplayRecorder,
+        rules::MatchRules,
+        spectator::Spectator,
+    },
+    registry::GameSession,
+};
+
+/// Time between two ticks of the game loop, replays are played back at the same rate
+pub const TICK_INTERVAL: Duration = Duration::from_millis(35);
+
+/// Which wall a paddle guards, the goal is right behind it
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Side {
+    #[default]
+    Left = 0,
+    Right = 1,
+    Top = 2,
+    Bottom = 3,
+}
+
+impl Side {
+    /// Left and right paddles move up and down, top and bottom ones move sideways
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Side::Left | Side::Right)
+    }
+
+    /// The same side on a map flipped left to right
+    pub fn mirrored(self) -> Self {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+            side => side,
+        }
+    }
+
+    /// Whether a ball moving this way is heading for the goal behind this side
+    pub fn facing(self, dx: i8, dy: i8) -> bool {
+        match self {
+            Side::Left => dx < 0,
+            Side::Right => dx > 0,
+            Side::Top => dy < 0,
+            Side::Bottom => dy > 0,
+        }
+    }
+}
+
+/// Where a player's paddle is on the map and who they play for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Seat {
+    /// Numbered from 1, teammates share a score
+    pub team: u8,
+    pub side: Side,
+    /// The column of a vertical paddle, or the row of a horizontal one
+    pub line: u8,
+}
+
+/// A ball and the way it is heading, one step a move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ball {
+    pub x: u8,
+    pub y: u8,
+    pub dx: i8,
+    pub dy: i8,
+}
+
+impl Ball {
+    /// Where the ball ends up if nothing is in its way
+    pub fn next_pos(&self) -> (u8, u8) {
+        (
+            (self.x as i16 + self.dx as i16) as u8,
+            (self.y as i16 + self.dy as i16) as u8,
+        )
+    }
+}
 
 #[derive(Debug)]
 pub struct GameState {
-    pub player_1: Player,
-    pub player_2: Player,
-    pub ball_pos_x: u8,
-    pub ball_pos_y: u8,
-    pub ball_dy: i8,
-    pub ball_dx: i8,
+    /// Player ids are their place in here plus one
+    pub players: Vec<Player>,
+    /// Never empty, modes with a single ball only ever use the first
+    pub balls: Vec<Ball>,
     pub map_width: u8,
     pub map_height: u8,
+    /// The size paddles start at, a player's own can change during the game
     pub paddle_size: u8,
-    pub player_1_score: u8,
-    pub player_2_score: u8,
+    /// Rows in the middle of each end wall that let the ball through, the rest is solid
+    pub goal_width: u8,
+    pub obstacles: Vec<Obstacle>,
+    /// One per team, team 1 first
+    pub scores: Vec<u8>,
+    pub session: GameSession,
+    pub spectators: Vec<Spectator>,
+    pub replay: Option<ReplayRecorder>,
+    /// Every random choice in the game comes from here, the same seed and inputs replay the same game
+    pub seed: u64,
+    pub rng: StdRng,
+    /// Cells the ball moves every two ticks
+    pub ball_speed: u8,
+    /// Set when the next tick is the second of a pair, which takes the odd step
+    pub stepping: bool,
+    pub reconnect_timeout: Duration,
+    /// Set while the players are at the other end from where they started. Nobody's view
+    /// flips along with them, so they see the swap happen.
+    pub ends_swapped: bool,
 }
 
 impl GameState {
-    pub fn reset_ball_to_center(&mut self) {
-        let mut rng = rand::rng();
-        self.ball_pos_x = self.map_width / 2;
-        self.ball_pos_y = self.map_height / 2;
-        self.ball_dx = if rng.random_bool(0.5) { 1 } else { -1 };
-        self.ball_dy = *[-1, 0, 1].choose(&mut rng).unwrap()
+    /// An empty map, with both paddles and the ball in the middle
+    pub fn new(
+        player_1: NewPlayer,
+        player_2: NewPlayer,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> Self {
+        let arena = Arena::empty(rules.map_width, rules.map_height);
+        Self::in_arena(player_1, player_2, &arena, rules, session, seed)
     }
 
-    pub fn increment_score(&mut self, player_idx: u8) {
-        if player_idx == 1 {
-            self.player_1_score = self.player_1_score.wrapping_add(1);
-        } else if player_idx == 2 {
-            self.player_2_score = self.player_2_score.wrapping_add(1);
+    /// Two players on either end of an arena, which brings its own size
+    pub fn in_arena(
+        player_1: NewPlayer,
+        player_2: NewPlayer,
+        arena: &Arena,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> Self {
+        let seats = [
+            Seat {
+                team: 1,
+                side: Side::Left,
+                line: 2,
+            },
+            Seat {
+                team: 2,
+                side: Side::Right,
+                line: arena.width - 3,
+            },
+        ];
+        // Arenas can be smaller than the map the rules were checked against
+        let rules = MatchRules {
+            map_width: arena.width,
+            map_height: arena.height,
+            paddle_size: rules.paddle_size.min(arena.height / 4),
+            ..*rules
+        };
+        let mut gamestate = Self::seated(
+            [player_1, player_2].into_iter().zip(seats).collect(),
+            &rules,
+            session,
+            seed,
+        );
+        gamestate.goal_width = arena.goal_width;
+        gamestate.obstacles = arena.obstacles.clone();
+        gamestate
+    }
+
+    /// Any number of players sat where the mode wants them, paddles start in the middle
+    /// of their wall
+    pub fn seated(
+        players: Vec<(NewPlayer, Seat)>,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> Self {
+        let (map_width, map_height) = (rules.map_width, rules.map_height);
+        let players: Vec<Player> = players
+            .into_iter()
+            .map(|(new_player, seat)| {
+                let mut player = Player::from_new_player(new_player);
+                player.seat = seat;
+                player.paddle_size = rules.paddle_size;
+                player.player_pos = if seat.side.is_vertical() {
+                    map_height / 2
+                } else {
+                    map_width / 2
+                };
+                player
+            })
+            .collect();
+        let teams = players
+            .iter()
+            .map(|player| player.seat.team)
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            players,
+            balls: vec![Ball {
+                x: map_width / 2,
+                y: map_height / 2,
+                dx: 1,
+                dy: 0,
+            }],
+            map_width,
+            map_height,
+            paddle_size: rules.paddle_size,
+            goal_width: map_height - 2,
+            obstacles: Vec::new(),
+            scores: vec![0; teams as usize],
+            session,
+            spectators: Vec::new(),
+            replay: None,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            ball_speed: rules.ball_speed,
+            stepping: false,
+            reconnect_timeout: rules.reconnect_timeout,
+            ends_swapped: false,
         }
     }
-}
-#[derive(Debug)]
-pub enum Gamemodes {
-    Standard,
-}
 
-pub trait Gamemode {
-    fn setup_game(player_1: NewPlayer, player_2: NewPlayer) -> (Self, GameState)
-    where
-        Self: Sized;
-
-    fn start_game(&mut self, gamestate: &mut GameState) -> i32;
-    fn step_ball(&mut self, gamestate: &mut GameState);
-
-    fn create_snapshot_packet(&self, gamestate: &mut GameState) -> Vec<u8> {
-        let mut v_data: Vec<u8> = Vec::new();
-        v_data.push(0);
-        v_data.push(1);
-        v_data.push(gamestate.player_1.player_pos);
-        v_data.push(gamestate.player_2.player_pos);
-        v_data.push(gamestate.ball_pos_x);
-        v_data.push(gamestate.ball_pos_y);
-        v_data.push(gamestate.map_width);
-        v_data.push(gamestate.map_height);
-        v_data.push(gamestate.paddle_size);
-        v_data
+    /// A ball in the middle of the map heading for a random side
+    pub fn center_ball(&mut self) -> Ball {
+        Ball {
+            x: self.map_width / 2,
+            y: self.map_height / 2,
+            dx: if self.rng.random_bool(0.5) { 1 } else { -1 },
+            dy: *[-1, 0, 1].choose(&mut self.rng).unwrap(),
+        }
     }
 
-    fn create_update_packet(&self, gamestate: &mut GameState) -> Vec<u8> {
-        let mut v_data: Vec<u8> = Vec::new();
-        v_data.push(1);
-        v_data.push(0);
-        v_data.push(gamestate.player_1.player_pos);
-        v_data.push(gamestate.player_2.player_pos);
-        v_data.push(gamestate.ball_pos_x);
-        v_data.push(gamestate.ball_pos_y);
-        v_data
+    pub fn reset_ball_to_center(&mut self, ball_idx: usize) {
+        self.balls[ball_idx] = self.center_ball();
     }
 
-    fn player_quit(&mut self, gamestate: &mut GameState) {
-        todo!()
+    /// Clients can send any position, paddles are kept clear of the walls
+    pub fn clamp_paddles(&mut self) {
+        for player in self.players.iter_mut() {
+            let length = if player.seat.side.is_vertical() {
+                self.map_height
+            } else {
+                self.map_width
+            };
+            player.player_pos = player
+                .player_pos
+                .clamp(player.paddle_size + 1, length - player.paddle_size - 2);
+        }
     }
 
-    fn update_player_location(&mut self, gamestate: &mut GameState) -> Result<(), PlayerError> {
-        let mut buff: [u8; 1024] = [0; 1024];
-        let player_1_result = gamestate.player_1.updated_position(&mut buff);
-        let player_2_result = gamestate.player_2.updated_position(&mut buff);
+    pub fn increment_score(&mut self, team: u8) {
+        if let Some(score) = self.scores.get_mut((team as usize).wrapping_sub(1)) {
+            *score = score.wrapping_add(1);
+        }
+    }
 
-        match (player_1_result, player_2_result) {
-            (Ok(_), Ok(_)) => Ok(()),
-            (Ok(_), Err(e)) => Err(e),
-            (Err(e), Ok(_)) => Err(e),
-            (Err(e), Err(e2)) => Err(e),
+    /// The side whose goal the ball just got into, goals are at column 1 and the right
+    /// hand paddle's column
+    pub fn goal_scored(&self, ball: &Ball) -> Option<Side> {
+        if !self.in_goal(ball.y) {
+            return None;
+        }
+        match ball.x {
+            1 => Some(Side::Left),
+            x if x == self.map_width - 3 => Some(Side::Right),
+            _ => None,
         }
     }
 
-    fn reset_ball_pos(&mut self, gamestate: &mut GameState) {
-        gamestate.ball_pos_x = gamestate.map_width / 2;
-        gamestate.ball_pos_y = gamestate.map_height / 2;
+    /// Whether a row is inside the goals, they are centred on the end walls
+    pub fn in_goal(&self, y: u8) -> bool {
+        let start = (self.map_height - self.goal_width) / 2;
+        (start..start + self.goal_width).contains(&y)
     }
 
-    fn new_ball_pos(&mut self, gamestate: &mut GameState) -> (u8, u8) {
-        (
-            (gamestate.ball_pos_x as i16 + gamestate.ball_dx as i16) as u8,
-            (gamestate.ball_pos_y as i16 + gamestate.ball_dy as i16) as u8,
-        )
+    pub fn is_obstacle(&self, x: u8, y: u8) -> bool {
+        self.obstacles
+            .iter()
+            .any(|obstacle| obstacle.contains(x, y))
+    }
+
+    /// The player whose paddle covers this cell. Paddles only stop a ball heading for
+    /// their own goal, teammates' shots go straight through.
+    pub fn paddle_hit(&self, ball: &Ball, x: u8, y: u8) -> Option<u8> {
+        self.players
+            .iter()
+            .position(|player| {
+                let seat = player.seat;
+                let (across, along) = if seat.side.is_vertical() {
+                    (x, y)
+                } else {
+                    (y, x)
+                };
+                seat.side.facing(ball.dx, ball.dy)
+                    && across == seat.line
+                    && along.abs_diff(player.player_pos) <= player.paddle_size
+            })
+            .map(|idx| idx as u8 + 1)
     }
 
-    fn calculate_next_frame(&mut self, gamestate: &mut GameState) {
-        match gamestate.ball_pos_x {
-            x if x == 1 => {
-                gamestate.player_2_score = gamestate.player_2_score.wrapping_add(1);
-                gamestate.reset_ball_to_center();
+    /// Moves a ball a single step, bouncing it off the walls and paddles the standard way.
+    /// Returns the player whose paddle it came off, if it hit one.
+    pub fn move_ball(&mut self, ball_idx: usize) -> Option<u8> {
+        let mut ball = self.balls[ball_idx];
+        let (mut new_x, mut new_y) = ball.next_pos();
+
+        let paddle_column = self
+            .players
+            .iter()
+            .any(|player| player.seat.side.is_vertical() && player.seat.line == new_x);
+        let paddle_hit = self.paddle_hit(&ball, new_x, new_y);
+
+        // Check wall + paddle colision
+        // Check wall collision
+        // Check paddle collision
+        let mut collison_detected: bool = false;
+        match (new_x, new_y) {
+            // paddle +wall collision
+            (_, y) if (y == 0 || y == self.map_height - 1) && paddle_column => {
+                let random_change = self.rng.random_range(-1..0);
+                ball.dx = -ball.dx;
+                ball.dy *= random_change;
+                collison_detected = true;
+            }
+
+            _ if let Some(player_idx) = paddle_hit => {
+                info!("Ball hit player {} paddle", player_idx);
+                ball.dy = match ball.dx {
+                    -1 => *[0, 1].choose(&mut self.rng).unwrap(),
+                    0 => *[-1, 0, 1].choose(&mut self.rng).unwrap(),
+                    1 => *[-1, 0].choose(&mut self.rng).unwrap(),
+                    _ => 0,
+                };
+                ball.dx = -ball.dx;
+                collison_detected = true;
             }
 
-            x if x == gamestate.map_width - 3 => {
-                gamestate.player_1_score = gamestate.player_1_score.wrapping_add(1);
-                gamestate.reset_ball_to_center();
+            (x, y) if y == self.map_height - 1 || y == 0 => {
+                ball.dy = -ball.dy;
+                // Into the corner, past the end of a goal
+                if x == 0 || x == self.map_width - 1 {
+                    ball.dx = -ball.dx;
+                }
+                collison_detected = true;
             }
 
-            _ => {}
+            // Only gets this far where the end wall isn't a goal
+            (x, _) if x == 0 || x == self.map_width - 1 => {
+                ball.dx = -ball.dx;
+                collison_detected = true;
+            }
+
+            // Turn back whichever way is blocked, both at the corner of an obstacle
+            (x, y) if self.is_obstacle(x, y) => {
+                let blocked_x = self.is_obstacle(x, ball.y);
+                let blocked_y = self.is_obstacle(ball.x, y);
+                if blocked_x || !blocked_y {
+                    ball.dx = -ball.dx;
+                }
+                if blocked_y || !blocked_x {
+                    ball.dy = -ball.dy;
+                }
+                collison_detected = true;
+            }
+
+            _ => {
+                // No collision
+            }
+        }
+        if collison_detected {
+            (new_x, new_y) = ball.next_pos();
+            // Boxed in, it waits a step for the way to clear
+            if self.is_obstacle(new_x, new_y) || new_x == 0 || new_x == self.map_width - 1 {
+                (new_x, new_y) = (ball.x, ball.y);
+            }
         }
+
+        ball.x = new_x;
+        ball.y = new_y;
+        self.balls[ball_idx] = ball;
+        paddle_hit.filter(|_| collison_detected)
     }
 
-    fn print_game_state(&self, gamestate: &mut GameState) {
-        debug!(
-            "{}:{} Ball x: {} Ball y: {} Ball DX: {} Ball DY: {} P1 Pos: {} P2 Pos: {} Map Width: {} Map Height {}",
-            gamestate.player_1_score,
-            gamestate.player_2_score,
-            gamestate.ball_pos_x,
-            gamestate.ball_pos_y,
-            gamestate.ball_dx,
-            gamestate.ball_dy,
-            gamestate.player_1.player_pos,
-            gamestate.player_2.player_pos,
-            gamestate.map_width,
-            gamestate.map_height
-        )
+    /// Half the ball's speed every tick, the second tick of each pair takes any odd step
+    pub fn ball_steps(&mut self) -> u8 {
+        let half = self.ball_speed / 2;
+        let steps = if self.stepping {
+            self.ball_speed - half
+        } else {
+            half
+        };
+        self.stepping = !self.stepping;
+        steps
+    }
+
+    /// With two teams, a goal behind one side is a point for the other
+    pub fn score_against(&mut self, goal: Side) {
+        let scorer = if self.defending_team(goal) == 1 { 2 } else { 1 };
+        self.increment_score(scorer);
+        self.session.report_scores(&self.scores);
+    }
+
+    /// The team guarding a side, 0 if nobody is
+    pub fn defending_team(&self, side: Side) -> u8 {
+        self.players
+            .iter()
+            .find(|player| player.seat.side == side)
+            .map_or(0, |player| player.seat.team)
+    }
+
+    /// Players who start on the right see the map flipped, so everyone starts out seeing
+    /// themselves on the left
+    pub fn mirrored_for(&self, player_idx: u8) -> bool {
+        player_idx != 0 && (self.player(player_idx).seat.side == Side::Right) != self.ends_swapped
+    }
+
+    /// A paddle's side, line and position as seen by someone who might have the map flipped
+    fn paddle_view(&self, player: &Player, mirrored: bool) -> (Side, u8, u8) {
+        let seat = player.seat;
+        match (mirrored, seat.side.is_vertical()) {
+            (false, _) => (seat.side, seat.line, player.player_pos),
+            (true, true) => (
+                seat.side.mirrored(),
+                self.map_width - seat.line - 1,
+                player.player_pos,
+            ),
+            (true, false) => (seat.side, seat.line, self.map_width - player.player_pos - 1),
+        }
+    }
+
+    /// The full picture of the game from one player's point of view, spectators are
+    /// player 0 and see it as player 1 does. After the seed and mode comes every paddle
+    /// as `[count, (player id, team, side, line, position)..]`, then any balls past the
+    /// first, the goal width, the obstacles as `[count, (x, y, width, height)..]` and the
+    /// ball's speed.
+    pub fn snapshot_packet(&self, game_type: Gamemodes, player_idx: u8) -> Vec<u8> {
+        let mirrored = self.mirrored_for(player_idx);
+        let own = self.player(player_idx.max(1));
+        let opponent = self
+            .players
+            .iter()
+            .find(|player| player.seat.team != own.seat.team)
+            .unwrap_or(own);
+        let balls = self.ball_view(mirrored);
+
+        let mut v_data: Vec<u8> = vec![
+            ServerPacket::Snapshot as u8,
+            player_idx,
+            own.player_pos,
+            opponent.player_pos,
+            balls[0].0,
+            balls[0].1,
+            self.map_width,
+            self.map_height,
+            self.paddle_size,
+        ];
+        v_data.extend_from_slice(&self.seed.to_le_bytes());
+        v_data.push(game_type as u8);
+        v_data.push(self.players.len() as u8);
+        for (idx, player) in self.players.iter().enumerate() {
+            let (side, line, position) = self.paddle_view(player, mirrored);
+            v_data.extend_from_slice(&[
+                idx as u8 + 1,
+                player.seat.team,
+                side as u8,
+                line,
+                position,
+            ]);
+        }
+        Self::push_more_balls(&mut v_data, &balls);
+        v_data.push(self.goal_width);
+        v_data.push(self.obstacles.len() as u8);
+        for obstacle in &self.obstacles {
+            let x = if mirrored {
+                self.map_width - obstacle.x - obstacle.width
+            } else {
+                obstacle.x
+            };
+            v_data.extend_from_slice(&[x, obstacle.y, obstacle.width, obstacle.height]);
+        }
+        v_data.push(self.ball_speed);
+        v_data
+    }
+
+    /// Every ball's position, flipped if need be
+    fn ball_view(&self, mirrored: bool) -> Vec<(u8, u8)> {
+        self.balls
+            .iter()
+            .map(|ball| {
+                let x = if mirrored {
+                    self.map_width - ball.x - 1
+                } else {
+                    ball.x
+                };
+                (x, ball.y)
+            })
+            .collect()
+    }
+
+    /// The first ball has its own place in the packets, the rest go on the end as
+    /// `[count, (x, y)..]`
+    fn push_more_balls(v_data: &mut Vec<u8>, balls: &[(u8, u8)]) {
+        v_data.push(balls.len() as u8 - 1);
+        for (x, y) in &balls[1..] {
+            v_data.extend_from_slice(&[*x, *y]);
+        }
+    }
+
+    /// `[id, player id, paddle 1, paddle 2, ball x, ball y, more paddles count, paddle 3..,
+    /// more balls count, (ball x, ball y)..]`, flipped for players who see the map that way
+    pub fn update_packet(&self, player_idx: u8) -> Vec<u8> {
+        let mirrored = self.mirrored_for(player_idx);
+        let positions: Vec<u8> = self
+            .players
+            .iter()
+            .map(|player| self.paddle_view(player, mirrored).2)
+            .collect();
+        let balls = self.ball_view(mirrored);
+
+        let mut v_data: Vec<u8> = vec![
+            ServerPacket::Update as u8,
+            player_idx,
+            positions[0],
+            positions.get(1).copied().unwrap_or(0),
+            balls[0].0,
+            balls[0].1,
+            positions.len().saturating_sub(2) as u8,
+        ];
+        v_data.extend(positions.iter().skip(2));
+        Self::push_more_balls(&mut v_data, &balls);
+        v_data
+    }
+
+    /// Sends the same packet to every player and everyone watching
+    pub fn broadcast(&mut self, packet: &[u8]) {
+        for player in self.players.iter_mut() {
+            player.send_bytes(packet);
+        }
+        self.send_to_spectators(packet);
+    }
+
+    pub fn send_to_spectators(&mut self, packet: &[u8]) {
+        for spectator in self.spectators.iter_mut() {
+            spectator.send_bytes(packet);
+        }
+    }
+
+    /// Every player id in the game, from 1
+    pub fn player_ids(&self) -> RangeInclusive<u8> {
+        1..=self.players.len() as u8
+    }
+
+    pub fn player(&self, player_idx: u8) -> &Player {
+        &self.players[player_idx as usize - 1]
+    }
+
+    pub fn player_mut(&mut self, player_idx: u8) -> &mut Player {
+        &mut self.players[player_idx as usize - 1]
+    }
+
+    /// Every command the players sent since this was last called, tagged with who sent it
+    pub fn take_commands(&mut self) -> Vec<(u8, PlayerCommand)> {
+        let mut commands: Vec<(u8, PlayerCommand)> = Vec::new();
+        for player_idx in self.player_ids() {
+            let player = self.player_mut(player_idx);
+            commands.extend(
+                player
+                    .commands
+                    .drain(..)
+                    .map(|command| (player_idx, command)),
+            );
+        }
+        commands
+    }
+
+    pub fn players_left(&self) -> Vec<u8> {
+        self.player_ids()
+            .filter(|idx| self.player(*idx).left)
+            .collect()
+    }
+
+    pub fn disconnected_players(&self) -> Vec<u8> {
+        self.player_ids()
+            .filter(|idx| !self.player(*idx).connected)
+            .collect()
+    }
+}
+/// Every mode a client can ask for, sent as a byte in the handshake and the snapshot
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Gamemodes {
+    Standard = 0,
+    /// One player against a solid wall, counting returns in a row
+    Practice = 1,
+    /// Two against two, a keeper and a forward on each side
+    Doubles = 2,
+    /// Four players, one on each wall, every wall is a goal
+    FreeForAll = 3,
+    /// Two players, with more balls joining in as the game goes on
+    MultiBall = 4,
+    /// Two players, with pickups on the map the ball can collect
+    PowerUps = 5,
+    /// Standard games back to back, best of however many the rules say
+    Series = 6,
+}
+
+/// Gives the byte back if no mode has that id
+impl TryFrom<u8> for Gamemodes {
+    type Error = u8;
+
+    fn try_from(mode: u8) -> Result<Self, Self::Error> {
+        match mode {
+            0 => Ok(Gamemodes::Standard),
+            1 => Ok(Gamemodes::Practice),
+            2 => Ok(Gamemodes::Doubles),
+            3 => Ok(Gamemodes::FreeForAll),
+            4 => Ok(Gamemodes::MultiBall),
+            5 => Ok(Gamemodes::PowerUps),
+            6 => Ok(Gamemodes::Series),
+            mode => Err(mode),
+        }
+    }
+}
+
+impl Gamemodes {
+    /// The names clients pick modes by, as in `--mode`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(Gamemodes::Standard),
+            "practice" => Some(Gamemodes::Practice),
+            "doubles" => Some(Gamemodes::Doubles),
+            "ffa" => Some(Gamemodes::FreeForAll),
+            "multiball" => Some(Gamemodes::MultiBall),
+            "powerups" => Some(Gamemodes::PowerUps),
+            "series" => Some(Gamemodes::Series),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gamemodes::Standard => "standard",
+            Gamemodes::Practice => "practice",
+            Gamemodes::Doubles => "doubles",
+            Gamemodes::FreeForAll => "ffa",
+            Gamemodes::MultiBall => "multiball",
+            Gamemodes::PowerUps => "powerups",
+            Gamemodes::Series => "series",
+        }
+    }
+}
+
+/// How a game ended, as told to everyone in it
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    /// The winning team, 0 when nobody won
+    pub winner: u8,
+    /// One per team
+    pub scores: Vec<u8>,
+    pub reason: GameOverReason,
+}
+
+impl GameResult {
+    /// `[id, winner, score 1, score 2, reason, score 3..]`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut v_data: Vec<u8> = vec![
+            ServerPacket::GameOver as u8,
+            self.winner,
+            self.scores.first().copied().unwrap_or(0),
+            self.scores.get(1).copied().unwrap_or(0),
+            self.reason as u8,
+        ];
+        v_data.extend(self.scores.iter().skip(2));
+        v_data
+    }
+}
+
+/// The rules of a mode. `GameRunner` looks after the connections, pausing and
+/// reconnecting, and calls these hooks to find out how the game itself plays.
+/// Anything a mode keeps track of beyond the shared `GameState` lives in the mode.
+pub trait Gamemode: Debug + Send {
+    fn game_type(&self) -> Gamemodes;
+
+    /// Runs once a tick before the ball moves, returning a winner ends the game
+    fn on_tick(&mut self, _gamestate: &mut GameState) -> Option<u8> {
+        None
+    }
+
+    /// Sees every pause and forfeit before the runner acts on it, returning false drops it
+    fn on_input(
+        &mut self,
+        _gamestate: &mut GameState,
+        _player_idx: u8,
+        _command: PlayerCommand,
+    ) -> bool {
+        true
+    }
+
+    /// Which goal the ball is in, checked before every step of the ball
+    fn goal_scored(&self, gamestate: &GameState, ball: &Ball) -> Option<Side> {
+        gamestate.goal_scored(ball)
+    }
+
+    /// A ball got into the goal behind `goal`, with two teams the other one scores
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, goal: Side) {
+        gamestate.score_against(goal);
+        gamestate.reset_ball_to_center(ball_idx);
+    }
+
+    /// How many steps every ball takes this tick, goals are checked before each one
+    fn ball_steps(&mut self, gamestate: &mut GameState) -> u8 {
+        gamestate.ball_steps()
+    }
+
+    /// Moves a ball a single step, bouncing it off the walls and paddles
+    fn step_ball(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        gamestate.move_ball(ball_idx);
+    }
+
+    /// Decides what everyone is told once the game is over
+    fn on_end(
+        &mut self,
+        gamestate: &mut GameState,
+        winner: u8,
+        reason: GameOverReason,
+    ) -> GameResult {
+        GameResult {
+            winner,
+            scores: gamestate.scores.clone(),
+            reason,
+        }
+    }
+
+    /// What a player joining the game is sent, spectators are player 0
+    fn render_snapshot(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        gamestate.snapshot_packet(self.game_type(), player_idx)
     }
 }
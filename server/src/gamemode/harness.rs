@@ -0,0 +1,582 @@
+//! Runs games without a network or a clock, driven by scripted in-memory players
+
+use std::{env, fs, path::PathBuf, process, sync::Arc};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    gamemode::{
+        doubles::DoublesGame,
+        free_for_all::FreeForAllGame,
+        gamemode::{GameState, Gamemode, Gamemodes},
+        practice::PracticeGame,
+        runner::GameRunner,
+        standard::StandardGame,
+    },
+    models::{
+        arena::{Arena, tests::PILLARS},
+        packet::{GameOverReason, ServerPacket},
+        player::{MessageType, NewPlayer, PlayerCommand},
+        replay::ReplayFrame,
+        rules::MatchRules,
+        spectator::Spectator,
+        transport::{MemoryTransport, Transport},
+    },
+    registry::{GameRegistry, GameSession},
+};
+
+/// The ball moves one column a tick, and every other tick it moves twice
+const MAX_STEPS_PER_TICK: u8 = 2;
+
+pub struct Harness {
+    pub runner: GameRunner,
+    /// The client ends of the players' connections in player order, practice only has the one
+    pub clients: Vec<MemoryTransport>,
+    registry: Arc<GameRegistry>,
+}
+
+impl Harness {
+    pub fn new(seed: u64) -> Self {
+        Self::with_mode(Box::new(StandardGame), seed)
+    }
+
+    /// Two players in any mode that plays on the standard map
+    pub fn with_mode(mode: Box<dyn Gamemode>, seed: u64) -> Self {
+        Self::with_rules(mode, &MatchRules::default(), seed)
+    }
+
+    /// Two players on an empty map, played by other rules
+    pub fn with_rules(mode: Box<dyn Gamemode>, rules: &MatchRules, seed: u64) -> Self {
+        let registry = Arc::new(GameRegistry::new());
+        let (player_1, client_1) = Self::player("alice");
+        let (player_2, client_2) = Self::player("bobby");
+        let session = registry.create_session(&[&player_1, &player_2]);
+        let gamestate = GameState::new(player_1, player_2, rules, session, seed);
+        Self::start(mode, gamestate, vec![client_1, client_2], registry)
+    }
+
+    /// A standard game on any arena
+    pub fn in_arena(arena: &Arena, seed: u64) -> Self {
+        let registry = Arc::new(GameRegistry::new());
+        let (player_1, client_1) = Self::player("alice");
+        let (player_2, client_2) = Self::player("bobby");
+        let session = registry.create_session(&[&player_1, &player_2]);
+        let gamestate = StandardGame::setup_game(
+            player_1,
+            player_2,
+            arena,
+            &MatchRules::default(),
+            session,
+            seed,
+        );
+        Self::start(
+            Box::new(StandardGame),
+            gamestate,
+            vec![client_1, client_2],
+            registry,
+        )
+    }
+
+    pub fn practice(seed: u64) -> Self {
+        let registry = Arc::new(GameRegistry::new());
+        let (player, client) = Self::player("alice");
+        let session = registry.create_session(&[&player]);
+        let gamestate = PracticeGame::setup_game(player, &MatchRules::default(), session, seed);
+        Self::start(
+            Box::new(PracticeGame::default()),
+            gamestate,
+            vec![client],
+            registry,
+        )
+    }
+
+    /// Four players, in doubles or free for all
+    pub fn four_players(
+        setup_game: fn(Vec<NewPlayer>, &MatchRules, GameSession, u64) -> GameState,
+        mode: Box<dyn Gamemode>,
+        rules: &MatchRules,
+        seed: u64,
+    ) -> Self {
+        let registry = Arc::new(GameRegistry::new());
+        let (players, clients): (Vec<NewPlayer>, Vec<MemoryTransport>) =
+            ["alice", "bobby", "carol", "dave"]
+                .into_iter()
+                .map(Self::player)
+                .unzip();
+        let player_refs: Vec<&NewPlayer> = players.iter().collect();
+        let session = registry.create_session(&player_refs);
+        let gamestate = setup_game(players, rules, session, seed);
+        Self::start(mode, gamestate, clients, registry)
+    }
+
+    pub fn doubles(seed: u64) -> Self {
+        Self::four_players(
+            DoublesGame::setup_game,
+            Box::new(DoublesGame),
+            &MatchRules::default(),
+            seed,
+        )
+    }
+
+    pub fn free_for_all(seed: u64) -> Self {
+        Self::four_players(
+            FreeForAllGame::setup_game,
+            Box::new(FreeForAllGame::default()),
+            &FreeForAllGame::default_rules(),
+            seed,
+        )
+    }
+
+    fn player(name: &str) -> (NewPlayer, MemoryTransport) {
+        let (server_end, client_end) = MemoryTransport::pair(name);
+        let mut player = NewPlayer::new(Gamemodes::Standard, Box::new(server_end));
+        player.player_name = Some(name.to_string());
+        (player, client_end)
+    }
+
+    fn start(
+        mode: Box<dyn Gamemode>,
+        gamestate: GameState,
+        mut clients: Vec<MemoryTransport>,
+        registry: Arc<GameRegistry>,
+    ) -> Self {
+        let mut runner = GameRunner::new(mode, gamestate);
+        // Nothing to wait on in memory, polling the players shouldn't cost a timeout each tick
+        for player in runner.gamestate.players.iter_mut() {
+            player.transport.set_nonblocking(true).unwrap();
+        }
+        for client in clients.iter_mut() {
+            client.set_nonblocking(true).unwrap();
+        }
+        Self {
+            runner,
+            clients,
+            registry,
+        }
+    }
+
+    pub fn gamestate(&self) -> &GameState {
+        &self.runner.gamestate
+    }
+
+    pub fn send(&mut self, player_idx: u8, message: &[u8]) {
+        self.clients[player_idx as usize - 1].send(message).unwrap();
+    }
+
+    pub fn move_paddle(&mut self, player_idx: u8, position: u8) {
+        self.send(player_idx, &[MessageType::PlayerPos as u8, position]);
+    }
+
+    /// Everything the server has sent this player since the last call
+    pub fn received(&mut self, player_idx: u8) -> Vec<Vec<u8>> {
+        let client = &mut self.clients[player_idx as usize - 1];
+        std::iter::from_fn(|| client.receive().unwrap()).collect()
+    }
+
+    /// Asks to watch the game, the spectator joins on the next tick
+    pub fn spectate(&mut self) -> MemoryTransport {
+        let (server_end, mut client_end) = MemoryTransport::pair("spectator");
+        client_end.set_nonblocking(true).unwrap();
+        let game_id = self.gamestate().session.game_id;
+        let spectator = Spectator::new(Box::new(server_end));
+        assert!(self.registry.try_spectate(game_id, spectator).is_ok());
+        client_end
+    }
+
+    pub fn tick(&mut self) -> Option<i32> {
+        self.runner.tick()
+    }
+
+    /// The game over this player was sent, anything before it is skipped
+    pub fn game_over(&mut self, player_idx: u8) -> Vec<u8> {
+        game_over_packet(&mut self.clients[player_idx as usize - 1])
+    }
+
+    pub fn frame(&self) -> ReplayFrame {
+        ReplayFrame::from_state(self.gamestate())
+    }
+}
+
+/// A file in the temp directory that goes away with the test, even one that fails
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        Self(env::temp_dir().join(format!("ping-pong-{}-{}.txt", name, process::id())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn game_over_packet(client: &mut MemoryTransport) -> Vec<u8> {
+    std::iter::from_fn(|| client.receive().unwrap())
+        .find(|packet| packet[0] == ServerPacket::GameOver as u8)
+        .expect("no game over packet")
+}
+
+/// Moves every paddle around at random, including positions well outside the map
+pub fn random_inputs(harness: &mut Harness, rng: &mut StdRng) {
+    for player_idx in harness.gamestate().player_ids() {
+        if rng.random_bool(0.3) {
+            let position = rng.random_range(0..=u8::MAX / 4);
+            harness.move_paddle(player_idx, position);
+        }
+    }
+}
+
+pub fn assert_invariants(
+    before: &ReplayFrame,
+    after: &ReplayFrame,
+    gamestate: &GameState,
+    tick: u32,
+) {
+    let (map_width, map_height) = (gamestate.map_width, gamestate.map_height);
+
+    assert!(
+        (1..map_width - 1).contains(&after.ball_x) && (1..map_height - 1).contains(&after.ball_y),
+        "tick {}: ball left the map at {:?}",
+        tick,
+        (after.ball_x, after.ball_y)
+    );
+
+    // Power-ups change paddle sizes, each is checked against its own
+    let paddle_sizes = gamestate.players.iter().map(|player| player.paddle_size);
+    for (paddle_pos, paddle_size) in [after.player_1_pos, after.player_2_pos]
+        .into_iter()
+        .zip(paddle_sizes)
+    {
+        assert!(
+            paddle_pos > paddle_size && paddle_pos + paddle_size < map_height - 1,
+            "tick {}: paddle at {} overlaps a wall",
+            tick,
+            paddle_pos
+        );
+    }
+
+    let scored = (
+        after.player_1_score - before.player_1_score,
+        after.player_2_score - before.player_2_score,
+    );
+    // Goals are at column 1 and the player 2 paddle, the ball has to have been in reach of one
+    match scored {
+        (0, 0) => {}
+        (1, 0) => assert!(
+            before.ball_x + MAX_STEPS_PER_TICK >= map_width - 3,
+            "tick {}: player 1 scored with the ball at {}",
+            tick,
+            before.ball_x
+        ),
+        (0, 1) => assert!(
+            before.ball_x <= 1 + MAX_STEPS_PER_TICK,
+            "tick {}: player 2 scored with the ball at {}",
+            tick,
+            before.ball_x
+        ),
+        scored => panic!("tick {}: scores jumped by {:?}", tick, scored),
+    }
+}
+
+#[test]
+fn invariants_hold_over_long_games() {
+    for seed in 0..20 {
+        let mut harness = Harness::new(seed);
+        let mut input_rng = StdRng::seed_from_u64(seed);
+        for tick in 0..5_000 {
+            random_inputs(&mut harness, &mut input_rng);
+            let before = harness.frame();
+            assert_eq!(harness.tick(), None, "seed {}: game ended by itself", seed);
+            assert_invariants(&before, &harness.frame(), harness.gamestate(), tick);
+            harness.received(1);
+            harness.received(2);
+        }
+    }
+}
+
+#[test]
+fn goals_are_scored() {
+    let mut harness = Harness::new(7);
+    // Both paddles parked in a corner miss most balls
+    for _ in 0..5_000 {
+        harness.move_paddle(1, 0);
+        harness.move_paddle(2, 0);
+        harness.tick();
+    }
+    let frame = harness.frame();
+    assert!(frame.player_1_score + frame.player_2_score > 0);
+}
+
+#[test]
+fn same_seed_and_inputs_play_the_same_game() {
+    let play = |seed: u64| {
+        let mut harness = Harness::new(seed);
+        let mut input_rng = StdRng::seed_from_u64(99);
+        (0..2_000)
+            .map(|_| {
+                random_inputs(&mut harness, &mut input_rng);
+                harness.tick();
+                harness.frame()
+            })
+            .collect::<Vec<ReplayFrame>>()
+    };
+    assert_eq!(play(42), play(42));
+    assert_ne!(play(42), play(43));
+}
+
+#[test]
+fn forfeit_ends_the_game() {
+    let mut harness = Harness::new(1);
+    harness.tick();
+    harness.send(1, &[MessageType::Forfeit as u8, 0]);
+    assert_eq!(harness.tick(), Some(2));
+
+    for player_idx in [1, 2] {
+        let game_over = harness.game_over(player_idx);
+        assert_eq!(game_over, [4, 2, 0, 0, GameOverReason::Forfeit as u8]);
+    }
+}
+
+#[test]
+fn admin_can_message_kick_and_end_games() {
+    let mut harness = Harness::new(1);
+    harness.tick();
+    assert_eq!(harness.registry.send_message("hello"), 1);
+    harness.tick();
+    let message = [&[ServerPacket::Message as u8][..], b"hello"].concat();
+    for player_idx in [1, 2] {
+        assert!(harness.received(player_idx).contains(&message));
+    }
+
+    // Kicking hands the game to whoever is left
+    let game_id = harness.gamestate().session.game_id;
+    assert_eq!(harness.registry.kick("alice"), Some(game_id));
+    assert_eq!(harness.registry.kick("carol"), None);
+    assert_eq!(harness.tick(), Some(2));
+    let game_over = harness.game_over(2);
+    assert_eq!(game_over, [4, 2, 0, 0, GameOverReason::Kicked as u8]);
+
+    // Ending one has no winner
+    let mut harness = Harness::new(1);
+    harness.tick();
+    let game_id = harness.gamestate().session.game_id;
+    assert!(harness.registry.end_game(game_id));
+    assert!(!harness.registry.end_game(game_id + 1));
+    assert_eq!(harness.tick(), Some(0));
+    let game_over = harness.game_over(1);
+    assert_eq!(game_over, [4, 0, 0, 0, GameOverReason::Ended as u8]);
+}
+
+#[test]
+fn empty_frames_from_spectators_are_ignored() {
+    let mut harness = Harness::new(1);
+    let mut spectator = harness.spectate();
+    harness.tick();
+    spectator.send(&[]).unwrap();
+    for _ in 0..3 {
+        assert_eq!(harness.tick(), None);
+    }
+    let packets: Vec<Vec<u8>> = std::iter::from_fn(|| spectator.receive().unwrap()).collect();
+    assert_eq!(packets[0][0], ServerPacket::Snapshot as u8);
+    assert!(
+        packets
+            .iter()
+            .skip(1)
+            .all(|packet| packet[0] == ServerPacket::Update as u8)
+    );
+    assert_eq!(harness.gamestate().spectators.len(), 1);
+}
+
+#[test]
+fn leaving_hands_the_other_player_the_game() {
+    let mut harness = Harness::new(1);
+    harness.tick();
+    harness.send(2, &[MessageType::Shutdown as u8, 0]);
+    assert_eq!(harness.tick(), Some(1));
+    assert!(
+        harness
+            .received(2)
+            .contains(&vec![ServerPacket::ShutdownAck as u8])
+    );
+}
+
+/// Breaks on the first tick, like a mode with a bug in it
+#[derive(Debug)]
+struct PanickingGame;
+
+impl Gamemode for PanickingGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::Standard
+    }
+
+    fn on_tick(&mut self, _gamestate: &mut GameState) -> Option<u8> {
+        panic!("bug in the mode");
+    }
+}
+
+#[test]
+fn a_crashing_game_tells_its_players_and_goes_away() {
+    let Harness {
+        mut runner,
+        mut clients,
+        registry,
+    } = Harness::with_mode(Box::new(PanickingGame), 1);
+    assert_eq!(runner.start_supervised(), 0);
+    for client in clients.iter_mut() {
+        let game_over = game_over_packet(client);
+        assert_eq!(game_over, [4, 0, 0, 0, GameOverReason::Crashed as u8]);
+    }
+    // Nothing is left behind once the game's thread lets go of it
+    drop(runner);
+    assert!(registry.is_empty());
+}
+
+/// Built only from the hooks, the game ends on the first goal
+#[derive(Debug)]
+struct FirstGoalWins;
+
+impl Gamemode for FirstGoalWins {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::Standard
+    }
+
+    fn on_tick(&mut self, gamestate: &mut GameState) -> Option<u8> {
+        match (gamestate.scores[0], gamestate.scores[1]) {
+            (0, 0) => None,
+            (player_1_score, player_2_score) if player_1_score > player_2_score => Some(1),
+            _ => Some(2),
+        }
+    }
+
+    fn on_input(
+        &mut self,
+        _gamestate: &mut GameState,
+        _player_idx: u8,
+        command: PlayerCommand,
+    ) -> bool {
+        // No pausing this one
+        command != PlayerCommand::Pause
+    }
+}
+
+#[test]
+fn modes_plug_in_through_hooks() {
+    let mut harness = Harness::with_mode(Box::new(FirstGoalWins), 7);
+    harness.send(1, &[MessageType::Pause as u8, 0]);
+    let mut result = None;
+    for _ in 0..5_000 {
+        // Both paddles parked in a corner, someone scores soon enough
+        harness.move_paddle(1, 0);
+        harness.move_paddle(2, 0);
+        result = harness.tick();
+        if result.is_some() {
+            break;
+        }
+    }
+    let winner = result.expect("nobody scored") as u8;
+
+    let game_over = harness.game_over(1);
+    assert_eq!(game_over[1], winner);
+    assert_eq!(game_over[4], GameOverReason::Finished as u8);
+    assert!(
+        harness.received(1).is_empty(),
+        "the game kept going after it ended"
+    );
+}
+
+#[test]
+fn obstacles_and_solid_walls_turn_the_ball_back() {
+    let arena = Arena::parse(PILLARS).unwrap();
+    let mut goals = 0;
+    for seed in 0..10 {
+        let mut harness = Harness::in_arena(&arena, seed);
+        let mut input_rng = StdRng::seed_from_u64(seed);
+        for tick in 0..5_000 {
+            random_inputs(&mut harness, &mut input_rng);
+            let before = harness.frame();
+            assert_eq!(harness.tick(), None, "seed {}: game ended by itself", seed);
+            let after = harness.frame();
+            assert_invariants(&before, &after, harness.gamestate(), tick);
+
+            let gamestate = harness.gamestate();
+            let ball = gamestate.balls[0];
+            assert!(
+                !gamestate.is_obstacle(ball.x, ball.y),
+                "seed {} tick {}: ball inside an obstacle at {:?}",
+                seed,
+                tick,
+                (ball.x, ball.y)
+            );
+            // The ball moves at most two rows a tick, so it was near the goal mouth
+            if after.player_1_score + after.player_2_score
+                != before.player_1_score + before.player_2_score
+            {
+                goals += 1;
+                let near_goal = (before.ball_y.saturating_sub(2)..=before.ball_y + 2)
+                    .any(|y| gamestate.in_goal(y));
+                assert!(
+                    near_goal,
+                    "seed {} tick {}: goal through the wall at row {}",
+                    seed, tick, before.ball_y
+                );
+            }
+            harness.received(1);
+            harness.received(2);
+        }
+    }
+    assert!(goals > 0);
+}
+
+#[test]
+fn arena_layout_is_in_the_snapshot() {
+    let arena = Arena::parse(PILLARS).unwrap();
+    let harness = Harness::in_arena(&arena, 1);
+    let render = |player_idx| {
+        harness
+            .runner
+            .mode
+            .render_snapshot(harness.gamestate(), player_idx)
+    };
+    // After the two paddles and the extra ball count
+    let layout = 19 + 2 * 5 + 1;
+    let snapshot = render(1);
+    assert_eq!(snapshot[layout..layout + 2], [14, 2]);
+    assert_eq!(snapshot[layout + 2..layout + 6], [30, 6, 3, 6]);
+    // Player 2 sees the map flipped
+    let snapshot = render(2);
+    assert_eq!(snapshot[layout + 2..layout + 6], [80 - 30 - 3, 6, 3, 6]);
+    // The ball's speed is last
+    assert_eq!(snapshot.len(), layout + 2 + 2 * 4 + 1);
+    assert_eq!(
+        snapshot[layout + 2 + 2 * 4],
+        MatchRules::default().ball_speed
+    );
+}
+
+#[test]
+fn games_are_played_by_their_rules() {
+    let rules = MatchRules {
+        map_width: 100,
+        map_height: 40,
+        paddle_size: 6,
+        ball_speed: 6,
+        ..MatchRules::default()
+    };
+    let mut harness = Harness::with_rules(Box::new(StandardGame), &rules, 3);
+    let snapshot = harness.runner.mode.render_snapshot(harness.gamestate(), 1);
+    assert_eq!(snapshot[6..9], [100, 40, 6]);
+    assert_eq!(snapshot.last(), Some(&6));
+
+    // Paddles stay clear of the walls at their bigger size
+    harness.move_paddle(1, 0);
+    harness.move_paddle(2, u8::MAX);
+    let ball_x = harness.gamestate().balls[0].x;
+    harness.tick();
+    let gamestate = harness.gamestate();
+    assert_eq!(gamestate.player(1).player_pos, 7);
+    assert_eq!(gamestate.player(2).player_pos, 40 - 6 - 2);
+    // The ball starts out heading straight right, three cells a tick
+    assert_eq!(gamestate.balls[0].x, ball_x + 3);
+}
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, io::ErrorKind, path::PathBuf, sync::Arc, thread};
+
+use log::{info, warn};
+use rand::seq::IndexedRandom;
+
+use crate::{
+    gamemode::{
+        doubles::DoublesGame,
+        free_for_all::FreeForAllGame,
+        gamemode::{GameState, Gamemode, Gamemodes, TICK_INTERVAL},
+        multi_ball::MultiBallGame,
+        power_ups::PowerUpsGame,
+        practice::PracticeGame,
+        runner::GameRunner,
+        series::SeriesGame,
+        standard::StandardGame,
+    },
+    models::{
+        arena::Arena,
+        player::NewPlayer,
+        replay::ReplayRecorder,
+        rules::{MatchRules, RulesError, RulesFile},
+    },
+    registry::GameRegistry,
+    stats::StatsStore,
+};
+
+/// What the server hands to every mode when starting one of its games
+#[derive(Debug)]
+pub struct GameContext {
+    pub game_registry: Arc<GameRegistry>,
+    /// Where every game is recorded to
+    pub replay_directory: PathBuf,
+    pub stats: Arc<StatsStore>,
+    /// The server's own arenas, standard games are played on one of them
+    pub arenas: Vec<Arena>,
+}
+
+impl GameContext {
+    /// Any of the server's arenas, an empty map the size the rules ask for when it has none
+    pub fn pick_arena(&self, rules: &MatchRules) -> Arena {
+        self.arenas
+            .choose(&mut rand::rng())
+            .cloned()
+            .unwrap_or_else(|| Arena::empty(rules.map_width, rules.map_height))
+    }
+
+    /// Records the game and plays it out on its own thread
+    pub fn run_game(&self, mode: Box<dyn Gamemode>, gamestate: GameState) {
+        self.run_game_then(mode, gamestate, |_| {});
+    }
+
+    /// Same as `run_game`, handing the winning team to `on_end` on the game's thread once
+    /// it's over
+    pub fn run_game_then(
+        &self,
+        mode: Box<dyn Gamemode>,
+        mut gamestate: GameState,
+        on_end: impl FnOnce(u8) + Send + 'static,
+    ) {
+        match ReplayRecorder::create(&self.replay_directory, &gamestate, TICK_INTERVAL) {
+            Ok(replay) => gamestate.replay = Some(replay),
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                info!("Not recording game {}: {}", gamestate.session.game_id, e)
+            }
+            Err(e) => warn!("Not recording game {}: {:?}", gamestate.session.game_id, e),
+        }
+        match thread::Builder::new()
+            .name("Game".to_string())
+            .spawn(move || on_end(GameRunner::new(mode, gamestate).start_supervised() as u8))
+        {
+            Ok(game_thread) => self.game_registry.add_thread(game_thread),
+            Err(e) => warn!("Failed to start a game thread: {:?}", e),
+        }
+    }
+}
+
+/// Starts a game with exactly as many players as the mode asked for
+pub type Launcher = fn(Vec<NewPlayer>, &MatchRules, &GameContext);
+
+#[derive(Clone, Copy, Debug)]
+pub struct ModeEntry {
+    /// How many players have to be queued before a game starts
+    pub players: usize,
+    /// Whether a bot can take an empty seat, for players asking for one or waiting too long
+    pub bots_allowed: bool,
+    /// What every game of the mode is played with
+    pub rules: MatchRules,
+    pub launch: Launcher,
+}
+
+/// Which modes the server offers and how to start each of them
+#[derive(Debug, Default)]
+pub struct ModeRegistry {
+    modes: HashMap<Gamemodes, ModeEntry>,
+}
+
+impl ModeRegistry {
+    pub fn new() -> Self {
+        Self {
+            modes: HashMap::new(),
+        }
+    }
+
+    /// Every mode this server comes with
+    pub fn with_default_modes() -> Self {
+        let mut mode_registry = Self::new();
+        mode_registry.register(
+            Gamemodes::Standard,
+            ModeEntry {
+                players: 2,
+                bots_allowed: true,
+                rules: MatchRules::default(),
+                launch: StandardGame::launch,
+            },
+        );
+        mode_registry.register(
+            Gamemodes::Practice,
+            ModeEntry {
+                players: 1,
+                bots_allowed: false,
+                rules: MatchRules::default(),
+                launch: PracticeGame::launch,
+            },
+        );
+        mode_registry.register(
+            Gamemodes::Doubles,
+            ModeEntry {
+                players: 4,
+                bots_allowed: true,
+                rules: MatchRules::default(),
+                launch: DoublesGame::launch,
+            },
+        );
+        mode_registry.register(
+            Gamemodes::FreeForAll,
+            ModeEntry {
+                players: 4,
+                bots_allowed: false,
+                rules: FreeForAllGame::default_rules(),
+                launch: FreeForAllGame::launch,
+            },
+        );
+        mode_registry.register(
+            Gamemodes::MultiBall,
+            ModeEntry {
+                players: 2,
+                bots_allowed: true,
+                rules: MatchRules::default(),
+                launch: MultiBallGame::launch,
+            },
+        );
+        mode_registry.register(
+            Gamemodes::PowerUps,
+            ModeEntry {
+                players: 2,
+                bots_allowed: true,
+                rules: MatchRules::default(),
+                launch: PowerUpsGame::launch,
+            },
+        );
+        // Played by the standard game, which turns into a series with more than one game
+        mode_registry.register(
+            Gamemodes::Series,
+            ModeEntry {
+                players: 2,
+                bots_allowed: true,
+                rules: SeriesGame::default_rules(),
+                launch: StandardGame::launch,
+            },
+        );
+        mode_registry
+    }
+
+    pub fn register(&mut self, mode: Gamemodes, entry: ModeEntry) {
+        self.modes.insert(mode, entry);
+    }
+
+    pub fn get(&self, mode: Gamemodes) -> Option<&ModeEntry> {
+        self.modes.get(&mode)
+    }
+
+    /// Changes every mode's rules to what the server's rules file says, all of them
+    /// have to come out valid
+    pub fn apply_rules(&mut self, rules_file: &RulesFile) -> Result<(), RulesError> {
+        for (mode, entry) in self.modes.iter_mut() {
+            entry.rules = rules_file.apply(*mode, entry.rules)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use log::info;
+
+use crate::{
+    gamemode::{
+        gamemode::{GameState, Gamemode, Gamemodes, Side},
+        modes::GameContext,
+    },
+    models::{player::NewPlayer, rules::MatchRules},
+};
+
+/// Most balls in play at once
+pub const MAX_BALLS: usize = 3;
+/// Ticks between two new balls, about ten seconds
+const SPAWN_INTERVAL: u32 = 300;
+/// Paddle hits in a rally before it gets another ball
+const RALLY_HITS: u32 = 6;
+
+/// The standard game, but a new ball joins every so often and whenever a rally goes on
+/// long enough. Extra balls leave once they score, the last one is served again.
+#[derive(Debug, Default)]
+pub struct MultiBallGame {
+    ticks_since_spawn: u32,
+    /// Paddle hits since the last goal
+    rally: u32,
+}
+
+impl MultiBallGame {
+    pub fn launch(players: Vec<NewPlayer>, rules: &MatchRules, context: &GameContext) {
+        let [player_1, player_2]: [NewPlayer; 2] = players.try_into().unwrap();
+        info!("Starting multi-ball game");
+        let session = context
+            .game_registry
+            .create_session(&[&player_1, &player_2]);
+        let gamestate = GameState::new(player_1, player_2, rules, session, rand::random());
+        context.run_game(Box::new(MultiBallGame::default()), gamestate);
+    }
+
+    /// Serves another ball from the middle, unless the map is full already
+    fn spawn_ball(&mut self, gamestate: &mut GameState) {
+        self.ticks_since_spawn = 0;
+        if gamestate.balls.len() < MAX_BALLS {
+            let ball = gamestate.center_ball();
+            gamestate.balls.push(ball);
+            info!("Ball {} joined the game", gamestate.balls.len());
+        }
+    }
+}
+
+impl Gamemode for MultiBallGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::MultiBall
+    }
+
+    fn on_tick(&mut self, gamestate: &mut GameState) -> Option<u8> {
+        self.ticks_since_spawn += 1;
+        if self.ticks_since_spawn >= SPAWN_INTERVAL {
+            self.spawn_ball(gamestate);
+        }
+        None
+    }
+
+    /// Every ball counts, but only the last one left gets served again
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, goal: Side) {
+        gamestate.score_against(goal);
+        self.rally = 0;
+        if gamestate.balls.len() > 1 {
+            gamestate.balls.remove(ball_idx);
+        } else {
+            gamestate.reset_ball_to_center(ball_idx);
+        }
+    }
+
+    fn step_ball(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        if gamestate.move_ball(ball_idx).is_some() {
+            self.rally += 1;
+            if self.rally.is_multiple_of(RALLY_HITS) {
+                self.spawn_ball(gamestate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{
+        gamemode::harness::{Harness, random_inputs},
+        models::packet::ServerPacket,
+    };
+
+    #[test]
+    fn keeps_every_ball_on_the_map() {
+        for seed in 0..10 {
+            let mut harness = Harness::with_mode(Box::new(MultiBallGame::default()), seed);
+            let mut input_rng = StdRng::seed_from_u64(seed);
+            let mut most_balls = 0;
+            for tick in 0..3_000 {
+                random_inputs(&mut harness, &mut input_rng);
+                // The update goes out before the balls move
+                let sent_balls = harness.gamestate().balls.clone();
+                assert_eq!(harness.tick(), None, "seed {}: game ended by itself", seed);
+
+                let gamestate = harness.gamestate();
+                let balls = &gamestate.balls;
+                assert!((1..=MAX_BALLS).contains(&balls.len()));
+                most_balls = most_balls.max(balls.len());
+                for ball in balls {
+                    assert!(
+                        (1..gamestate.map_width - 1).contains(&ball.x)
+                            && (1..gamestate.map_height - 1).contains(&ball.y),
+                        "seed {} tick {}: ball left the map at {:?}",
+                        seed,
+                        tick,
+                        (ball.x, ball.y)
+                    );
+                }
+
+                // Player 1 sees the map as it is, every ball is on the end of the update
+                let update = harness.received(1).pop().unwrap();
+                assert_eq!(update[0], ServerPacket::Update as u8);
+                assert_eq!(update[4..6], [sent_balls[0].x, sent_balls[0].y]);
+                assert_eq!(update[6], 0, "no paddles past the first two");
+                assert_eq!(update[7] as usize, sent_balls.len() - 1);
+                let more_balls: Vec<&[u8]> = update[8..].chunks_exact(2).collect();
+                assert_eq!(more_balls.len(), sent_balls.len() - 1);
+                for (ball, position) in sent_balls[1..].iter().zip(more_balls) {
+                    assert_eq!(position, [ball.x, ball.y]);
+                }
+                harness.received(2);
+            }
+            assert!(most_balls > 1, "seed {}: no balls ever joined", seed);
+        }
+    }
+
+    #[test]
+    fn scores_every_ball_and_keeps_one_in_play() {
+        let mut harness = Harness::with_mode(Box::new(MultiBallGame::default()), 3);
+        // Both paddles parked in a corner miss most balls
+        for _ in 0..5_000 {
+            harness.move_paddle(1, 0);
+            harness.move_paddle(2, 0);
+            harness.tick();
+            assert!(!harness.gamestate().balls.is_empty());
+            harness.received(1);
+            harness.received(2);
+        }
+        let frame = harness.frame();
+        assert!(frame.player_1_score + frame.player_2_score > 0);
+    }
+}
@@ -0,0 +1,368 @@
+use log::info;
+use rand::{Rng, seq::IndexedRandom};
+
+use crate::{
+    gamemode::{
+        gamemode::{GameState, Gamemode, Gamemodes, Side},
+        modes::GameContext,
+    },
+    models::{packet::ServerPacket, player::NewPlayer, rules::MatchRules},
+};
+
+/// Ticks between two new pickups, about five seconds
+const PICKUP_INTERVAL: u32 = 150;
+/// Most pickups waiting on the map at once
+const MAX_PICKUPS: usize = 3;
+/// How long an effect lasts once picked up, about ten seconds
+const EFFECT_TICKS: u32 = 300;
+/// How many steps of the ball a sticky paddle holds on to it for
+const HOLD_STEPS: u32 = 20;
+/// How much a paddle grows or shrinks, on each side of its middle
+const SIZE_CHANGE: u8 = 2;
+
+/// What a pickup does for whoever last hit the ball through it
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerUp {
+    BigPaddle = 0,
+    /// Every other paddle gets smaller
+    ShrinkOpponent = 1,
+    /// The ball moves twice as fast
+    FastBall = 2,
+    /// The ball stops on the paddle for a moment before it goes back
+    StickyPaddle = 3,
+    /// Stops the next goal against the player
+    Shield = 4,
+}
+
+impl PowerUp {
+    const ALL: [PowerUp; 5] = [
+        PowerUp::BigPaddle,
+        PowerUp::ShrinkOpponent,
+        PowerUp::FastBall,
+        PowerUp::StickyPaddle,
+        PowerUp::Shield,
+    ];
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pickup {
+    power_up: PowerUp,
+    x: u8,
+    y: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Effect {
+    player_idx: u8,
+    power_up: PowerUp,
+    ticks_left: u32,
+}
+
+/// The standard game with pickups showing up in the middle of the map. The ball
+/// collects one by passing through it, for whoever hit the ball last, and the effect
+/// wears off after a while.
+#[derive(Debug, Default)]
+pub struct PowerUpsGame {
+    pickups: Vec<Pickup>,
+    effects: Vec<Effect>,
+    ticks_since_pickup: u32,
+    last_hit: Option<u8>,
+    /// The player holding the ball on a sticky paddle, and for how many more steps
+    held: Option<(u8, u32)>,
+}
+
+impl PowerUpsGame {
+    pub fn launch(players: Vec<NewPlayer>, rules: &MatchRules, context: &GameContext) {
+        let [player_1, player_2]: [NewPlayer; 2] = players.try_into().unwrap();
+        info!("Starting power-ups game");
+        let session = context
+            .game_registry
+            .create_session(&[&player_1, &player_2]);
+        let gamestate = GameState::new(player_1, player_2, rules, session, rand::random());
+        context.run_game(Box::new(PowerUpsGame::default()), gamestate);
+    }
+
+    fn has_effect(&self, player_idx: u8, power_up: PowerUp) -> bool {
+        self.effects
+            .iter()
+            .any(|effect| effect.player_idx == player_idx && effect.power_up == power_up)
+    }
+
+    /// Drops a new pickup somewhere in the middle half of the map, away from the walls
+    fn spawn_pickup(&mut self, gamestate: &mut GameState) {
+        self.ticks_since_pickup = 0;
+        if self.pickups.len() >= MAX_PICKUPS {
+            return;
+        }
+        let x = gamestate
+            .rng
+            .random_range(gamestate.map_width / 4..gamestate.map_width * 3 / 4);
+        let y = gamestate.rng.random_range(2..gamestate.map_height - 2);
+        let power_up = *PowerUp::ALL.choose(&mut gamestate.rng).unwrap();
+        let taken = self
+            .pickups
+            .iter()
+            .any(|pickup| (pickup.x, pickup.y) == (x, y));
+        if !taken {
+            self.pickups.push(Pickup { power_up, x, y });
+            self.send_power_ups(gamestate);
+        }
+    }
+
+    /// Hands the pickup under the ball to the last player to hit it, a ball nobody has
+    /// touched since the serve goes straight through
+    fn collect_pickup(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        let ball = gamestate.balls[ball_idx];
+        let Some(player_idx) = self.last_hit else {
+            return;
+        };
+        let Some(pickup_idx) = self
+            .pickups
+            .iter()
+            .position(|pickup| (pickup.x, pickup.y) == (ball.x, ball.y))
+        else {
+            return;
+        };
+        let power_up = self.pickups.remove(pickup_idx).power_up;
+        info!(
+            "{} picked up {:?}",
+            gamestate.player(player_idx).player_name,
+            power_up
+        );
+        // Picking up the same thing again only starts it over
+        self.effects
+            .retain(|effect| effect.player_idx != player_idx || effect.power_up != power_up);
+        self.effects.push(Effect {
+            player_idx,
+            power_up,
+            ticks_left: EFFECT_TICKS,
+        });
+        self.resize_paddles(gamestate);
+        self.send_power_ups(gamestate);
+    }
+
+    /// Works out every paddle's size from the effects in play
+    fn resize_paddles(&self, gamestate: &mut GameState) {
+        for player_idx in gamestate.player_ids() {
+            let mut paddle_size = gamestate.paddle_size;
+            if self.has_effect(player_idx, PowerUp::BigPaddle) {
+                paddle_size += SIZE_CHANGE;
+            }
+            if self.effects.iter().any(|effect| {
+                effect.power_up == PowerUp::ShrinkOpponent && effect.player_idx != player_idx
+            }) {
+                paddle_size = paddle_size.saturating_sub(SIZE_CHANGE);
+            }
+            gamestate.player_mut(player_idx).paddle_size = paddle_size;
+        }
+        gamestate.clamp_paddles();
+    }
+
+    /// Keeps a held ball just in front of the paddle holding it, then sends it back
+    fn hold_ball(&mut self, gamestate: &mut GameState, ball_idx: usize, player_idx: u8) {
+        let player = gamestate.player(player_idx);
+        let (x, dx) = if player.seat.side == Side::Left {
+            (player.seat.line + 1, 1)
+        } else {
+            (player.seat.line - 1, -1)
+        };
+        let y = player.player_pos;
+        let dy = *[-1, 0, 1].choose(&mut gamestate.rng).unwrap();
+
+        let ball = &mut gamestate.balls[ball_idx];
+        (ball.x, ball.y) = (x, y);
+        self.held = match self.held {
+            Some((_, ticks_left)) if ticks_left > 1 => Some((player_idx, ticks_left - 1)),
+            _ => {
+                (ball.dx, ball.dy) = (dx, dy);
+                None
+            }
+        };
+    }
+
+    /// `[id, pickup count, (power up, x, y).., player count, (paddle size, effects)..]`,
+    /// with a bit set in `effects` for every power up the player has going
+    fn power_ups_packet(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        let mut v_data: Vec<u8> = vec![ServerPacket::PowerUps as u8];
+        v_data.extend_from_slice(&self.power_up_bytes(gamestate, player_idx));
+        v_data
+    }
+
+    fn power_up_bytes(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        let mirrored = gamestate.mirrored_for(player_idx);
+        let mut v_data: Vec<u8> = vec![self.pickups.len() as u8];
+        for pickup in &self.pickups {
+            let x = if mirrored {
+                gamestate.map_width - pickup.x - 1
+            } else {
+                pickup.x
+            };
+            v_data.extend_from_slice(&[pickup.power_up as u8, x, pickup.y]);
+        }
+        v_data.push(gamestate.players.len() as u8);
+        for (idx, player) in (1..).zip(&gamestate.players) {
+            let effects = self
+                .effects
+                .iter()
+                .filter(|effect| effect.player_idx == idx)
+                .fold(0u8, |bits, effect| bits | 1 << effect.power_up as u8);
+            v_data.extend_from_slice(&[player.paddle_size, effects]);
+        }
+        v_data
+    }
+
+    /// Everyone gets told whenever a pickup or an effect comes or goes, in their own view
+    fn send_power_ups(&self, gamestate: &mut GameState) {
+        for player_idx in gamestate.player_ids() {
+            let packet = self.power_ups_packet(gamestate, player_idx);
+            gamestate.player_mut(player_idx).send_bytes(&packet);
+        }
+        let packet = self.power_ups_packet(gamestate, 0);
+        gamestate.send_to_spectators(&packet);
+    }
+}
+
+impl Gamemode for PowerUpsGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::PowerUps
+    }
+
+    fn on_tick(&mut self, gamestate: &mut GameState) -> Option<u8> {
+        self.ticks_since_pickup += 1;
+        if self.ticks_since_pickup >= PICKUP_INTERVAL {
+            self.spawn_pickup(gamestate);
+        }
+
+        let effects = self.effects.len();
+        for effect in self.effects.iter_mut() {
+            effect.ticks_left -= 1;
+        }
+        self.effects.retain(|effect| effect.ticks_left > 0);
+        if self.effects.len() != effects {
+            self.resize_paddles(gamestate);
+            self.send_power_ups(gamestate);
+        }
+        None
+    }
+
+    /// A shield sends the ball back out of the goal once, anything else scores as usual
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, goal: Side) {
+        let defender = gamestate
+            .player_ids()
+            .find(|player_idx| gamestate.player(*player_idx).seat.side == goal);
+        if let Some(defender) = defender
+            && self.has_effect(defender, PowerUp::Shield)
+        {
+            info!(
+                "{} was saved by a shield",
+                gamestate.player(defender).player_name
+            );
+            self.effects.retain(|effect| {
+                effect.player_idx != defender || effect.power_up != PowerUp::Shield
+            });
+            let ball = &mut gamestate.balls[ball_idx];
+            ball.dx = -ball.dx;
+            ball.x = (ball.x as i16 + ball.dx as i16) as u8;
+            self.send_power_ups(gamestate);
+            return;
+        }
+
+        gamestate.score_against(goal);
+        gamestate.reset_ball_to_center(ball_idx);
+        self.last_hit = None;
+        self.held = None;
+    }
+
+    fn ball_steps(&mut self, gamestate: &mut GameState) -> u8 {
+        if self
+            .effects
+            .iter()
+            .any(|effect| effect.power_up == PowerUp::FastBall)
+        {
+            gamestate.ball_speed
+        } else {
+            gamestate.ball_steps()
+        }
+    }
+
+    fn step_ball(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        if let Some((player_idx, _)) = self.held {
+            self.hold_ball(gamestate, ball_idx, player_idx);
+            return;
+        }
+        if let Some(player_idx) = gamestate.move_ball(ball_idx) {
+            self.last_hit = Some(player_idx);
+            if self.has_effect(player_idx, PowerUp::StickyPaddle) {
+                self.held = Some((player_idx, HOLD_STEPS));
+            }
+        }
+        self.collect_pickup(gamestate, ball_idx);
+    }
+
+    /// Pickups and effects go after the paddles, as in the power ups packet
+    fn render_snapshot(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        let mut snapshot_packet = gamestate.snapshot_packet(self.game_type(), player_idx);
+        snapshot_packet.extend_from_slice(&self.power_up_bytes(gamestate, player_idx));
+        snapshot_packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::gamemode::harness::{Harness, assert_invariants, random_inputs};
+
+    #[test]
+    fn change_paddles_and_show_up_mirrored() {
+        let mut effects_seen = false;
+        for seed in 0..10 {
+            let mut harness = Harness::with_mode(Box::new(PowerUpsGame::default()), seed);
+            let mut input_rng = StdRng::seed_from_u64(seed);
+            for tick in 0..5_000 {
+                random_inputs(&mut harness, &mut input_rng);
+                let before = harness.frame();
+                assert_eq!(harness.tick(), None, "seed {}: game ended by itself", seed);
+                assert_invariants(&before, &harness.frame(), harness.gamestate(), tick);
+
+                let power_ups = |packets: Vec<Vec<u8>>| {
+                    packets
+                        .into_iter()
+                        .rfind(|packet| packet[0] == ServerPacket::PowerUps as u8)
+                };
+                let map_width = harness.gamestate().map_width;
+                let sizes: Vec<u8> = harness
+                    .gamestate()
+                    .players
+                    .iter()
+                    .map(|player| player.paddle_size)
+                    .collect();
+                let packets = (
+                    power_ups(harness.received(1)),
+                    power_ups(harness.received(2)),
+                );
+                let (Some(packet_1), Some(packet_2)) = packets else {
+                    continue;
+                };
+                // [id, pickup count, (power up, x, y).., player count, (paddle size, effects)..]
+                let pickups = packet_1[1] as usize;
+                let (pickups_1, players_1) = packet_1[2..].split_at(pickups * 3);
+                let (pickups_2, players_2) = packet_2[2..].split_at(pickups * 3);
+                for (pickup_1, pickup_2) in pickups_1.chunks_exact(3).zip(pickups_2.chunks_exact(3))
+                {
+                    assert_eq!(pickup_1[0], pickup_2[0]);
+                    assert_eq!(pickup_1[1], map_width - pickup_2[1] - 1);
+                    assert_eq!(pickup_1[2], pickup_2[2]);
+                }
+                assert_eq!(players_1, players_2);
+                assert_eq!(players_1[0], 2);
+                let packet_sizes: Vec<u8> = players_1[1..].iter().step_by(2).copied().collect();
+                assert_eq!(packet_sizes, sizes);
+                effects_seen |= players_1[1..].chunks_exact(2).any(|player| player[1] != 0);
+            }
+        }
+        assert!(effects_seen, "nobody ever picked anything up");
+    }
+}
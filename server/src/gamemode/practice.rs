@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use rand::seq::IndexedRandom;
+
+use crate::{
+    gamemode::{
+        gamemode::{GameResult, GameState, Gamemode, Gamemodes, Side},
+        modes::GameContext,
+    },
+    models::{
+        packet::{GameOverReason, ServerPacket},
+        player::NewPlayer,
+        rules::MatchRules,
+        transport::NullTransport,
+    },
+    registry::GameSession,
+    stats::StatsStore,
+};
+
+/// Warm up mode, one player against a solid wall. Every return adds to a streak
+/// and a miss starts it again from zero.
+#[derive(Debug, Default)]
+pub struct PracticeGame {
+    streak: u32,
+    best_streak: u32,
+    personal_best: u32,
+    stats: Option<Arc<StatsStore>>,
+}
+
+impl PracticeGame {
+    pub fn launch(players: Vec<NewPlayer>, rules: &MatchRules, context: &GameContext) {
+        let [player]: [NewPlayer; 1] = players.try_into().unwrap();
+        info!("Starting practice for {:?}", player.player_name);
+        let player_name = player.player_name.clone().unwrap_or_default();
+        let session = context.game_registry.create_session(&[&player]);
+        let gamestate = Self::setup_game(player, rules, session, rand::random());
+        let mut practice = PracticeGame::default();
+        practice.track_personal_best(Arc::clone(&context.stats), &player_name);
+        context.run_game(Box::new(practice), gamestate);
+    }
+
+    /// An empty map with the wall in player 2's seat, and the first ball heading for
+    /// the player
+    pub fn setup_game(
+        player: NewPlayer,
+        rules: &MatchRules,
+        session: GameSession,
+        seed: u64,
+    ) -> GameState {
+        let mut gamestate = GameState::new(player, Self::wall(), rules, session, seed);
+        gamestate.balls[0].dx = -1;
+        gamestate
+    }
+
+    /// Stands in as player 2, it never moves and never says anything
+    fn wall() -> NewPlayer {
+        let mut wall = NewPlayer::new(Gamemodes::Practice, Box::new(NullTransport));
+        wall.player_name = Some("Wall".to_string());
+        wall
+    }
+
+    /// Starts from the player's personal best, and saves it again once the game is over
+    pub fn track_personal_best(&mut self, stats: Arc<StatsStore>, player_name: &str) {
+        self.personal_best = stats.practice_best(player_name);
+        self.stats = Some(stats);
+    }
+
+    /// `[streak, best this game, personal best]`, each capped to fit a byte
+    fn score_bytes(&self) -> [u8; 3] {
+        [self.streak, self.best_streak, self.personal_best].map(|score| score.min(255) as u8)
+    }
+
+    /// The streak and best stand in for the two scores, so updates, replays
+    /// and the game list show them too
+    fn send_score(&self, gamestate: &mut GameState) {
+        let [streak, best_streak, _] = self.score_bytes();
+        gamestate.scores = vec![streak, best_streak];
+        gamestate.session.report_scores(&gamestate.scores);
+
+        let mut score_packet = vec![ServerPacket::PracticeScore as u8];
+        score_packet.extend_from_slice(&self.score_bytes());
+        gamestate.broadcast(&score_packet);
+    }
+
+    fn ball_returned(&mut self, gamestate: &mut GameState) {
+        self.streak += 1;
+        self.best_streak = self.best_streak.max(self.streak);
+        self.personal_best = self.personal_best.max(self.streak);
+        self.send_score(gamestate);
+    }
+}
+
+impl Gamemode for PracticeGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::Practice
+    }
+
+    /// Only the player's side has a goal, a miss ends the streak
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, _goal: Side) {
+        info!(
+            "{} missed after {} returns",
+            gamestate.player(1).player_name,
+            self.streak
+        );
+        self.streak = 0;
+        self.send_score(gamestate);
+        gamestate.reset_ball_to_center(ball_idx);
+    }
+
+    /// The wall takes up the column the other paddle would be in
+    fn step_ball(&mut self, gamestate: &mut GameState, ball_idx: usize) {
+        let mut ball = gamestate.balls[ball_idx];
+        let (mut new_x, mut new_y) = ball.next_pos();
+
+        let mut collision_detected = false;
+        if new_y == 0 || new_y == gamestate.map_height - 1 {
+            ball.dy = -ball.dy;
+            collision_detected = true;
+        }
+        if new_x == gamestate.map_width - 3 {
+            ball.dx = -ball.dx;
+            collision_detected = true;
+        } else if gamestate.paddle_hit(&ball, new_x, new_y) == Some(1) {
+            ball.dy = *[-1, 0, 1].choose(&mut gamestate.rng).unwrap();
+            ball.dx = -ball.dx;
+            collision_detected = true;
+            self.ball_returned(gamestate);
+        }
+        if collision_detected {
+            (new_x, new_y) = ball.next_pos();
+        }
+
+        ball.x = new_x;
+        ball.y = new_y;
+        gamestate.balls[ball_idx] = ball;
+    }
+
+    /// Nobody wins practice, the best streak and personal best go out in place of the scores
+    fn on_end(
+        &mut self,
+        gamestate: &mut GameState,
+        _winner: u8,
+        reason: GameOverReason,
+    ) -> GameResult {
+        let player_name = gamestate.player(1).player_name.clone();
+        info!(
+            "Best practice streak for {}: {}",
+            player_name, self.best_streak
+        );
+        if let Some(stats) = &self.stats {
+            match stats.record_practice(&player_name, self.best_streak) {
+                Ok(true) => info!(
+                    "New personal best for {}: {}",
+                    player_name, self.best_streak
+                ),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to save personal best for {}: {:?}", player_name, e),
+            }
+        }
+
+        let [_, best_streak, personal_best] = self.score_bytes();
+        GameResult {
+            winner: 0,
+            scores: vec![best_streak, personal_best],
+            reason,
+        }
+    }
+
+    fn render_snapshot(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        let mut snapshot_packet = gamestate.snapshot_packet(self.game_type(), player_idx);
+        snapshot_packet.extend_from_slice(&self.score_bytes());
+        snapshot_packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gamemode::harness::Harness, models::player::MessageType};
+
+    #[test]
+    fn wall_sends_every_ball_back() {
+        let mut harness = Harness::practice(3);
+        for tick in 0..5_000 {
+            // Following the ball never misses
+            let ball_y = harness.gamestate().balls[0].y;
+            harness.move_paddle(1, ball_y);
+            let before = harness.frame();
+            assert_eq!(harness.tick(), None);
+            let after = harness.frame();
+            assert!(
+                after.ball_x < harness.gamestate().map_width - 3,
+                "tick {}: ball went through the wall at {:?}",
+                tick,
+                (after.ball_x, after.ball_y)
+            );
+            assert!(
+                after.player_1_score >= before.player_1_score,
+                "tick {}: streak ended",
+                tick
+            );
+            harness.received(1);
+        }
+        let frame = harness.frame();
+        assert!(frame.player_1_score > 0);
+        assert_eq!(frame.player_1_score, frame.player_2_score);
+    }
+
+    #[test]
+    fn misses_reset_the_streak() {
+        let mut harness = Harness::practice(3);
+        let mut missed = false;
+        for _ in 0..5_000 {
+            let ball_y = harness.gamestate().balls[0].y;
+            // Follows the ball, then gives up once a streak is going
+            let position = if harness.frame().player_1_score < 3 {
+                ball_y
+            } else {
+                0
+            };
+            harness.move_paddle(1, position);
+            harness.tick();
+            let frame = harness.frame();
+            if frame.player_2_score >= 3 && frame.player_1_score == 0 {
+                missed = true;
+                break;
+            }
+        }
+        assert!(missed, "the streak never reset");
+
+        let score_packets: Vec<Vec<u8>> = harness
+            .received(1)
+            .into_iter()
+            .filter(|packet| packet[0] == ServerPacket::PracticeScore as u8)
+            .collect();
+        assert_eq!(score_packets.last().unwrap()[1..3], [0, 3]);
+    }
+
+    #[test]
+    fn game_over_reports_the_best_streak() {
+        let mut harness = Harness::practice(3);
+        while harness.frame().player_1_score < 2 {
+            let ball_y = harness.gamestate().balls[0].y;
+            harness.move_paddle(1, ball_y);
+            harness.tick();
+        }
+        harness.send(1, &[MessageType::Forfeit as u8, 0]);
+        assert_eq!(harness.tick(), Some(0));
+
+        let game_over = harness.game_over(1);
+        // Nobody wins, then the best streak and personal best
+        assert_eq!(game_over, [4, 0, 2, 2, GameOverReason::Forfeit as u8]);
+    }
+}
@@ -0,0 +1,565 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+
+use crate::{
+    gamemode::gamemode::{GameResult, GameState, Gamemode, TICK_INTERVAL},
+    metrics::{DisconnectCause, GameMetrics, METRICS},
+    models::{
+        packet::{GameOverReason, GameStatus, ServerPacket, message_packet},
+        player::{Player, PlayerCommand, PlayerError},
+        replay::ReplayFrame,
+        spectator::Spectator,
+    },
+    registry::SessionRequest,
+    supervisor,
+};
+
+pub const PLAYER_TIMEOUT: Duration = Duration::from_millis(1);
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a pause lasts when only one player asked for it
+pub const PAUSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a pause can last once a second player has agreed to it
+pub const AGREED_PAUSE_TIMEOUT: Duration = Duration::from_secs(60 * 4);
+pub const RESUME_COUNTDOWN: u8 = 3;
+
+/// How the game carries on after something held it up, a pause or a request from outside
+#[derive(Debug, PartialEq)]
+pub enum PauseOutcome {
+    Resumed,
+    Forfeited(u8),
+    Abandoned(u8),
+    Kicked(u8),
+    Ended,
+}
+
+/// Plays out a game of any mode. Talking to the players and spectators, pauses and
+/// reconnects are all handled here, the mode only decides how the game plays.
+#[derive(Debug)]
+pub struct GameRunner {
+    pub mode: Box<dyn Gamemode>,
+    pub gamestate: GameState,
+}
+
+impl GameRunner {
+    pub fn new(mode: Box<dyn Gamemode>, mut gamestate: GameState) -> Self {
+        for player in gamestate.players.iter_mut() {
+            Self::prepare_player_stream(player);
+        }
+        gamestate.session.report_scores(&gamestate.scores);
+        Self { mode, gamestate }
+    }
+
+    pub fn start_game(&mut self) -> i32 {
+        let players: Vec<String> = self
+            .gamestate
+            .players
+            .iter()
+            .map(|player| player.to_string())
+            .collect();
+        info!(
+            "Starting {:?} game {}: {} with seed {}",
+            self.mode.game_type(),
+            self.gamestate.session.game_id,
+            players.join(" vs "),
+            self.gamestate.seed
+        );
+
+        debug!("Sending game snapshot");
+        for player_idx in self.gamestate.player_ids() {
+            self.send_snapshot(player_idx);
+        }
+        let mut game_metrics = GameMetrics::new(&METRICS, self.gamestate.session.game_id);
+        loop {
+            let tick_start = Instant::now();
+            let result = self.tick();
+            game_metrics.record_tick(tick_start.elapsed());
+            if let Some(result) = result {
+                return result;
+            }
+            thread::sleep(TICK_INTERVAL);
+        }
+    }
+
+    /// Same as `start_game`, only a panic takes nothing but this game down. Everyone in it
+    /// is told it crashed and nobody wins, the mode isn't asked since it may be what broke.
+    pub fn start_supervised(&mut self) -> i32 {
+        let payload = match panic::catch_unwind(AssertUnwindSafe(|| self.start_game())) {
+            Ok(result) => return result,
+            Err(payload) => payload,
+        };
+        let players: Vec<&str> = self
+            .gamestate
+            .players
+            .iter()
+            .map(|player| player.player_name.as_str())
+            .collect();
+        error!(
+            "{:?} game {} ({}) crashed at {:?}: {}",
+            self.mode.game_type(),
+            self.gamestate.session.game_id,
+            players.join(" vs "),
+            self.gamestate.scores,
+            supervisor::panic_message(&*payload)
+        );
+        let result = GameResult {
+            winner: 0,
+            scores: self.gamestate.scores.clone(),
+            reason: GameOverReason::Crashed,
+        };
+        self.gamestate.broadcast(&result.as_bytes());
+        0
+    }
+
+    /// Runs a single step of the game, returns the result once the game is over
+    pub fn tick(&mut self) -> Option<i32> {
+        if let Some(outcome) = self.handle_session_requests() {
+            return self.finish(outcome);
+        }
+        self.update_spectators();
+        debug!("Sending snapshot");
+        self.send_update();
+
+        let update_result = self.update_player_location();
+        match update_result {
+            Ok(_) => {
+                if let Some(result) = self.handle_player_commands() {
+                    return Some(result);
+                }
+                if let Some(winner) = self.mode.on_tick(&mut self.gamestate) {
+                    return Some(self.end_game(winner, GameOverReason::Finished));
+                }
+                let steps = self.mode.ball_steps(&mut self.gamestate);
+                for _ in 0..steps {
+                    // Goals are checked before every step, or a ball could skip past one.
+                    // Backwards, since a mode may take a ball out of play when it scores.
+                    for ball_idx in (0..self.gamestate.balls.len()).rev() {
+                        let ball = self.gamestate.balls[ball_idx];
+                        if let Some(goal) = self.mode.goal_scored(&self.gamestate, &ball) {
+                            self.mode.on_goal(&mut self.gamestate, ball_idx, goal);
+                        }
+                    }
+                    for ball_idx in 0..self.gamestate.balls.len() {
+                        self.mode.step_ball(&mut self.gamestate, ball_idx);
+                    }
+                }
+                self.record_frame();
+                self.print_game_state();
+            }
+            Err(e) => match e {
+                PlayerError::Io(error) => warn!("Player IO Error {:?}", error),
+                PlayerError::PlayerLeft => {
+                    let player_idx = self.gamestate.players_left()[0];
+                    return Some(self.player_quit(player_idx));
+                }
+                PlayerError::PlayerDisconnected => {
+                    if let Some(outcome) = self.wait_for_reconnect() {
+                        return self.finish(outcome);
+                    }
+                }
+                PlayerError::UndefinedPacket(n) => warn!("Undefined Packet Number: {}", n),
+            },
+        }
+        None
+    }
+
+    /// Snapshot from the point of view of one player, everyone on the left or right sees
+    /// themselves on the left
+    fn send_snapshot(&mut self, player_idx: u8) {
+        let snapshot_packet = self.mode.render_snapshot(&self.gamestate, player_idx);
+        self.gamestate
+            .player_mut(player_idx)
+            .send_bytes(&snapshot_packet);
+    }
+
+    /// Spectators get player 1's point of view, with a player id of 0
+    fn send_spectator_snapshot(&self, spectator: &mut Spectator) {
+        let snapshot_packet = self.mode.render_snapshot(&self.gamestate, 0);
+        spectator.send_bytes(&snapshot_packet);
+    }
+
+    /// Drops spectators that went away or asked to leave
+    fn update_spectators(&mut self) {
+        for spectator in self.gamestate.spectators.iter_mut() {
+            spectator.poll();
+        }
+        self.gamestate.spectators.retain(|spectator| {
+            if !spectator.connected {
+                info!("Spectator {} stopped watching", spectator);
+            }
+            spectator.connected
+        });
+    }
+
+    /// Takes in players coming back and new spectators, handed over by other threads.
+    /// Returns how the game ends if the admin console asked for that.
+    fn handle_session_requests(&mut self) -> Option<PauseOutcome> {
+        while let Ok(request) = self.gamestate.session.request_rx.try_recv() {
+            match request {
+                SessionRequest::Reconnect(new_player) => {
+                    // A player can come back before the old connection is noticed as dead
+                    let player_idx = self.gamestate.player_ids().find(|idx| {
+                        let player = self.gamestate.player(*idx);
+                        !player.left && player.session_token == new_player.session_token
+                    });
+                    match player_idx {
+                        Some(player_idx) => {
+                            let game_id = self.gamestate.session.game_id;
+                            let player = self.gamestate.player_mut(player_idx);
+                            player.reconnect(new_player);
+                            Self::prepare_player_stream(player);
+                            info!("{} rejoined game {}", player.player_name, game_id);
+                            self.send_snapshot(player_idx);
+                        }
+                        None => warn!("Reconnect for a player who already left, ignoring"),
+                    }
+                }
+                SessionRequest::Spectate(mut spectator) => {
+                    spectator.prepare_stream();
+                    info!(
+                        "{} is spectating game {}",
+                        spectator, self.gamestate.session.game_id
+                    );
+                    self.send_spectator_snapshot(&mut spectator);
+                    self.gamestate.spectators.push(spectator);
+                }
+                SessionRequest::Kick(player_name) => {
+                    let player_idx = self.gamestate.player_ids().find(|idx| {
+                        let player = self.gamestate.player(*idx);
+                        !player.left && player.player_name == player_name
+                    });
+                    match player_idx {
+                        Some(player_idx) => return Some(PauseOutcome::Kicked(player_idx)),
+                        None => warn!("Kick for {} who isn't playing, ignoring", player_name),
+                    }
+                }
+                SessionRequest::End => return Some(PauseOutcome::Ended),
+                SessionRequest::Message(text) => self.gamestate.broadcast(&message_packet(&text)),
+            }
+        }
+        None
+    }
+
+    fn send_update(&mut self) {
+        let spectator_packet = self.gamestate.update_packet(0);
+        self.gamestate.send_to_spectators(&spectator_packet);
+        for player_idx in self.gamestate.player_ids() {
+            let update_packet = self.gamestate.update_packet(player_idx);
+            self.gamestate
+                .player_mut(player_idx)
+                .send_bytes(&update_packet);
+        }
+    }
+
+    /// `player_idx` is whoever the status is about, the one who paused or dropped out
+    fn send_status(&mut self, status: GameStatus, seconds_left: u8, player_idx: u8) {
+        let status_packet = [
+            ServerPacket::Status as u8,
+            status as u8,
+            seconds_left,
+            player_idx,
+        ];
+        self.gamestate.broadcast(&status_packet);
+    }
+
+    /// Lets the mode decide what everyone is told, then tells them
+    fn end_game(&mut self, winner: u8, reason: GameOverReason) -> i32 {
+        let result = self.mode.on_end(&mut self.gamestate, winner, reason);
+        info!(
+            "Game {} over ({:?}) {:?}, winner: team {}",
+            self.gamestate.session.game_id, result.reason, result.scores, result.winner
+        );
+        self.gamestate.broadcast(&result.as_bytes());
+        result.winner as i32
+    }
+
+    /// The team that wins once this player is out, if only one other team is left.
+    /// With more than that it is 0 and up to the mode.
+    fn remaining_team(&self, player_idx: u8, connected_only: bool) -> u8 {
+        let out_team = self.gamestate.player(player_idx).seat.team;
+        let mut teams = self
+            .gamestate
+            .players
+            .iter()
+            .filter(|player| player.seat.team != out_team && (player.connected || !connected_only))
+            .map(|player| player.seat.team);
+        match teams.next() {
+            Some(team) if teams.all(|other| other == team) => team,
+            _ => 0,
+        }
+    }
+
+    /// The player isn't coming back, the other team wins if they are still around
+    fn player_quit(&mut self, player_idx: u8) -> i32 {
+        let winner = self.remaining_team(player_idx, true);
+        self.end_game(winner, GameOverReason::Abandoned)
+    }
+
+    /// Same as the player quitting, only the game over says who made them go
+    fn kick(&mut self, player_idx: u8) -> i32 {
+        METRICS.disconnected(DisconnectCause::Kicked);
+        info!(
+            "{} was kicked from game {}",
+            self.gamestate.player(player_idx).player_name,
+            self.gamestate.session.game_id
+        );
+        let winner = self.remaining_team(player_idx, false);
+        self.end_game(winner, GameOverReason::Kicked)
+    }
+
+    /// Ends the game the way the outcome says, `None` if it carries on
+    fn finish(&mut self, outcome: PauseOutcome) -> Option<i32> {
+        match outcome {
+            PauseOutcome::Resumed => None,
+            PauseOutcome::Forfeited(idx) => Some(self.forfeit(idx)),
+            PauseOutcome::Abandoned(idx) => Some(self.player_quit(idx)),
+            PauseOutcome::Kicked(idx) => Some(self.kick(idx)),
+            PauseOutcome::Ended => {
+                info!("Game {} ended by the admin", self.gamestate.session.game_id);
+                Some(self.end_game(0, GameOverReason::Ended))
+            }
+        }
+    }
+
+    fn forfeit(&mut self, player_idx: u8) -> i32 {
+        info!(
+            "{} forfeited",
+            self.gamestate.player(player_idx).player_name
+        );
+        let winner = self.remaining_team(player_idx, false);
+        self.end_game(winner, GameOverReason::Forfeit)
+    }
+
+    /// Acts on pause and forfeit requests the mode let through, returns the result
+    /// if the game ended because of one
+    fn handle_player_commands(&mut self) -> Option<i32> {
+        for (player_idx, command) in self.gamestate.take_commands() {
+            if !self.mode.on_input(&mut self.gamestate, player_idx, command) {
+                continue;
+            }
+            match command {
+                PlayerCommand::Forfeit => return Some(self.forfeit(player_idx)),
+                PlayerCommand::Pause => {
+                    let outcome = self.pause_game(player_idx);
+                    if outcome != PauseOutcome::Resumed {
+                        return self.finish(outcome);
+                    }
+                }
+                PlayerCommand::Unpause => debug!("Unpause while the game isn't paused"),
+            }
+        }
+        None
+    }
+
+    /// Holds the ball still until the pause is lifted or times out. A pause only one player
+    /// asked for is short, if anyone else asks for one too it becomes an agreed pause.
+    fn pause_game(&mut self, requester: u8) -> PauseOutcome {
+        let player = self.gamestate.player_mut(requester);
+        if player.pauses_left == 0 {
+            info!("{} has no pauses left", player.player_name);
+            return PauseOutcome::Resumed;
+        }
+        player.pauses_left -= 1;
+        info!(
+            "Game {} paused by player {}",
+            self.gamestate.session.game_id, requester
+        );
+
+        let mut agreed = false;
+        let mut deadline = Instant::now() + PAUSE_TIMEOUT;
+        let mut last_countdown: Option<u8> = None;
+        'paused: while Instant::now() < deadline {
+            let seconds_left = (deadline - Instant::now()).as_secs().min(u8::MAX as u64) as u8;
+            if last_countdown != Some(seconds_left) {
+                let status = if agreed {
+                    GameStatus::PausedAgreed
+                } else {
+                    GameStatus::Paused
+                };
+                self.send_status(status, seconds_left, requester);
+                last_countdown = Some(seconds_left);
+            }
+
+            if let Some(outcome) = self.check_connections() {
+                return outcome;
+            }
+            for (player_idx, command) in self.gamestate.take_commands() {
+                match command {
+                    PlayerCommand::Forfeit => return PauseOutcome::Forfeited(player_idx),
+                    PlayerCommand::Pause if player_idx != requester && !agreed => {
+                        info!("Player {} agreed to the pause", player_idx);
+                        agreed = true;
+                        deadline = Instant::now() + AGREED_PAUSE_TIMEOUT;
+                        last_countdown = None;
+                    }
+                    PlayerCommand::Unpause if agreed || player_idx == requester => {
+                        break 'paused;
+                    }
+                    _ => {}
+                }
+            }
+            thread::sleep(RECONNECT_POLL_INTERVAL);
+        }
+
+        self.resume_countdown()
+    }
+
+    fn resume_countdown(&mut self) -> PauseOutcome {
+        for seconds_left in (1..=RESUME_COUNTDOWN).rev() {
+            self.send_status(GameStatus::Resuming, seconds_left, 0);
+            let second_end = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < second_end {
+                if let Some(outcome) = self.check_connections() {
+                    return outcome;
+                }
+                if let Some((player_idx, _)) = self
+                    .gamestate
+                    .take_commands()
+                    .into_iter()
+                    .find(|(_, command)| *command == PlayerCommand::Forfeit)
+                {
+                    return PauseOutcome::Forfeited(player_idx);
+                }
+                thread::sleep(RECONNECT_POLL_INTERVAL);
+            }
+        }
+        self.send_status(GameStatus::Running, 0, 0);
+        PauseOutcome::Resumed
+    }
+
+    /// Keeps reading input while the ball is held, and covers anyone dropping out meanwhile
+    fn check_connections(&mut self) -> Option<PauseOutcome> {
+        if let Some(outcome) = self.handle_session_requests() {
+            return Some(outcome);
+        }
+        self.update_spectators();
+        match self.update_player_location() {
+            Err(PlayerError::PlayerLeft) => {
+                Some(PauseOutcome::Abandoned(self.gamestate.players_left()[0]))
+            }
+            Err(PlayerError::PlayerDisconnected) => self.wait_for_reconnect(),
+            _ => None,
+        }
+    }
+
+    /// Reads every player's input, marking anyone whose connection dropped
+    fn update_player_location(&mut self) -> Result<(), PlayerError> {
+        let mut result = Ok(());
+        for player_idx in self.gamestate.player_ids() {
+            let player = self.gamestate.player_mut(player_idx);
+            if !player.connected {
+                continue;
+            }
+            match player.updated_position() {
+                Ok(_) => {}
+                Err(PlayerError::PlayerLeft) => {
+                    info!("{} left the game", player.player_name);
+                    METRICS.disconnected(DisconnectCause::Left);
+                    result = Err(PlayerError::PlayerLeft);
+                }
+                Err(PlayerError::PlayerDisconnected) => {
+                    warn!("{} disconnected", player.player_name);
+                    METRICS.disconnected(DisconnectCause::ConnectionLost);
+                    player.connected = false;
+                    if !matches!(result, Err(PlayerError::PlayerLeft)) {
+                        result = Err(PlayerError::PlayerDisconnected);
+                    }
+                }
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        self.gamestate.clamp_paddles();
+        result
+    }
+
+    /// Pauses the game until every dropped player is back, or the countdown runs out.
+    /// Returns how the game ends if it can't carry on.
+    fn wait_for_reconnect(&mut self) -> Option<PauseOutcome> {
+        let deadline = Instant::now() + self.gamestate.reconnect_timeout;
+        info!(
+            "Game {} paused, waiting for players {:?}",
+            self.gamestate.session.game_id,
+            self.gamestate.disconnected_players()
+        );
+
+        let mut last_countdown: Option<u8> = None;
+        while Instant::now() < deadline {
+            let seconds_left = (deadline - Instant::now()).as_secs() as u8;
+            if last_countdown != Some(seconds_left) {
+                let player_idx = match self.gamestate.disconnected_players().as_slice() {
+                    [player_idx] => *player_idx,
+                    _ => 0,
+                };
+                self.send_status(GameStatus::WaitingForReconnect, seconds_left, player_idx);
+                last_countdown = Some(seconds_left);
+            }
+
+            if let Some(outcome) = self.handle_session_requests() {
+                return Some(outcome);
+            }
+            self.update_spectators();
+
+            // Anyone still here could have left in the meantime
+            if let Err(PlayerError::PlayerLeft) = self.update_player_location() {
+                break;
+            }
+            if self.gamestate.disconnected_players().is_empty() {
+                self.send_status(GameStatus::Running, 0, 0);
+                return None;
+            }
+            thread::sleep(RECONNECT_POLL_INTERVAL);
+        }
+        let player_idx = self
+            .gamestate
+            .players_left()
+            .into_iter()
+            .chain(self.gamestate.disconnected_players())
+            .next()
+            .unwrap();
+        Some(PauseOutcome::Abandoned(player_idx))
+    }
+
+    fn prepare_player_stream(player: &mut Player) {
+        if let Err(e) = player.transport.set_read_timeout(Some(PLAYER_TIMEOUT)) {
+            warn!(
+                "Failed to set read timeout for {}: {:?}",
+                player.player_name, e
+            );
+        }
+    }
+
+    /// Adds the current tick to the replay, recording stops for good if the file can't be written
+    fn record_frame(&mut self) {
+        let frame = ReplayFrame::from_state(&self.gamestate);
+        if let Some(replay) = &mut self.gamestate.replay
+            && let Err(e) = replay.record(frame)
+        {
+            warn!(
+                "Stopped recording game {}: {:?}",
+                self.gamestate.session.game_id, e
+            );
+            self.gamestate.replay = None;
+        }
+    }
+
+    fn print_game_state(&self) {
+        let gamestate = &self.gamestate;
+        let positions: Vec<u8> = gamestate
+            .players
+            .iter()
+            .map(|player| player.player_pos)
+            .collect();
+        debug!(
+            "{:?} Balls: {:?} Paddles: {:?} Map Width: {} Map Height {}",
+            gamestate.scores, gamestate.balls, positions, gamestate.map_width, gamestate.map_height
+        )
+    }
+}
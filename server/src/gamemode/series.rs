@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+
+use crate::{
+    gamemode::gamemode::{GameResult, GameState, Gamemode, Gamemodes, Side},
+    models::{
+        packet::{GameOverReason, ServerPacket},
+        rules::MatchRules,
+    },
+    stats::StatsStore,
+};
+
+/// Standard games played back to back without anyone reconnecting, whoever wins most of
+/// them takes the series. Each game goes to the rules' points and the players swap ends
+/// before the next one.
+#[derive(Debug)]
+pub struct SeriesGame {
+    best_of: u8,
+    game_points: u8,
+    /// Games won, one per team
+    wins: [u8; 2],
+    stats: Option<Arc<StatsStore>>,
+}
+
+impl SeriesGame {
+    pub fn new(rules: &MatchRules) -> Self {
+        Self {
+            best_of: rules.best_of,
+            game_points: rules.game_points,
+            wins: [0; 2],
+            stats: None,
+        }
+    }
+
+    /// What the series queue plays unless the rules file says otherwise
+    pub fn default_rules() -> MatchRules {
+        MatchRules {
+            best_of: 3,
+            ..MatchRules::default()
+        }
+    }
+
+    /// The series result goes into every player's record once it's over
+    pub fn with_stats(mut self, stats: Arc<StatsStore>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    fn wins_needed(&self) -> u8 {
+        self.best_of / 2 + 1
+    }
+
+    /// `[best of, points to win a game, games won 1, games won 2, points 1, points 2]`
+    fn series_bytes(&self, gamestate: &GameState) -> [u8; 6] {
+        [
+            self.best_of,
+            self.game_points,
+            self.wins[0],
+            self.wins[1],
+            gamestate.scores.first().copied().unwrap_or(0),
+            gamestate.scores.get(1).copied().unwrap_or(0),
+        ]
+    }
+
+    fn send_series(&self, gamestate: &mut GameState) {
+        let mut series_packet = vec![ServerPacket::Series as u8];
+        series_packet.extend_from_slice(&self.series_bytes(gamestate));
+        gamestate.broadcast(&series_packet);
+    }
+
+    /// Swaps ends, puts everything back in the middle and sends everyone the new map
+    fn next_game(&mut self, gamestate: &mut GameState) {
+        let [first, second] = [0, 1].map(|idx| gamestate.players[idx].seat);
+        for (player, seat) in gamestate.players.iter_mut().zip([second, first]) {
+            player.seat.side = seat.side;
+            player.seat.line = seat.line;
+            player.player_pos = gamestate.map_height / 2;
+        }
+        gamestate.ends_swapped = !gamestate.ends_swapped;
+        gamestate.scores = vec![0; 2];
+        gamestate.session.report_scores(&gamestate.scores);
+        gamestate.balls = vec![gamestate.center_ball()];
+        info!(
+            "Game {} of the series in game {}, {}:{}",
+            self.wins[0] + self.wins[1] + 1,
+            gamestate.session.game_id,
+            self.wins[0],
+            self.wins[1]
+        );
+
+        for player_idx in gamestate.player_ids() {
+            let snapshot_packet = self.render_snapshot(gamestate, player_idx);
+            gamestate
+                .player_mut(player_idx)
+                .send_bytes(&snapshot_packet);
+        }
+        let spectator_packet = self.render_snapshot(gamestate, 0);
+        gamestate.send_to_spectators(&spectator_packet);
+    }
+
+    fn record_result(&self, gamestate: &GameState, winner: u8) {
+        let Some(stats) = &self.stats else {
+            return;
+        };
+        let (Some(winner), Some(loser)) = (
+            gamestate
+                .players
+                .iter()
+                .find(|player| player.seat.team == winner),
+            gamestate
+                .players
+                .iter()
+                .find(|player| player.seat.team != winner),
+        ) else {
+            return;
+        };
+        // Bots don't log in, so they don't get a record
+        if winner.session_token.is_none() || loser.session_token.is_none() {
+            return;
+        }
+        if let Err(e) = stats.record_series(&winner.player_name, &loser.player_name) {
+            warn!("Failed to save the series result: {:?}", e);
+        }
+    }
+}
+
+impl Gamemode for SeriesGame {
+    fn game_type(&self) -> Gamemodes {
+        Gamemodes::Series
+    }
+
+    /// A game is over once someone has the points, the series once they have the games
+    fn on_tick(&mut self, gamestate: &mut GameState) -> Option<u8> {
+        let team = gamestate
+            .scores
+            .iter()
+            .position(|score| *score >= self.game_points)? as u8
+            + 1;
+        self.wins[team as usize - 1] += 1;
+        if self.wins[team as usize - 1] >= self.wins_needed() {
+            return Some(team);
+        }
+        self.next_game(gamestate);
+        None
+    }
+
+    fn on_goal(&mut self, gamestate: &mut GameState, ball_idx: usize, goal: Side) {
+        gamestate.score_against(goal);
+        gamestate.reset_ball_to_center(ball_idx);
+        self.send_series(gamestate);
+    }
+
+    /// The games won stand in for the scores, forfeiting gives up the whole series
+    fn on_end(
+        &mut self,
+        gamestate: &mut GameState,
+        winner: u8,
+        reason: GameOverReason,
+    ) -> GameResult {
+        if winner != 0 {
+            self.record_result(gamestate, winner);
+        }
+        GameResult {
+            winner,
+            scores: self.wins.to_vec(),
+            reason,
+        }
+    }
+
+    fn render_snapshot(&self, gamestate: &GameState, player_idx: u8) -> Vec<u8> {
+        let mut snapshot_packet = gamestate.snapshot_packet(self.game_type(), player_idx);
+        snapshot_packet.extend_from_slice(&self.series_bytes(gamestate));
+        snapshot_packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemode::harness::{Harness, TempFile};
+
+    #[test]
+    fn swap_ends_between_games_and_end_with_enough_wins() {
+        let rules = MatchRules {
+            best_of: 3,
+            game_points: 2,
+            ..MatchRules::default()
+        };
+        let stats_path = TempFile::new("series");
+        let stats = Arc::new(StatsStore::load(&stats_path.0).unwrap());
+        let series = SeriesGame::new(&rules).with_stats(Arc::clone(&stats));
+        let mut harness = Harness::with_rules(Box::new(series), &rules, 5);
+        // Bots don't get a record, logged in players do
+        for (idx, player) in harness.runner.gamestate.players.iter_mut().enumerate() {
+            player.session_token = Some([idx as u8; 16]);
+        }
+        harness.received(1);
+
+        harness.runner.gamestate.scores = vec![2, 0];
+        assert_eq!(harness.tick(), None);
+        let gamestate = harness.gamestate();
+        assert_eq!(gamestate.scores, [0, 0]);
+        assert_eq!(gamestate.player(1).seat.side, Side::Right);
+        assert_eq!(gamestate.player(2).seat.side, Side::Left);
+        assert_eq!(gamestate.player(2).seat.line, 2);
+        // Replays need to know to draw the paddles the other way round
+        assert!(harness.frame().ends_swapped);
+        // Everyone gets the new map, and sees themselves at the other end of it
+        let map_width = harness.gamestate().map_width;
+        for (player_idx, own_paddle) in [(1, 19..24), (2, 24..29)] {
+            let snapshot = harness
+                .received(player_idx)
+                .into_iter()
+                .find(|packet| packet[0] == ServerPacket::Snapshot as u8)
+                .expect("no snapshot for the next game");
+            assert_eq!(snapshot[17], Gamemodes::Series as u8);
+            assert_eq!(snapshot[snapshot.len() - 6..], [3, 2, 1, 0, 0, 0]);
+            assert_eq!(
+                snapshot[own_paddle][..4],
+                [player_idx, player_idx, Side::Right as u8, map_width - 3]
+            );
+        }
+        // The ball is drawn where it is for player 1, and flipped for player 2 as before
+        harness.runner.gamestate.balls[0].x = 10;
+        harness.tick();
+        let ball_x = |packets: Vec<Vec<u8>>| {
+            packets
+                .into_iter()
+                .rfind(|packet| packet[0] == ServerPacket::Update as u8)
+                .expect("no update")[4]
+        };
+        let ball_1 = ball_x(harness.received(1));
+        let ball_2 = ball_x(harness.received(2));
+        assert_eq!(ball_1 + ball_2, map_width - 1);
+        assert!(ball_1 < map_width / 2);
+
+        harness.runner.gamestate.scores = vec![0, 2];
+        assert_eq!(harness.tick(), None);
+        assert_eq!(harness.gamestate().player(1).seat.side, Side::Left);
+        harness.runner.gamestate.scores = vec![2, 1];
+        assert_eq!(harness.tick(), Some(1));
+        let game_over = harness.game_over(2);
+        // The games won stand in for the scores
+        assert_eq!(game_over[..5], [ServerPacket::GameOver as u8, 1, 2, 1, 0]);
+
+        let alice = stats.player("alice");
+        assert_eq!((alice.series_won, alice.series_lost), (1, 0));
+        let reloaded = StatsStore::load(&stats_path.0).unwrap();
+        assert_eq!(reloaded.player("bobby").series_lost, 1);
+    }
+}
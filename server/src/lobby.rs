@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use log::{info, warn};
+use rand::seq::IndexedRandom;
+
+use crate::models::{
+    packet::ServerPacket,
+    player::{MessageType, NewPlayer, PlayerMessage},
+    rules::MatchRules,
+};
+
+/// Left out anything that is easy to mix up when read out loud, like 0 and O
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 5;
+/// Lobbies are for two, the host and a friend
+const LOBBY_SIZE: usize = 2;
+
+/// What a player asked for before logging in, instead of going into the public queue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LobbyRequest {
+    Create,
+    Join(String),
+}
+
+/// Sent with every lobby packet, only `Open` and `RulesRejected` come with the lobby
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LobbyStatus {
+    Open = 0,
+    NotFound = 1,
+    Full = 2,
+    /// The host left, taking the lobby with them
+    Closed = 3,
+    /// The host asked for rules outside the server's limits, the old ones still stand
+    RulesRejected = 4,
+}
+
+#[derive(Debug)]
+struct Member {
+    player: NewPlayer,
+    ready: bool,
+}
+
+/// A private game waiting to start, the first member is the host
+#[derive(Debug)]
+struct Lobby {
+    code: String,
+    members: Vec<Member>,
+    rules: MatchRules,
+}
+
+impl Lobby {
+    /// `[id, status, code length, code.., width, height, paddle size, ball speed, best of,
+    /// member count, (ready, name length, name..)..]`, host first
+    fn state_packet(&self, status: LobbyStatus) -> Vec<u8> {
+        let mut v_data: Vec<u8> = vec![
+            ServerPacket::Lobby as u8,
+            status as u8,
+            self.code.len() as u8,
+        ];
+        v_data.extend_from_slice(self.code.as_bytes());
+        v_data.extend_from_slice(&[
+            self.rules.map_width,
+            self.rules.map_height,
+            self.rules.paddle_size,
+            self.rules.ball_speed,
+            self.rules.best_of,
+            self.members.len() as u8,
+        ]);
+        for member in &self.members {
+            let name = member.player.player_name.as_deref().unwrap_or_default();
+            v_data.extend_from_slice(&[member.ready as u8, name.len() as u8]);
+            v_data.extend_from_slice(name.as_bytes());
+        }
+        v_data
+    }
+
+    fn send_state(&mut self) {
+        let packet = self.state_packet(LobbyStatus::Open);
+        for member in self.members.iter_mut() {
+            let _ = member.player.transport.send(&packet);
+        }
+    }
+
+    /// Reads everything a member sent since the last poll. Returns false once they have
+    /// left, one way or another.
+    fn handle_messages(&mut self, member_idx: usize) -> bool {
+        let transport = &mut self.members[member_idx].player.transport;
+        if transport.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut changed = false;
+        let stayed = loop {
+            let frame = match self.members[member_idx].player.transport.receive() {
+                Ok(Some(frame)) if !frame.is_empty() => frame,
+                Ok(Some(_)) => continue,
+                Ok(None) => break true,
+                Err(_) => break false,
+            };
+            match PlayerMessage::decode_message(frame[0]) {
+                MessageType::Ready => {
+                    self.members[member_idx].ready = frame.get(1).is_some_and(|ready| *ready != 0);
+                    changed = true;
+                }
+                // Only the host picks the rules, and everyone has to agree to them again
+                MessageType::LobbyRules if member_idx == 0 => {
+                    let [map_width, map_height, paddle_size, ball_speed, best_of] = frame[1..]
+                    else {
+                        continue;
+                    };
+                    let rules = MatchRules {
+                        map_width,
+                        map_height,
+                        paddle_size,
+                        ball_speed,
+                        best_of,
+                        ..self.rules
+                    };
+                    if rules.validate().is_err() {
+                        let packet = self.state_packet(LobbyStatus::RulesRejected);
+                        let _ = self.members[0].player.transport.send(&packet);
+                        continue;
+                    }
+                    self.rules = rules;
+                    for member in self.members.iter_mut() {
+                        member.ready = false;
+                    }
+                    changed = true;
+                }
+                MessageType::Shutdown => {
+                    let transport = &mut self.members[member_idx].player.transport;
+                    let _ = transport.send(&[ServerPacket::ShutdownAck as u8]);
+                    break false;
+                }
+                _ => warn!("Unexpected message {} in lobby {}", frame[0], self.code),
+            }
+        };
+        let _ = self.members[member_idx]
+            .player
+            .transport
+            .set_nonblocking(false);
+        if changed && stayed {
+            self.send_state();
+        }
+        stayed
+    }
+
+    /// Takes a member out, returns them along with whether the lobby is still open.
+    /// The host leaving closes it for everyone.
+    fn remove_member(&mut self, member_idx: usize) -> (NewPlayer, bool) {
+        let member = self.members.remove(member_idx);
+        info!("{:?} left lobby {}", member.player.player_name, self.code);
+        if member_idx == 0 {
+            for member in self.members.iter_mut() {
+                let packet = [ServerPacket::Lobby as u8, LobbyStatus::Closed as u8];
+                let _ = member.player.transport.send(&packet);
+            }
+            info!("Lobby {} closed", self.code);
+            return (member.player, false);
+        }
+        // Whoever is left waits for someone else to turn up
+        for member in self.members.iter_mut() {
+            member.ready = false;
+        }
+        self.send_state();
+        (member.player, true)
+    }
+}
+
+/// Every private lobby on the server, looked up by join code
+#[derive(Debug, Default)]
+pub struct Lobbies {
+    lobbies: HashMap<String, Lobby>,
+}
+
+impl Lobbies {
+    pub fn new() -> Self {
+        Self {
+            lobbies: HashMap::new(),
+        }
+    }
+
+    /// Puts the player in a lobby of their own, or the one they have the code for
+    pub fn handle_request(&mut self, player: NewPlayer, rules: &MatchRules) {
+        match player.lobby.clone() {
+            Some(LobbyRequest::Join(code)) => self.join(&code, player),
+            _ => self.open(player, rules),
+        }
+    }
+
+    /// Starts with the server's own rules, the host changes them from there
+    fn open(&mut self, host: NewPlayer, rules: &MatchRules) {
+        let code = loop {
+            let code: String = (0..CODE_LENGTH)
+                .map(|_| *CODE_ALPHABET.choose(&mut rand::rng()).unwrap() as char)
+                .collect();
+            if !self.lobbies.contains_key(&code) {
+                break code;
+            }
+        };
+        info!("{:?} opened lobby {}", host.player_name, code);
+        let mut lobby = Lobby {
+            code: code.clone(),
+            members: vec![Member {
+                player: host,
+                ready: false,
+            }],
+            rules: *rules,
+        };
+        lobby.send_state();
+        self.lobbies.insert(code, lobby);
+    }
+
+    /// Players with a wrong code, or for a full lobby, are told so and let go
+    fn join(&mut self, code: &str, mut player: NewPlayer) {
+        let status = match self.lobbies.get_mut(&code.to_ascii_uppercase()) {
+            Some(lobby) if lobby.members.len() < LOBBY_SIZE => {
+                info!("{:?} joined lobby {}", player.player_name, lobby.code);
+                lobby.members.push(Member {
+                    player,
+                    ready: false,
+                });
+                lobby.send_state();
+                return;
+            }
+            Some(_) => LobbyStatus::Full,
+            None => LobbyStatus::NotFound,
+        };
+        info!(
+            "{:?} couldn't join lobby {}: {:?}",
+            player.player_name, code, status
+        );
+        let _ = player
+            .transport
+            .send(&[ServerPacket::Lobby as u8, status as u8]);
+    }
+
+    /// The join code and name of everyone in a lobby
+    pub fn members(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lobbies.values().flat_map(|lobby| {
+            lobby.members.iter().map(|member| {
+                let name = member.player.player_name.as_deref().unwrap_or_default();
+                (lobby.code.as_str(), name)
+            })
+        })
+    }
+
+    /// Takes the player out of whichever lobby they are in
+    pub fn remove(&mut self, player_name: &str) -> Option<NewPlayer> {
+        let (code, member_idx) = self.lobbies.iter().find_map(|(code, lobby)| {
+            let member_idx = lobby
+                .members
+                .iter()
+                .position(|member| member.player.player_name.as_deref() == Some(player_name))?;
+            Some((code.clone(), member_idx))
+        })?;
+        let lobby = self.lobbies.get_mut(&code)?;
+        let (player, still_open) = lobby.remove_member(member_idx);
+        if !still_open {
+            self.lobbies.remove(&code);
+        }
+        Some(player)
+    }
+
+    /// Empties every lobby, handing back everyone who was in one
+    pub fn close_all(&mut self) -> Vec<NewPlayer> {
+        self.lobbies
+            .drain()
+            .flat_map(|(_, lobby)| lobby.members.into_iter().map(|member| member.player))
+            .collect()
+    }
+
+    /// Sends the packet to everyone in a lobby, returns how many it reached
+    pub fn broadcast(&mut self, packet: &[u8]) -> usize {
+        self.lobbies
+            .values_mut()
+            .flat_map(|lobby| lobby.members.iter_mut())
+            .map(|member| member.player.transport.send(packet))
+            .filter(Result::is_ok)
+            .count()
+    }
+
+    /// Acts on what everyone in a lobby sent, and hands back the players and rules of
+    /// every lobby that is full and ready to play
+    pub fn poll(&mut self) -> Vec<(Vec<NewPlayer>, MatchRules)> {
+        let mut ready_lobbies = Vec::new();
+        self.lobbies.retain(|code, lobby| {
+            let mut member_idx = 0;
+            while member_idx < lobby.members.len() {
+                if lobby.handle_messages(member_idx) {
+                    member_idx += 1;
+                    continue;
+                }
+                if !lobby.remove_member(member_idx).1 {
+                    return false;
+                }
+            }
+
+            let ready = lobby.members.len() == LOBBY_SIZE
+                && lobby.members.iter().all(|member| member.ready);
+            if ready {
+                info!("Lobby {} is ready to play", code);
+                let players = lobby
+                    .members
+                    .drain(..)
+                    .map(|member| member.player)
+                    .collect();
+                ready_lobbies.push((players, lobby.rules));
+            }
+            !ready
+        });
+        ready_lobbies
+    }
+}
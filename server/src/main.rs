@@ -1,38 +1,257 @@
+pub mod admin;
+pub mod auth;
+pub mod cli;
 pub mod gamemode;
+pub mod lobby;
 pub mod logger_setup;
 pub mod matchmaking;
+pub mod metrics;
 pub mod models;
+pub mod registry;
+pub mod shutdown;
+pub mod stats;
+pub mod supervisor;
+pub mod tournament;
 
-use std::{net::TcpListener, thread};
+use std::{
+    env, fs,
+    io::{self, ErrorKind},
+    net::{Shutdown, TcpListener, TcpStream},
+    process,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
-use gamemode::gamemode::Gamemodes;
-use log::info;
-use matchmaking::MatchMaker;
-use models::player::NewPlayer;
+use admin::AdminConsole;
+use auth::Authenticator;
+use cli::ServerArgs;
+use gamemode::{
+    gamemode::Gamemodes,
+    modes::{GameContext, ModeRegistry},
+};
+use log::{info, warn};
+use matchmaking::{AdminRequest, ConnectionKind, MatchMaker};
+use metrics::{DisconnectCause, METRICS};
+use models::{
+    arena::Arena, packet::ServerPacket, player::NewPlayer, rules::RulesFile, spectator::Spectator,
+    transport::TcpTransport,
+};
+use registry::GameRegistry;
+use stats::StatsStore;
 use std::sync::mpsc::channel;
+use tournament::Tournaments;
 
-const SERVER_ADDRESS: &str = "127.0.0.1:9090";
+/// All of these live in the data directory
+const ACCOUNTS_FILE: &str = "accounts.txt";
+const STATS_FILE: &str = "stats.txt";
+const REPLAY_DIRECTORY: &str = "replays";
+const ARENA_DIRECTORY: &str = "arenas";
+const RULES_FILE: &str = "rules.txt";
+const TOURNAMENT_FILE: &str = "tournament.txt";
+/// How often the listener checks for a shutdown between connections
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Keeps a matchmaker that panics straight away again from filling the log
+const MATCHMAKING_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// A copy of a stream still logging in, so a shutdown can cut the handshake short. The
+/// handshake takes it back once it's done, the connection isn't ours to cut then.
+type LoggingIn = Arc<Mutex<Option<TcpStream>>>;
 
 fn main() {
-    let tcp_listener = TcpListener::bind(SERVER_ADDRESS).unwrap();
+    let server_args = match ServerArgs::parse(env::args()) {
+        Ok(server_args) => server_args,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    let tcp_listener = TcpListener::bind(&server_args.bind_address).unwrap();
+    tcp_listener.set_nonblocking(true).unwrap();
+    let local_address = tcp_listener.local_addr().unwrap();
     // env_logger::init();
     logger_setup::init_logger();
-    info!("Listening to {}", SERVER_ADDRESS);
+    shutdown::install_handlers();
+    info!("Listening to {}", local_address);
+    // Printed on its own so scripts binding port 0 can find out where to connect
+    println!("Listening on {}", local_address);
+    let metrics_endpoint = server_args.metrics_address.as_ref().map(|address| {
+        let serving = TcpListener::bind(address).and_then(|metrics_listener| {
+            let metrics_address = metrics_listener.local_addr()?;
+            let endpoint = metrics::serve(metrics_listener, &METRICS)?;
+            info!("Serving metrics on {}", metrics_address);
+            println!("Metrics on http://{}/metrics", metrics_address);
+            Ok(endpoint)
+        });
+        serving.unwrap_or_else(|e| {
+            eprintln!("Couldn't serve metrics on {}: {}", address, e);
+            process::exit(1);
+        })
+    });
+
+    fs::create_dir_all(&server_args.data_dir).unwrap();
+    let authenticator =
+        Arc::new(Authenticator::load(server_args.data_dir.join(ACCOUNTS_FILE)).unwrap());
+    let stats = Arc::new(StatsStore::load(server_args.data_dir.join(STATS_FILE)).unwrap());
+    let arenas = match Arena::load_dir(server_args.data_dir.join(ARENA_DIRECTORY)) {
+        Ok(arenas) => arenas,
+        Err(e) => {
+            eprintln!("Bad {}: {}", ARENA_DIRECTORY, e);
+            process::exit(1);
+        }
+    };
+    let mut mode_registry = ModeRegistry::with_default_modes();
+    // Better not to start at all than to play by rules nobody asked for
+    if let Err(e) = RulesFile::load(server_args.data_dir.join(RULES_FILE))
+        .and_then(|rules_file| mode_registry.apply_rules(&rules_file))
+    {
+        eprintln!("Bad {}: {}", RULES_FILE, e);
+        process::exit(1);
+    }
+    let tournaments =
+        match Tournaments::load(server_args.data_dir.join(TOURNAMENT_FILE), &authenticator) {
+            Ok(tournaments) => Arc::new(tournaments),
+            Err(e) => {
+                eprintln!("Bad {}: {}", TOURNAMENT_FILE, e);
+                process::exit(1);
+            }
+        };
+    let game_registry = Arc::new(GameRegistry::new());
     let (tx, rx) = channel::<NewPlayer>();
+    let (admin_tx, admin_rx) = channel::<AdminRequest>();
 
-    let mut match_making = MatchMaker::new(rx);
+    let game_context = GameContext {
+        game_registry: Arc::clone(&game_registry),
+        replay_directory: server_args.data_dir.join(REPLAY_DIRECTORY),
+        stats: Arc::clone(&stats),
+        arenas,
+    };
+    let mut match_making = MatchMaker::new(
+        rx,
+        admin_rx,
+        mode_registry,
+        game_context,
+        Arc::clone(&tournaments),
+    );
 
-    let _match_making_listener = thread::Builder::new()
+    let match_making_listener = thread::Builder::new()
         .name("Matchmaking".to_string())
-        .spawn(move || match_making.recieve_new_player());
-    for stream in tcp_listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                // thread::spawn(move || handle_stream(stream));
-                let new_player = NewPlayer::new(Gamemodes::Standard, stream);
-                let _ = tx.send(new_player);
+        .spawn(move || {
+            // Everyone waiting stays in the queue, only the player being handled is lost
+            supervisor::restart_on_panic("Matchmaking", MATCHMAKING_RESTART_DELAY, || {
+                match_making.recieve_new_player()
+            })
+        })
+        .unwrap();
+    let admin_console = AdminConsole::new(
+        Arc::clone(&game_registry),
+        admin_tx,
+        server_args.data_dir.join(RULES_FILE),
+        server_args.data_dir.join(ARENA_DIRECTORY),
+    );
+    let _admin_console = thread::Builder::new()
+        .name("Admin console".to_string())
+        .spawn(move || admin_console.run(io::stdin().lock(), io::stdout()));
+    let mut handshakes: Vec<(JoinHandle<()>, LoggingIn)> = Vec::new();
+    while !shutdown::shutdown_requested() {
+        handshakes.retain(|(handshake, _)| !handshake.is_finished());
+        let stream = match tcp_listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
             }
-            Err(e) => println!("Error Occured: {:?}", e),
+            Err(e) => {
+                println!("Error Occured: {:?}", e);
+                continue;
+            }
+        };
+        let Ok(handshake_stream) = stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.try_clone())
+        else {
+            continue;
+        };
+        let handshake_stream = Arc::new(Mutex::new(Some(handshake_stream)));
+        let logging_in = Arc::clone(&handshake_stream);
+        let tx = tx.clone();
+        let authenticator = Arc::clone(&authenticator);
+        let game_registry = Arc::clone(&game_registry);
+        let tournaments = Arc::clone(&tournaments);
+        // Handshakes run on their own thread so a slow client can't hold up the queue
+        let handshake = thread::Builder::new()
+            .name("Handshake".to_string())
+            .spawn(move || {
+                let mut new_player =
+                    NewPlayer::new(Gamemodes::Standard, Box::new(TcpTransport::new(stream)));
+                let result = MatchMaker::setup_player(
+                    &mut new_player,
+                    &authenticator,
+                    &game_registry,
+                    &tournaments,
+                );
+                logging_in.lock().unwrap().take();
+                match result {
+                    // Players still in a game go back to it instead of the queue
+                    Ok(ConnectionKind::Player) => match game_registry.try_reconnect(new_player) {
+                        Ok(game_id) => info!("Player rejoining game {}", game_id),
+                        Err(new_player) => {
+                            let _ = tx.send(new_player);
+                        }
+                    },
+                    Ok(ConnectionKind::Spectator(game_id)) => {
+                        add_spectator(new_player, game_id, &game_registry)
+                    }
+                    Err(e) => {
+                        warn!("Handshake failed: {}", e);
+                        METRICS.disconnected(DisconnectCause::HandshakeFailed);
+                    }
+                }
+            });
+        if let Ok(handshake) = handshake {
+            handshakes.push((handshake, handshake_stream));
+        }
+    }
+
+    // No new players, then nobody waiting, then no games
+    info!("Shutting down");
+    drop(tcp_listener);
+    for (handshake, stream) in handshakes {
+        if let Some(stream) = stream.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
         }
+        let _ = handshake.join();
+    }
+    // The matchmaker sends everyone away once nothing can reach it anymore
+    drop(tx);
+    let _ = match_making_listener.join();
+    game_registry.shut_down(server_args.grace_period);
+    if let Some(metrics_endpoint) = metrics_endpoint {
+        let _ = metrics_endpoint.join();
+    }
+    if let Err(e) = stats.flush() {
+        warn!("Failed to save the stats: {:?}", e);
+    }
+    // The console is left blocked on stdin, exiting takes it down with it
+    info!("Shut down cleanly");
+    log::logger().flush();
+}
+
+/// Hands a spectator to the game it asked for, telling it first whether that game exists
+fn add_spectator(mut connection: NewPlayer, game_id: u32, game_registry: &GameRegistry) {
+    let game_exists = game_registry
+        .list_games()
+        .iter()
+        .any(|summary| summary.game_id == game_id);
+    let result = [ServerPacket::SpectateResult as u8, !game_exists as u8];
+    if connection.transport.send(&result).is_err() || !game_exists {
+        info!("Spectator turned away from game {}", game_id);
+        return;
+    }
+
+    let spectator = Spectator::new(connection.transport);
+    if game_registry.try_spectate(game_id, spectator).is_err() {
+        info!("Game {} ended before the spectator joined", game_id);
     }
 }
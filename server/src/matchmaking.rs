@@ -1,47 +1,531 @@
-use std::{collections::VecDeque, io::Read, sync::mpsc::Receiver, thread};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, ErrorKind},
+    sync::{
+        Arc,
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+    },
+    time::{Duration, Instant},
+};
 
-use log::debug;
+use log::{debug, info, warn};
 
 use crate::{
-    gamemode::{gamemode::Gamemode, standard::StandardGame},
-    models::player::NewPlayer,
+    auth::{AuthError, AuthRequest, AuthStatus, Authenticator},
+    gamemode::{
+        gamemode::Gamemodes,
+        modes::{GameContext, ModeRegistry},
+        standard::StandardGame,
+    },
+    lobby::{Lobbies, LobbyRequest},
+    metrics::{DisconnectCause, METRICS},
+    models::{
+        arena::Arena,
+        bot::{Bot, Difficulty},
+        packet::{ServerPacket, message_packet},
+        player::{MessageType, NewPlayer, PlayerMessage},
+    },
+    registry::GameRegistry,
+    tournament::{TournamentStatus, Tournaments},
 };
 
+/// The whole handshake has to be over in this time, however many messages it takes
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_AUTH_ATTEMPTS: usize = 3;
+/// How often the queue is checked for players that left or waited too long
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// A player left alone in the queue this long is matched with a bot
+const BOT_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a connection turned out to be once the handshake is over
+#[derive(Debug, PartialEq)]
+pub enum ConnectionKind {
+    Player,
+    /// Wants to watch the game with this id
+    Spectator(u32),
+}
+
+/// What the admin console asks of the matchmaker, answers go back on the sender
+#[derive(Debug)]
+pub enum AdminRequest {
+    ListWaiting(Sender<Vec<WaitingPlayer>>),
+    /// Sends the player away if they are waiting anywhere, answers whether they were
+    Kick(String, Sender<bool>),
+    /// Shows a message to everyone waiting, answers how many got it
+    Message(String, Sender<usize>),
+    /// Freshly loaded modes and arenas, replacing the ones games are started with
+    Reload(ModeRegistry, Vec<Arena>),
+}
+
+/// Somebody who isn't in a game yet
+#[derive(Clone, Debug)]
+pub struct WaitingPlayer {
+    pub player_name: String,
+    pub waiting_for: Waiting,
+}
+
+#[derive(Clone, Debug)]
+pub enum Waiting {
+    /// In the public queue for this mode, for this long
+    Queue(Gamemodes, Duration),
+    /// In the lobby with this code
+    Lobby(String),
+    Tournament,
+}
+
+impl fmt::Display for Waiting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Waiting::Queue(mode, waited) => {
+                write!(f, "{:?} queue for {}s", mode, waited.as_secs())
+            }
+            Waiting::Lobby(code) => write!(f, "lobby {}", code),
+            Waiting::Tournament => write!(f, "tournament match"),
+        }
+    }
+}
+
 pub struct MatchMaker {
     client_rx: Receiver<NewPlayer>,
-    player_queue: VecDeque<NewPlayer>,
+    admin_rx: Receiver<AdminRequest>,
+    /// Waiting players for each mode, along with when they joined the queue
+    player_queues: HashMap<Gamemodes, VecDeque<(Instant, NewPlayer)>>,
+    lobbies: Lobbies,
+    /// Entered players waiting for their next tournament match
+    tournament_players: Vec<NewPlayer>,
+    tournaments: Arc<Tournaments>,
+    modes: ModeRegistry,
+    context: GameContext,
 }
 
 impl MatchMaker {
-    pub fn new(client_rx: Receiver<NewPlayer>) -> Self {
+    pub fn new(
+        client_rx: Receiver<NewPlayer>,
+        admin_rx: Receiver<AdminRequest>,
+        modes: ModeRegistry,
+        context: GameContext,
+        tournaments: Arc<Tournaments>,
+    ) -> Self {
         Self {
             client_rx,
-            player_queue: VecDeque::new(),
+            admin_rx,
+            player_queues: HashMap::new(),
+            lobbies: Lobbies::new(),
+            tournament_players: Vec::new(),
+            tournaments,
+            modes,
+            context,
         }
     }
 
     pub fn recieve_new_player(&mut self) {
-        while let Ok(mut player) = self.client_rx.recv() {
-            Self::setup_player(&mut player);
-            debug!("Recieved player: {:?}", player);
-            let _ = self.player_queue.push_back(player);
+        loop {
+            match self.client_rx.recv_timeout(QUEUE_POLL_INTERVAL) {
+                Ok(player) => {
+                    debug!("Recieved player: {:?}", player);
+                    self.enqueue(player);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send_everyone_away();
+                    break;
+                }
+            }
+            while let Ok(request) = self.admin_rx.try_recv() {
+                self.handle_admin_request(request);
+            }
+            self.match_players();
+            self.start_lobby_games();
+            self.start_tournament_games();
+            self.report_queue_lengths();
+        }
+    }
+
+    /// Lobbies and tournaments count as queues of their own
+    fn report_queue_lengths(&self) {
+        let mut queue_lengths: Vec<(String, usize)> = self
+            .player_queues
+            .iter()
+            .map(|(mode, queue)| (mode.name().to_string(), queue.len()))
+            .collect();
+        queue_lengths.sort();
+        queue_lengths.push(("lobby".to_string(), self.lobbies.members().count()));
+        queue_lengths.push(("tournament".to_string(), self.tournament_players.len()));
+        METRICS.set_queue_lengths(queue_lengths);
+    }
 
-            if self.player_queue.len() > 1 {
-                println!("Starting game");
-                let player_1 = self.player_queue.pop_front().unwrap();
-                let player_2 = self.player_queue.pop_front().unwrap();
+    /// The server is going away, so is everyone still waiting for a game
+    fn send_everyone_away(&mut self) {
+        let packet = message_packet("The server is shutting down");
+        let mut waiting: Vec<NewPlayer> = self
+            .player_queues
+            .drain()
+            .flat_map(|(_, queue)| queue.into_iter().map(|(_, player)| player))
+            .chain(self.tournament_players.drain(..))
+            .chain(self.lobbies.close_all())
+            .collect();
+        for player in waiting.iter_mut() {
+            let _ = player.transport.send(&packet);
+        }
+        info!("Sent {} waiting players away", waiting.len());
+    }
 
-                let (mut gamelogic, mut gamestate) = StandardGame::setup_game(player_1, player_2);
-                let _ = thread::Builder::new()
-                    .name("Game".to_string())
-                    .spawn(move || gamelogic.start_game(&mut gamestate));
+    fn handle_admin_request(&mut self, request: AdminRequest) {
+        match request {
+            AdminRequest::ListWaiting(reply_tx) => {
+                let _ = reply_tx.send(self.waiting_players());
+            }
+            AdminRequest::Kick(player_name, reply_tx) => {
+                let _ = reply_tx.send(self.kick(&player_name));
+            }
+            AdminRequest::Message(text, reply_tx) => {
+                let packet = message_packet(&text);
+                let mut sent = self.lobbies.broadcast(&packet);
+                let waiting = self
+                    .player_queues
+                    .values_mut()
+                    .flat_map(|queue| queue.iter_mut().map(|(_, player)| player))
+                    .chain(self.tournament_players.iter_mut());
+                for player in waiting {
+                    if player.transport.send(&packet).is_ok() {
+                        sent += 1;
+                    }
+                }
+                let _ = reply_tx.send(sent);
+            }
+            AdminRequest::Reload(modes, arenas) => {
+                info!("Reloaded the rules and {} arenas", arenas.len());
+                self.modes = modes;
+                self.context.arenas = arenas;
             }
         }
     }
 
-    pub fn setup_player(player: &mut NewPlayer) {
-        let mut buff: [u8; 1024] = [0; 1024];
-        let n = player.tcp_stream.read(&mut buff).unwrap();
-        player.player_name = Some(buff[..n].iter().map(|c| *c as char).collect::<String>())
+    fn waiting_players(&self) -> Vec<WaitingPlayer> {
+        let queued = self.player_queues.iter().flat_map(|(mode, queue)| {
+            queue
+                .iter()
+                .map(|(queued_at, player)| (player, Waiting::Queue(*mode, queued_at.elapsed())))
+        });
+        let tournament = self
+            .tournament_players
+            .iter()
+            .map(|player| (player, Waiting::Tournament));
+        let mut waiting: Vec<WaitingPlayer> = queued
+            .chain(tournament)
+            .map(|(player, waiting_for)| WaitingPlayer {
+                player_name: player.player_name.clone().unwrap_or_default(),
+                waiting_for,
+            })
+            .collect();
+        waiting.extend(
+            self.lobbies
+                .members()
+                .map(|(code, player_name)| WaitingPlayer {
+                    player_name: player_name.to_string(),
+                    waiting_for: Waiting::Lobby(code.to_string()),
+                }),
+        );
+        waiting
     }
+
+    /// Tells the player why they are being let go first, then drops the connection
+    fn kick(&mut self, player_name: &str) -> bool {
+        let is_them = |player: &NewPlayer| player.player_name.as_deref() == Some(player_name);
+        let mut kicked: Vec<NewPlayer> = Vec::new();
+        for queue in self.player_queues.values_mut() {
+            while let Some(idx) = queue.iter().position(|(_, player)| is_them(player)) {
+                kicked.extend(queue.remove(idx).map(|(_, player)| player));
+            }
+        }
+        while let Some(idx) = self.tournament_players.iter().position(is_them) {
+            kicked.push(self.tournament_players.remove(idx));
+        }
+        kicked.extend(self.lobbies.remove(player_name));
+
+        if kicked.is_empty() {
+            return false;
+        }
+        let packet = message_packet("You were kicked by the server");
+        for player in kicked.iter_mut() {
+            let _ = player.transport.send(&packet);
+            METRICS.disconnected(DisconnectCause::Kicked);
+        }
+        info!("Kicked {} while they were waiting", player_name);
+        true
+    }
+
+    /// Players asking for a bot skip the queue, if their mode has room for one. Private
+    /// games go through a lobby instead, and tournament games wait for the right opponent.
+    fn enqueue(&mut self, mut player: NewPlayer) {
+        if player.tournament {
+            let name = player.player_name.as_deref().unwrap_or_default();
+            if self.tournaments.still_in(name) {
+                let _ = player
+                    .transport
+                    .send(&self.tournaments.packet(TournamentStatus::Waiting));
+                self.tournament_players.push(player);
+            } else {
+                info!("{:?} has no tournament match to play", player.player_name);
+                let _ = player
+                    .transport
+                    .send(&self.tournaments.packet(TournamentStatus::Out));
+            }
+            return;
+        }
+        if player.lobby.is_some() {
+            let rules = self
+                .modes
+                .get(Gamemodes::Standard)
+                .map(|entry| entry.rules)
+                .unwrap_or_default();
+            self.lobbies.handle_request(player, &rules);
+            return;
+        }
+        let Some(entry) = self.modes.get(player.game_type) else {
+            warn!(
+                "No {:?} mode on this server, turning away {:?}",
+                player.game_type, player.player_name
+            );
+            let text = format!("This server doesn't run {} games", player.game_type.name());
+            let _ = player.transport.send(&message_packet(&text));
+            return;
+        };
+        match player.bot_opponent {
+            Some(difficulty) if entry.bots_allowed => {
+                let players = fill_with_bots(vec![player], entry.players, difficulty);
+                (entry.launch)(players, &entry.rules, &self.context);
+            }
+            _ => self
+                .player_queues
+                .entry(player.game_type)
+                .or_default()
+                .push_back((Instant::now(), player)),
+        }
+    }
+
+    /// Starts a game for every mode with enough players waiting
+    fn match_players(&mut self) {
+        for (mode, player_queue) in self.player_queues.iter_mut() {
+            let Some(entry) = self.modes.get(*mode) else {
+                continue;
+            };
+
+            player_queue.retain_mut(|(_, queued_player)| {
+                let connected = queued_player.still_connected();
+                if !connected {
+                    info!("{:?} left the queue", queued_player.player_name);
+                    METRICS.disconnected(DisconnectCause::LeftQueue);
+                }
+                connected
+            });
+
+            while player_queue.len() >= entry.players {
+                let players = player_queue
+                    .drain(..entry.players)
+                    .map(|(_, player)| player)
+                    .collect();
+                (entry.launch)(players, &entry.rules, &self.context);
+            }
+
+            // Nobody else turned up, whoever is left gets bots instead
+            if entry.bots_allowed
+                && let Some((queued_at, _)) = player_queue.front()
+                && queued_at.elapsed() >= BOT_QUEUE_TIMEOUT
+            {
+                let players: Vec<NewPlayer> =
+                    player_queue.drain(..).map(|(_, player)| player).collect();
+                info!(
+                    "{} waited too long for a {:?} game, matching with bots",
+                    players.len(),
+                    mode
+                );
+                let players = fill_with_bots(players, entry.players, Difficulty::Medium);
+                (entry.launch)(players, &entry.rules, &self.context);
+            }
+        }
+    }
+
+    /// Private games are always standard ones, on an empty map
+    fn start_lobby_games(&mut self) {
+        for (players, rules) in self.lobbies.poll() {
+            let arena = Arena::empty(rules.map_width, rules.map_height);
+            StandardGame::launch_on(players, &arena, &rules, &self.context, |_| {});
+        }
+    }
+
+    /// Tournament games are standard ones, played by the standard mode's rules. The bracket
+    /// moves on from the game's own thread once it is over.
+    fn start_tournament_games(&mut self) {
+        self.tournament_players.retain_mut(|player| {
+            let connected = player.still_connected();
+            if !connected {
+                info!("{:?} stopped waiting for their match", player.player_name);
+                METRICS.disconnected(DisconnectCause::LeftQueue);
+            }
+            connected
+        });
+
+        let rules = self
+            .modes
+            .get(Gamemodes::Standard)
+            .map(|entry| entry.rules)
+            .unwrap_or_default();
+        loop {
+            let present: Vec<&str> = self
+                .tournament_players
+                .iter()
+                .filter_map(|player| player.player_name.as_deref())
+                .collect();
+            let Some((match_idx, names)) = self.tournaments.start_match(&present) else {
+                break;
+            };
+            let players: Vec<NewPlayer> = names
+                .iter()
+                .map(|name| {
+                    let idx = self
+                        .tournament_players
+                        .iter()
+                        .position(|player| player.player_name.as_ref() == Some(name))
+                        .unwrap();
+                    self.tournament_players.remove(idx)
+                })
+                .collect();
+            info!("Tournament match {}: {}", match_idx, names.join(" vs "));
+            let tournaments = Arc::clone(&self.tournaments);
+            StandardGame::launch_on(
+                players,
+                &self.context.pick_arena(&rules),
+                &rules,
+                &self.context,
+                move |winner| tournaments.report(match_idx, winner),
+            );
+        }
+    }
+
+    /// Runs the login / register exchange, a player only reaches the queue once this succeeds.
+    /// Spectators skip logging in, they can list the running games and pick one to watch.
+    pub fn setup_player(
+        player: &mut NewPlayer,
+        authenticator: &Authenticator,
+        game_registry: &GameRegistry,
+        tournaments: &Tournaments,
+    ) -> Result<ConnectionKind, AuthError> {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let mut auth_attempts = 0;
+        while auth_attempts < MAX_AUTH_ATTEMPTS {
+            // Only logging in counts as an attempt, so the messages before it can't be
+            // allowed to keep the handshake going forever
+            let time_left = deadline.saturating_duration_since(Instant::now());
+            if time_left.is_zero() {
+                return Err(io::Error::from(ErrorKind::TimedOut).into());
+            }
+            player.transport.set_read_timeout(Some(time_left))?;
+            let frame = player
+                .transport
+                .receive()?
+                .ok_or(io::Error::from(ErrorKind::TimedOut))?;
+
+            let message_id = *frame.first().ok_or(AuthError::MalformedRequest)?;
+            match PlayerMessage::decode_message(message_id) {
+                MessageType::ListGames => {
+                    player
+                        .transport
+                        .send(&game_registry.create_game_list_packet())?;
+                    continue;
+                }
+                MessageType::Bracket => {
+                    player
+                        .transport
+                        .send(&tournaments.packet(TournamentStatus::Bracket))?;
+                    continue;
+                }
+                // Sent before logging in by players who want a bot as their opponent
+                MessageType::PlayBot => {
+                    let difficulty = frame.get(1).copied().unwrap_or(Difficulty::Medium as u8);
+                    player.bot_opponent = Some(Difficulty::from(difficulty));
+                    continue;
+                }
+                // Also sent before logging in, picks what the player gets put into
+                // A newer client may ask for a mode this server doesn't know, better to say so
+                // than to put them in a game they didn't pick
+                MessageType::SelectMode => {
+                    let mode = frame.get(1).copied().unwrap_or(Gamemodes::Standard as u8);
+                    match Gamemodes::try_from(mode) {
+                        Ok(game_type) => player.game_type = game_type,
+                        Err(mode) => {
+                            let error = AuthError::UnknownMode(mode);
+                            let response = [
+                                ServerPacket::AuthResult as u8,
+                                AuthStatus::from(&error) as u8,
+                            ];
+                            player.transport.send(&response)?;
+                            return Err(error);
+                        }
+                    }
+                    continue;
+                }
+                // Sent before logging in too, for a private game with a friend
+                MessageType::CreateLobby => {
+                    player.lobby = Some(LobbyRequest::Create);
+                    continue;
+                }
+                MessageType::JoinLobby => {
+                    let code = String::from_utf8_lossy(&frame[1..]).trim().to_string();
+                    player.lobby = Some(LobbyRequest::Join(code));
+                    continue;
+                }
+                // And by players here for their tournament match
+                MessageType::JoinTournament => {
+                    player.tournament = true;
+                    continue;
+                }
+                MessageType::Spectate => {
+                    let game_id = frame
+                        .get(1..5)
+                        .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+                        .ok_or(AuthError::MalformedRequest)?;
+                    player.transport.set_read_timeout(None)?;
+                    return Ok(ConnectionKind::Spectator(game_id));
+                }
+                _ => auth_attempts += 1,
+            }
+
+            let result = AuthRequest::cast_buffer(&frame)
+                .and_then(|request| authenticator.authenticate(request));
+            match result {
+                Ok((username, token)) => {
+                    let mut response = vec![ServerPacket::AuthResult as u8, AuthStatus::Ok as u8];
+                    response.extend_from_slice(&token);
+                    player.transport.send(&response)?;
+                    player.transport.set_read_timeout(None)?;
+
+                    info!("{} logged in", username);
+                    player.player_name = Some(username);
+                    player.session_token = Some(token);
+                    return Ok(ConnectionKind::Player);
+                }
+                Err(AuthError::Io(e)) => return Err(AuthError::Io(e)),
+                Err(e) => {
+                    warn!("Authentication failed: {}", e);
+                    let response = [ServerPacket::AuthResult as u8, AuthStatus::from(&e) as u8];
+                    player.transport.send(&response)?;
+                }
+            }
+        }
+
+        Err(AuthError::InvalidCredentials)
+    }
+}
+
+/// Tops the players up to a full game with bots
+fn fill_with_bots(
+    mut players: Vec<NewPlayer>,
+    player_count: usize,
+    difficulty: Difficulty,
+) -> Vec<NewPlayer> {
+    let missing = player_count.saturating_sub(players.len());
+    players.extend((0..missing).map(|_| Bot::spawn(difficulty)));
+    players
 }
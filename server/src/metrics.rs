@@ -0,0 +1,481 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::shutdown;
+
+/// Everything the server counts, shared by every thread. Only real connections are
+/// counted, bots and in-memory players don't add to the bytes.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the tick duration buckets, in seconds. A tick is due every 35ms, so
+/// anything near the top means games are falling behind.
+const TICK_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+/// How long a game's ticks are counted before its rate is worked out again
+const TICK_RATE_WINDOW: Duration = Duration::from_secs(1);
+/// How often the endpoint checks for a shutdown between scrapes
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// A scraper that connects and says nothing doesn't get to hold up the next one
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Why someone stopped being connected, or stopped waiting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisconnectCause {
+    /// Said they were leaving
+    Left = 0,
+    /// Their connection dropped in a game
+    ConnectionLost = 1,
+    /// Sent away by the admin
+    Kicked = 2,
+    /// Went away while waiting in a queue for a game
+    LeftQueue = 3,
+    /// Never got past logging in
+    HandshakeFailed = 4,
+}
+
+impl DisconnectCause {
+    const ALL: [DisconnectCause; 5] = [
+        DisconnectCause::Left,
+        DisconnectCause::ConnectionLost,
+        DisconnectCause::Kicked,
+        DisconnectCause::LeftQueue,
+        DisconnectCause::HandshakeFailed,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            DisconnectCause::Left => "left",
+            DisconnectCause::ConnectionLost => "connection_lost",
+            DisconnectCause::Kicked => "kicked",
+            DisconnectCause::LeftQueue => "left_queue",
+            DisconnectCause::HandshakeFailed => "handshake_failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Histogram {
+    /// Not cumulative, they are added up when rendered
+    buckets: [AtomicU64; TICK_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; TICK_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = TICK_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    connections: AtomicU64,
+    open_connections: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    disconnects: [AtomicU64; DisconnectCause::ALL.len()],
+    active_games: AtomicU64,
+    tick_durations: Histogram,
+    /// Ticks per second of every running game, by game id
+    tick_rates: Mutex<BTreeMap<u32, f64>>,
+    /// Players waiting in each queue, as the matchmaker last saw them
+    queue_lengths: Mutex<Vec<(String, usize)>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            connections: AtomicU64::new(0),
+            open_connections: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            disconnects: [const { AtomicU64::new(0) }; DisconnectCause::ALL.len()],
+            active_games: AtomicU64::new(0),
+            tick_durations: Histogram::new(),
+            tick_rates: Mutex::new(BTreeMap::new()),
+            queue_lengths: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn bytes_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self, cause: DisconnectCause) {
+        self.disconnects[cause as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Replaces the queue lengths wholesale, queues nobody waits in can be left out
+    pub fn set_queue_lengths(&self, queue_lengths: Vec<(String, usize)>) {
+        *self.queue_lengths.lock().unwrap() = queue_lengths;
+    }
+
+    /// Prometheus text format, version 0.0.4
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            header(out, name, help, "counter");
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        };
+        counter(
+            &mut out,
+            "pingpong_connections_total",
+            "Client connections accepted.",
+            &self.connections,
+        );
+        header(
+            &mut out,
+            "pingpong_open_connections",
+            "Client connections currently open.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "pingpong_open_connections {}",
+            self.open_connections.load(Ordering::Relaxed)
+        );
+        counter(
+            &mut out,
+            "pingpong_received_bytes_total",
+            "Bytes read from clients, framing included.",
+            &self.bytes_received,
+        );
+        counter(
+            &mut out,
+            "pingpong_sent_bytes_total",
+            "Bytes written to clients, framing included.",
+            &self.bytes_sent,
+        );
+
+        header(
+            &mut out,
+            "pingpong_disconnects_total",
+            "Players that went away, by why they did.",
+            "counter",
+        );
+        for cause in DisconnectCause::ALL {
+            let _ = writeln!(
+                out,
+                "pingpong_disconnects_total{{cause=\"{}\"}} {}",
+                cause.label(),
+                self.disconnects[cause as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "pingpong_queue_length",
+            "Players waiting for a game, by queue.",
+            "gauge",
+        );
+        for (queue, length) in self.queue_lengths.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "pingpong_queue_length{{queue=\"{}\"}} {}",
+                queue, length
+            );
+        }
+
+        header(
+            &mut out,
+            "pingpong_active_games",
+            "Games currently running.",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "pingpong_active_games {}",
+            self.active_games.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "pingpong_game_ticks_per_second",
+            "How fast each running game ticked over the last second.",
+            "gauge",
+        );
+        for (game_id, rate) in self.tick_rates.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "pingpong_game_ticks_per_second{{game=\"{}\"}} {:.2}",
+                game_id, rate
+            );
+        }
+
+        let histogram = &self.tick_durations;
+        header(
+            &mut out,
+            "pingpong_tick_duration_seconds",
+            "Time spent on a single game tick.",
+            "histogram",
+        );
+        let mut cumulative = 0;
+        for (bound, bucket) in TICK_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "pingpong_tick_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = Duration::from_nanos(histogram.sum_nanos.load(Ordering::Relaxed));
+        let _ = writeln!(
+            out,
+            "pingpong_tick_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "pingpong_tick_duration_seconds_sum {}",
+            sum.as_secs_f64()
+        );
+        let _ = writeln!(out, "pingpong_tick_duration_seconds_count {}", count);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Counts one running game and how fast it ticks. Dropping it takes the game off again,
+/// which also happens when the game panics.
+#[derive(Debug)]
+pub struct GameMetrics {
+    metrics: &'static Metrics,
+    game_id: u32,
+    window_start: Instant,
+    window_ticks: u32,
+}
+
+impl GameMetrics {
+    pub fn new(metrics: &'static Metrics, game_id: u32) -> Self {
+        metrics.active_games.fetch_add(1, Ordering::Relaxed);
+        Self {
+            metrics,
+            game_id,
+            window_start: Instant::now(),
+            window_ticks: 0,
+        }
+    }
+
+    pub fn record_tick(&mut self, duration: Duration) {
+        self.metrics.tick_durations.observe(duration);
+        self.window_ticks += 1;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= TICK_RATE_WINDOW {
+            let rate = self.window_ticks as f64 / elapsed.as_secs_f64();
+            self.metrics
+                .tick_rates
+                .lock()
+                .unwrap()
+                .insert(self.game_id, rate);
+            self.window_start = Instant::now();
+            self.window_ticks = 0;
+        }
+    }
+}
+
+impl Drop for GameMetrics {
+    fn drop(&mut self) {
+        self.metrics.active_games.fetch_sub(1, Ordering::Relaxed);
+        self.metrics
+            .tick_rates
+            .lock()
+            .unwrap()
+            .remove(&self.game_id);
+    }
+}
+
+/// Answers `GET /metrics` until the server shuts down. Scrapes are answered one at a
+/// time, on a thread of their own so they never hold up the games.
+pub fn serve(listener: TcpListener, metrics: &'static Metrics) -> io::Result<JoinHandle<()>> {
+    listener.set_nonblocking(true)?;
+    thread::Builder::new()
+        .name("Metrics".to_string())
+        .spawn(move || {
+            while !shutdown::shutdown_requested() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = answer(stream, metrics) {
+                            info!("Metrics request failed: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL)
+                    }
+                    Err(e) => warn!("Metrics listener failed: {:?}", e),
+                }
+            }
+        })
+}
+
+/// Just enough HTTP for a scraper or curl, every connection gets one answer and is closed
+fn answer(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers don't change the answer, but are read so closing doesn't reset them
+    let mut header_line = String::new();
+    while reader.read_line(&mut header_line)? > 0 && header_line.trim_end() != "" {
+        header_line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "Try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn tick_durations_are_rendered_as_a_cumulative_histogram() {
+        let metrics = Metrics::new();
+        metrics.tick_durations.observe(Duration::from_micros(300));
+        metrics.tick_durations.observe(Duration::from_micros(800));
+        metrics.tick_durations.observe(Duration::from_secs(1));
+
+        let rendered = metrics.render();
+        for line in [
+            "# TYPE pingpong_tick_duration_seconds histogram",
+            "pingpong_tick_duration_seconds_bucket{le=\"0.00025\"} 0",
+            "pingpong_tick_duration_seconds_bucket{le=\"0.0005\"} 1",
+            "pingpong_tick_duration_seconds_bucket{le=\"0.001\"} 2",
+            "pingpong_tick_duration_seconds_bucket{le=\"0.1\"} 2",
+            "pingpong_tick_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "pingpong_tick_duration_seconds_sum 1.0011",
+            "pingpong_tick_duration_seconds_count 3",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {:?}", line);
+        }
+    }
+
+    #[test]
+    fn counters_and_gauges_carry_their_labels() {
+        let metrics = Metrics::new();
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.connection_closed();
+        metrics.bytes_sent(7);
+        metrics.disconnected(DisconnectCause::Kicked);
+        metrics.set_queue_lengths(vec![("standard".to_string(), 1)]);
+
+        let rendered = metrics.render();
+        for line in [
+            "pingpong_connections_total 2",
+            "pingpong_open_connections 1",
+            "pingpong_sent_bytes_total 7",
+            "pingpong_received_bytes_total 0",
+            "pingpong_disconnects_total{cause=\"kicked\"} 1",
+            "pingpong_disconnects_total{cause=\"left\"} 0",
+            "pingpong_queue_length{queue=\"standard\"} 1",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {:?}", line);
+        }
+    }
+
+    #[test]
+    fn finished_games_are_taken_off() {
+        static METRICS: Metrics = Metrics::new();
+        let mut game = GameMetrics::new(&METRICS, 12);
+        game.window_start -= TICK_RATE_WINDOW;
+        game.record_tick(Duration::from_millis(1));
+        assert!(METRICS.render().contains("pingpong_active_games 1"));
+        assert!(
+            METRICS
+                .render()
+                .contains("pingpong_game_ticks_per_second{game=\"12\"}")
+        );
+
+        drop(game);
+        assert!(METRICS.render().contains("pingpong_active_games 0"));
+        assert!(!METRICS.render().contains("game=\"12\""));
+    }
+
+    #[test]
+    fn the_endpoint_only_answers_metrics() {
+        static METRICS: Metrics = Metrics::new();
+        let fetch = |request: &str| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            answer(stream, &METRICS).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = fetch("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP pingpong_connections_total"));
+        assert!(fetch("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(fetch("POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    }
+}
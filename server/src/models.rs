@@ -1,2 +1,8 @@
+pub mod arena;
+pub mod bot;
 pub mod packet;
 pub mod player;
+pub mod replay;
+pub mod rules;
+pub mod spectator;
+pub mod transport;
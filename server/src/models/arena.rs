@@ -0,0 +1,253 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::models::rules::{HEIGHT_RANGE, WIDTH_RANGE};
+
+/// Obstacles stay this far from the side walls, clear of both paddles and the goals,
+/// and a little less from the top and bottom so a ball never gets stuck between them
+const SIDE_CLEARANCE: u8 = 5;
+const WALL_CLEARANCE: u8 = 3;
+
+#[derive(Error, Debug)]
+pub enum ArenaError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+/// A solid rectangle the ball bounces off
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Obstacle {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+impl Obstacle {
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// The layout of a map, read from a file like this one:
+///
+/// ```text
+/// # Lines starting with # are comments
+/// name Pillars
+/// size 80 30
+/// goal 14
+/// obstacle 30 8 2 5
+/// obstacle 48 17 2 5
+/// ```
+///
+/// `goal` is how many rows in the middle of each end wall let the ball through, the
+/// rest of the wall is solid. Left out, the whole wall is a goal. Obstacles are
+/// `x y width height`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arena {
+    pub name: String,
+    pub width: u8,
+    pub height: u8,
+    pub goal_width: u8,
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Arena {
+    /// A plain rectangle with the whole end walls as goals, what every game used to be
+    /// played on
+    pub fn empty(width: u8, height: u8) -> Self {
+        Self {
+            name: "Standard".to_string(),
+            width,
+            height,
+            goal_width: height - 2,
+            obstacles: Vec::new(),
+        }
+    }
+
+    /// Arenas without a name go by their file's
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ArenaError> {
+        let path = path.as_ref();
+        let mut arena = Self::parse(&fs::read_to_string(path)?)?;
+        if arena.name.is_empty() {
+            arena.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+        Ok(arena)
+    }
+
+    /// Every `.map` file in the directory, in name order. Broken ones are logged and
+    /// left out, and a missing directory just means there are none.
+    pub fn load_dir(directory: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let mut paths: Vec<PathBuf> = match fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "map"))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        paths.sort();
+
+        let arenas: Vec<Self> = paths
+            .iter()
+            .filter_map(|path| match Self::load(path) {
+                Ok(arena) => Some(arena),
+                Err(e) => {
+                    warn!("Skipping arena {:?}: {}", path, e);
+                    None
+                }
+            })
+            .collect();
+        info!("Loaded {} arenas", arenas.len());
+        Ok(arenas)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ArenaError> {
+        let mut arena = Self {
+            name: String::new(),
+            width: 0,
+            height: 0,
+            goal_width: 0,
+            obstacles: Vec::new(),
+        };
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_number = idx + 1;
+            let malformed = |reason| ArenaError::Malformed {
+                line: line_number,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let numbers: Option<Vec<u8>> = rest
+                .split_whitespace()
+                .map(|number| number.parse().ok())
+                .collect();
+            match (key, numbers.as_deref()) {
+                ("name", _) => arena.name = rest.trim().to_string(),
+                ("size", Some(&[width, height])) => (arena.width, arena.height) = (width, height),
+                ("goal", Some(&[goal_width])) => arena.goal_width = goal_width,
+                ("obstacle", Some(&[x, y, width, height])) => arena.obstacles.push(Obstacle {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                ("size" | "goal" | "obstacle", _) => {
+                    return Err(malformed("values missing or out of range"));
+                }
+                _ => return Err(malformed("unknown setting")),
+            }
+        }
+
+        if arena.goal_width == 0 {
+            arena.goal_width = arena.height.saturating_sub(2);
+        }
+        arena.validate()?;
+        Ok(arena)
+    }
+
+    fn validate(&self) -> Result<(), ArenaError> {
+        if !WIDTH_RANGE.contains(&self.width) || !HEIGHT_RANGE.contains(&self.height) {
+            return Err(ArenaError::Invalid(
+                "size must be 40-120 wide and 16-50 high",
+            ));
+        }
+        if self.goal_width > self.height - 2 {
+            return Err(ArenaError::Invalid("goal is taller than the map"));
+        }
+        if self.obstacles.len() > u8::MAX as usize {
+            return Err(ArenaError::Invalid("too many obstacles"));
+        }
+        let fits = |obstacle: &Obstacle| {
+            obstacle.width > 0
+                && obstacle.height > 0
+                && obstacle.x >= SIDE_CLEARANCE
+                && obstacle.x as u16 + obstacle.width as u16 <= (self.width - SIDE_CLEARANCE) as u16
+                && obstacle.y >= WALL_CLEARANCE
+                && obstacle.y as u16 + obstacle.height as u16
+                    <= (self.height - WALL_CLEARANCE) as u16
+        };
+        if !self.obstacles.iter().all(fits) {
+            return Err(ArenaError::Invalid(
+                "obstacles have to stay clear of the paddles and walls",
+            ));
+        }
+        let (middle_x, middle_y) = (self.width / 2, self.height / 2);
+        if self
+            .obstacles
+            .iter()
+            .any(|obstacle| obstacle.contains(middle_x, middle_y))
+        {
+            return Err(ArenaError::Invalid("the ball is served from the middle"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// The harness plays games on it as well
+    pub const PILLARS: &str = "
+# Two pillars and goals half as tall as the map
+name Pillars
+size 80 30
+goal 14
+obstacle 30 6 3 6
+obstacle 47 18 3 6
+";
+
+    #[test]
+    fn arenas_are_read_from_map_files() {
+        let arena = Arena::parse(PILLARS).unwrap();
+        assert_eq!(arena.name, "Pillars");
+        assert_eq!((arena.width, arena.height, arena.goal_width), (80, 30, 14));
+        assert_eq!(arena.obstacles.len(), 2);
+        assert!(arena.obstacles[1].contains(49, 23));
+        assert!(!arena.obstacles[1].contains(50, 23));
+
+        // Without a goal line the whole end wall is one
+        let open = Arena::parse("size 60 20").unwrap();
+        assert_eq!(open.goal_width, 18);
+
+        assert!(matches!(
+            Arena::parse("size 80 30\nwalls 3"),
+            Err(ArenaError::Malformed { line: 2, .. })
+        ));
+        assert!(matches!(
+            Arena::parse("size 80 30\nobstacle 2 10 1 1"),
+            Err(ArenaError::Invalid(_))
+        ));
+        assert!(matches!(
+            Arena::parse("size 80 30\ngoal 29"),
+            Err(ArenaError::Invalid(_))
+        ));
+        assert!(matches!(
+            Arena::parse("size 200 30"),
+            Err(ArenaError::Invalid(_))
+        ));
+        assert!(matches!(
+            Arena::parse("size 300 30"),
+            Err(ArenaError::Malformed { line: 1, .. })
+        ));
+    }
+}
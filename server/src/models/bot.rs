@@ -0,0 +1,297 @@
+use std::{collections::VecDeque, thread};
+
+use log::{debug, info, warn};
+use rand::Rng;
+
+use crate::{
+    gamemode::gamemode::{Gamemodes, Side},
+    models::{
+        packet::ServerPacket,
+        player::{MessageType, NewPlayer},
+        transport::{MemoryTransport, Transport},
+    },
+};
+
+/// Column of a keeper's paddle, every player starts out seeing themselves on the left
+const KEEPER_COLUMN: i16 = 2;
+/// Where the paddles start in the snapshot, each one is `[player id, team, side, line, position]`
+const SNAPSHOT_PADDLES: usize = 19;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difficulty {
+    Easy = 0,
+    Medium = 1,
+    Hard = 2,
+}
+
+impl From<u8> for Difficulty {
+    fn from(difficulty: u8) -> Self {
+        match difficulty {
+            0 => Difficulty::Easy,
+            2 => Difficulty::Hard,
+            _ => Difficulty::Medium,
+        }
+    }
+}
+
+impl Difficulty {
+    /// How many ticks old the updates the bot reacts to are
+    fn reaction_delay(&self) -> usize {
+        match self {
+            Difficulty::Easy => 6,
+            Difficulty::Medium => 3,
+            Difficulty::Hard => 1,
+        }
+    }
+
+    /// Furthest the paddle moves in a single tick
+    fn max_speed(&self) -> i16 {
+        match self {
+            Difficulty::Easy => 1,
+            Difficulty::Medium => 2,
+            Difficulty::Hard => 3,
+        }
+    }
+
+    /// How far off the bot's guess of where the ball arrives can be
+    fn prediction_error(&self) -> i16 {
+        match self {
+            Difficulty::Easy => 6,
+            Difficulty::Medium => 3,
+            Difficulty::Hard => 1,
+        }
+    }
+}
+
+/// The ball as the bot saw it in one update
+#[derive(Clone, Copy, Debug)]
+struct BallSighting {
+    x: i16,
+    y: i16,
+}
+
+/// Plays one side of a game from its own thread, talking to the game like any client
+/// would, over the other end of an in-memory transport
+#[derive(Debug)]
+pub struct Bot {
+    difficulty: Difficulty,
+    transport: MemoryTransport,
+    player_id: u8,
+    paddle_pos: i16,
+    /// Forwards in doubles stand further up than the keeper
+    paddle_column: i16,
+    /// Set once a series swaps ends, the ball then comes at the bot from the left
+    on_right: bool,
+    map_height: i16,
+    paddle_size: i16,
+    sightings: VecDeque<BallSighting>,
+    /// Picked once per approach, so the paddle doesn't jitter around the target
+    aim_offset: i16,
+    approaching: bool,
+}
+
+impl Bot {
+    /// Starts a bot and returns the player to put in the game for it
+    pub fn spawn(difficulty: Difficulty) -> NewPlayer {
+        let (server_end, bot_end) = MemoryTransport::pair("bot");
+        let mut bot = Self::new(difficulty, bot_end);
+        let _ = thread::Builder::new()
+            .name("Bot".to_string())
+            .spawn(move || bot.play());
+
+        let mut new_player = NewPlayer::new(Gamemodes::Standard, Box::new(server_end));
+        new_player.player_name = Some(format!("Bot ({:?})", difficulty));
+        new_player
+    }
+
+    fn new(difficulty: Difficulty, transport: MemoryTransport) -> Self {
+        Self {
+            difficulty,
+            transport,
+            player_id: 0,
+            paddle_pos: 0,
+            paddle_column: KEEPER_COLUMN,
+            on_right: false,
+            map_height: 0,
+            paddle_size: 0,
+            sightings: VecDeque::new(),
+            aim_offset: 0,
+            approaching: false,
+        }
+    }
+
+    fn play(&mut self) {
+        loop {
+            let packet = match self.transport.receive() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => continue,
+                // The game is gone once the other end is dropped
+                Err(_) => break,
+            };
+            if !self.handle(&packet) {
+                break;
+            }
+        }
+        debug!("Bot finished playing");
+    }
+
+    /// Acts on one packet from the game, false once the game is over
+    fn handle(&mut self, packet: &[u8]) -> bool {
+        match packet.first().copied() {
+            Some(id) if id == ServerPacket::Snapshot as u8 => self.initialize(packet),
+            Some(id) if id == ServerPacket::Update as u8 => self.react(packet),
+            Some(id) if id == ServerPacket::GameOver as u8 => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn initialize(&mut self, snapshot: &[u8]) {
+        self.player_id = snapshot[1];
+        self.paddle_pos = snapshot[2].into();
+        self.map_height = snapshot[7].into();
+        self.paddle_size = snapshot[8].into();
+        let own_paddle = snapshot
+            .get(SNAPSHOT_PADDLES..)
+            .unwrap_or_default()
+            .chunks_exact(5)
+            .find(|paddle| paddle[0] == self.player_id);
+        self.paddle_column = own_paddle.map_or(KEEPER_COLUMN, |paddle| paddle[3].into());
+        self.on_right = own_paddle.is_some_and(|paddle| paddle[2] == Side::Right as u8);
+        self.sightings.clear();
+        info!("Bot playing as player {}", self.player_id);
+    }
+
+    fn react(&mut self, update: &[u8]) {
+        if self.player_id == 0 {
+            return;
+        }
+        self.sightings.push_back(BallSighting {
+            x: update[4].into(),
+            y: update[5].into(),
+        });
+        // Only ever acts on what it saw a few ticks ago
+        if self.sightings.len() <= self.difficulty.reaction_delay() {
+            return;
+        }
+        let previous = self.sightings.pop_front().unwrap();
+        let current = self.sightings[0];
+
+        let target = self.predict_arrival(previous, current);
+        let max_speed = self.difficulty.max_speed();
+        self.paddle_pos += (target - self.paddle_pos).clamp(-max_speed, max_speed);
+        self.paddle_pos = self
+            .paddle_pos
+            .clamp(self.paddle_size + 1, self.map_height - self.paddle_size - 2);
+
+        let position_update = [MessageType::PlayerPos as u8, self.paddle_pos as u8];
+        if let Err(e) = self.transport.send(&position_update) {
+            warn!("Bot failed to send its position: {:?}", e);
+        }
+    }
+
+    /// Where the ball will meet the paddle, bouncing it off the walls on the way.
+    /// The paddle drifts back to the middle while the ball heads away.
+    fn predict_arrival(&mut self, previous: BallSighting, current: BallSighting) -> i16 {
+        let (dx, dy) = (current.x - previous.x, current.y - previous.y);
+        let approaching = if self.on_right {
+            dx > 0 && current.x < self.paddle_column
+        } else {
+            dx < 0 && current.x > self.paddle_column
+        };
+        if approaching && !self.approaching {
+            let error = self.difficulty.prediction_error();
+            self.aim_offset = rand::rng().random_range(-error..=error);
+        }
+        self.approaching = approaching;
+        if !approaching {
+            return self.map_height / 2;
+        }
+
+        let ticks = (self.paddle_column - current.x) / dx;
+        let (top, bottom) = (1, self.map_height - 2);
+        let span = bottom - top;
+        let mut arrival = (current.y - top + dy * ticks).rem_euclid(2 * span);
+        if arrival > span {
+            arrival = 2 * span - arrival;
+        }
+        top + arrival + self.aim_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gamemode::{harness::Harness, series::SeriesGame},
+        models::rules::MatchRules,
+    };
+
+    fn bot_on_map(map_height: i16) -> Bot {
+        let (_, bot_end) = MemoryTransport::pair("bot");
+        let mut bot = Bot::new(Difficulty::Hard, bot_end);
+        bot.map_height = map_height;
+        // Already mid approach, so no random aim offset gets picked
+        bot.approaching = true;
+        bot
+    }
+
+    #[test]
+    fn predicts_a_straight_shot() {
+        let mut bot = bot_on_map(30);
+        let previous = BallSighting { x: 41, y: 10 };
+        let current = BallSighting { x: 40, y: 10 };
+        assert_eq!(bot.predict_arrival(previous, current), 10);
+    }
+
+    #[test]
+    fn predicts_a_bounce_off_the_wall() {
+        let mut bot = bot_on_map(30);
+        // 8 ticks away heading down from row 25, hits the bottom wall at row 28 and comes back up
+        let previous = BallSighting { x: 11, y: 24 };
+        let current = BallSighting { x: 10, y: 25 };
+        assert_eq!(bot.predict_arrival(previous, current), 23);
+    }
+
+    #[test]
+    fn heads_for_the_middle_while_the_ball_moves_away() {
+        let mut bot = bot_on_map(30);
+        let previous = BallSighting { x: 10, y: 5 };
+        let current = BallSighting { x: 11, y: 6 };
+        assert_eq!(bot.predict_arrival(previous, current), 15);
+    }
+
+    #[test]
+    fn keeps_returning_the_ball_after_a_series_swaps_ends() {
+        let rules = MatchRules {
+            best_of: 3,
+            game_points: 5,
+            ..MatchRules::default()
+        };
+        let mut harness = Harness::with_rules(Box::new(SeriesGame::new(&rules)), &rules, 7);
+        let mut bot = Bot::new(Difficulty::Hard, harness.clients.pop().unwrap());
+        // Player 1 takes the first game, the bot plays the second from the left
+        harness.runner.gamestate.scores = vec![5, 0];
+
+        let mut returns = 0;
+        for _ in 0..2000 {
+            // Player 1 follows the ball and never misses
+            let ball = harness.gamestate().balls[0];
+            harness.move_paddle(1, ball.y);
+            let scores = harness.gamestate().scores.clone();
+            if harness.tick().is_some() {
+                break;
+            }
+            while let Some(packet) = bot.transport.receive().unwrap() {
+                bot.handle(&packet);
+            }
+            let gamestate = harness.gamestate();
+            if ball.dx < 0 && gamestate.balls[0].dx > 0 && gamestate.scores == scores {
+                returns += 1;
+            }
+        }
+        assert_eq!(harness.gamestate().player(2).seat.side, Side::Left);
+        assert!(returns >= 10, "the bot only returned {} balls", returns);
+    }
+}
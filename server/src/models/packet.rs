@@ -1,3 +1,5 @@
+use std::io::{self, ErrorKind, Read, Write};
+
 pub struct Packet<'a> {
     message: &'a str,
 }
@@ -9,8 +11,116 @@ impl<'a> Packet<'a> {
 
     pub fn to_buff(self) -> [u8; 1024] {
         let mut buff_to_fill: [u8; 1024] = [0; 1024];
-        buff_to_fill[..self.message.len()].copy_from_slice(&self.message.as_bytes());
+        buff_to_fill[..self.message.len()].copy_from_slice(self.message.as_bytes());
 
         buff_to_fill
     }
 }
+
+/// First byte of every packet the server sends
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServerPacket {
+    Snapshot = 0,
+    Update = 1,
+    AuthResult = 2,
+    Status = 3,
+    GameOver = 4,
+    ShutdownAck = 5,
+    GameList = 6,
+    SpectateResult = 7,
+    PracticeScore = 8,
+    PowerUps = 9,
+    Lobby = 10,
+    Tournament = 11,
+    /// Games won and points in the current game, whenever someone scores in a series
+    Series = 12,
+    /// A line of text from whoever runs the server
+    Message = 13,
+}
+
+/// Carried by the status packet so clients can show what the game is waiting on
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameStatus {
+    Running = 0,
+    WaitingForReconnect = 1,
+    Paused = 2,
+    PausedAgreed = 3,
+    Resuming = 4,
+}
+
+/// Why a game ended, sent along with the winner
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOverReason {
+    Finished = 0,
+    Forfeit = 1,
+    Abandoned = 2,
+    /// The server's admin took a player out of the game
+    Kicked = 3,
+    /// The server's admin stopped the game, nobody wins
+    Ended = 4,
+    /// Something went wrong on the server, nobody wins
+    Crashed = 5,
+}
+
+/// `[id, text..]`
+pub fn message_packet(text: &str) -> Vec<u8> {
+    let mut v_data: Vec<u8> = vec![ServerPacket::Message as u8];
+    v_data.extend_from_slice(text.as_bytes());
+    v_data
+}
+
+/// Every message on the wire is prefixed with its length as a little endian u16,
+/// so several packets arriving in one read can be split apart again.
+pub fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut frame: Vec<u8> = Vec::with_capacity(payload.len() + 2);
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// Returns the next complete frame, `Ok(None)` if the stream timed out before one
+    /// arrived, and `UnexpectedEof` once the other side has closed the connection.
+    pub fn read_frame(&mut self, stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+        let mut buff: [u8; 1024] = [0; 1024];
+        loop {
+            if let Some(frame) = self.take_frame() {
+                return Ok(Some(frame));
+            }
+            match stream.read(&mut buff) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buffer.extend_from_slice(&buff[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let length = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+        if self.buffer.len() < length + 2 {
+            return None;
+        }
+        let frame = self.buffer[2..length + 2].to_vec();
+        self.buffer.drain(..length + 2);
+        Some(frame)
+    }
+}
@@ -1,14 +1,46 @@
-use crate::gamemode::gamemode::Gamemodes;
+use crate::auth::sessions::SessionToken;
+use crate::gamemode::gamemode::{Gamemodes, Seat};
+use crate::lobby::LobbyRequest;
+use crate::models::{bot::Difficulty, packet::ServerPacket, transport::Transport};
+use std::collections::VecDeque;
 use std::fmt::Display;
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::io::ErrorKind;
 use thiserror::Error;
+/// How many times each player may pause a single game
+pub const MAX_PAUSES: u8 = 3;
+
 #[repr(C)]
 pub enum MessageType {
     PlayerPos,
     Shutdown,
+    Login,
+    Register,
+    Resume,
+    Pause,
+    Unpause,
+    Forfeit,
+    ListGames,
+    Spectate,
+    PlayBot,
+    SelectMode,
+    CreateLobby,
+    JoinLobby,
+    /// The host changing a lobby's rules
+    LobbyRules,
+    Ready,
+    /// Asks how the tournament is going
+    Bracket,
+    JoinTournament,
     Undefined,
 }
+
+/// In game requests that the game mode has to act on, rather than just a paddle move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerCommand {
+    Pause,
+    Unpause,
+    Forfeit,
+}
 #[derive(Error, Debug)]
 pub enum PlayerError {
     #[error("I/O error: {0}")]
@@ -17,6 +49,9 @@ pub enum PlayerError {
     #[error("Player Disconnected")]
     PlayerDisconnected,
 
+    #[error("Player left the game")]
+    PlayerLeft,
+
     #[error("Undefined packet recieved: {0}")]
     UndefinedPacket(u8),
 }
@@ -43,6 +78,22 @@ impl PlayerMessage {
         match message_id {
             0 => MessageType::PlayerPos,
             1 => MessageType::Shutdown,
+            2 => MessageType::Login,
+            3 => MessageType::Register,
+            4 => MessageType::Resume,
+            5 => MessageType::Pause,
+            6 => MessageType::Unpause,
+            7 => MessageType::Forfeit,
+            8 => MessageType::ListGames,
+            9 => MessageType::Spectate,
+            10 => MessageType::PlayBot,
+            11 => MessageType::SelectMode,
+            12 => MessageType::CreateLobby,
+            13 => MessageType::JoinLobby,
+            14 => MessageType::LobbyRules,
+            15 => MessageType::Ready,
+            16 => MessageType::Bracket,
+            17 => MessageType::JoinTournament,
             _ => MessageType::Undefined,
         }
     }
@@ -51,70 +102,147 @@ impl PlayerMessage {
 #[derive(Debug)]
 pub struct NewPlayer {
     pub player_name: Option<String>,
+    pub session_token: Option<SessionToken>,
     pub game_type: Gamemodes,
-    pub tcp_stream: TcpStream,
+    /// Set when the player asked to skip the queue and play a bot
+    pub bot_opponent: Option<Difficulty>,
+    /// Set when the player wants a private game instead of the public queue
+    pub lobby: Option<LobbyRequest>,
+    /// Set when the player is here for their next tournament match
+    pub tournament: bool,
+    pub transport: Box<dyn Transport>,
 }
 
 impl NewPlayer {
-    pub fn new(game_type: Gamemodes, tcp_stream: TcpStream) -> Self {
+    pub fn new(game_type: Gamemodes, transport: Box<dyn Transport>) -> Self {
         Self {
             player_name: None,
+            session_token: None,
             game_type,
-            tcp_stream,
+            bot_opponent: None,
+            lobby: None,
+            tournament: false,
+            transport,
         }
     }
+
+    /// Checks a queued connection is still usable before it is put into a game,
+    /// answering a Shutdown the client sent while it was waiting
+    pub fn still_connected(&mut self) -> bool {
+        if self.transport.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let connected = loop {
+            match self.transport.receive() {
+                Ok(None) => break true,
+                // Empty frames say nothing, they don't get to take the matchmaker down
+                Ok(Some(frame)) => {
+                    if let Some(&message_id) = frame.first()
+                        && let MessageType::Shutdown = PlayerMessage::decode_message(message_id)
+                    {
+                        let _ = self.transport.send(&[ServerPacket::ShutdownAck as u8]);
+                        break false;
+                    }
+                }
+                Err(_) => break false,
+            }
+        };
+        let _ = self.transport.set_nonblocking(false);
+        connected
+    }
 }
 
 #[derive(Debug)]
 pub struct Player {
+    pub player_name: String,
+    pub session_token: Option<SessionToken>,
+    pub seat: Seat,
     pub player_pos: u8,
-    pub stream: TcpStream,
+    /// How far the paddle reaches either side of `player_pos`
+    pub paddle_size: u8,
+    pub connected: bool,
+    /// Set once the player shut down on purpose, they won't be waited on to reconnect
+    pub left: bool,
+    pub pauses_left: u8,
+    pub commands: VecDeque<PlayerCommand>,
+    pub transport: Box<dyn Transport>,
 }
 
 impl Player {
     pub fn from_new_player(new_player: NewPlayer) -> Self {
         Self {
+            player_name: new_player.player_name.unwrap_or_default(),
+            session_token: new_player.session_token,
+            seat: Seat::default(),
             player_pos: 30,
-            stream: new_player.tcp_stream,
+            paddle_size: 0,
+            connected: true,
+            left: false,
+            pauses_left: MAX_PAUSES,
+            commands: VecDeque::new(),
+            transport: new_player.transport,
         }
     }
 
-    pub fn updated_position(&mut self, buff: &mut [u8]) -> Result<(), PlayerError> {
-        let update_packet = self.stream.read(buff);
+    /// Swaps in the connection of a player coming back with the same session
+    pub fn reconnect(&mut self, new_player: NewPlayer) {
+        self.transport = new_player.transport;
+        self.connected = true;
+    }
 
-        match update_packet {
-            Ok(n) => {
-                if n == 0 {
+    /// Drains every packet that has arrived since the last tick, the latest position wins
+    pub fn updated_position(&mut self) -> Result<(), PlayerError> {
+        loop {
+            let frame = match self.transport.receive() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::UnexpectedEof
+                            | ErrorKind::ConnectionReset
+                            | ErrorKind::ConnectionAborted
+                            | ErrorKind::BrokenPipe
+                    ) =>
+                {
                     return Err(PlayerError::PlayerDisconnected);
                 }
-                let player_message: PlayerMessage = PlayerMessage::cast_buffer(&buff[..n]);
-                //
-                match player_message.message_type {
-                    MessageType::PlayerPos => {
-                        self.player_pos = player_message.data;
-                        Ok(())
-                    }
-                    MessageType::Shutdown => Err(PlayerError::PlayerDisconnected),
-                    MessageType::Undefined => Err(PlayerError::UndefinedPacket(buff[0])),
-                }
+                Err(e) => return Err(PlayerError::Io(e)),
+            };
+            if frame.len() < 2 {
+                return Err(PlayerError::UndefinedPacket(
+                    frame.first().copied().unwrap_or(0),
+                ));
             }
-            Err(e) => {
-                return Err(PlayerError::Io(e));
+
+            let player_message: PlayerMessage = PlayerMessage::cast_buffer(&frame);
+            match player_message.message_type {
+                MessageType::PlayerPos => self.player_pos = player_message.data,
+                MessageType::Shutdown => {
+                    self.send_bytes(&[ServerPacket::ShutdownAck as u8]);
+                    self.connected = false;
+                    self.left = true;
+                    return Err(PlayerError::PlayerLeft);
+                }
+                MessageType::Pause => self.commands.push_back(PlayerCommand::Pause),
+                MessageType::Unpause => self.commands.push_back(PlayerCommand::Unpause),
+                MessageType::Forfeit => self.commands.push_back(PlayerCommand::Forfeit),
+                _ => return Err(PlayerError::UndefinedPacket(frame[0])),
             }
         }
     }
 
     pub fn send_hello(&mut self) {
-        let _ = self.stream.write(b"Hello");
+        self.send_bytes(b"Hello");
     }
 
     pub fn send_bytes(&mut self, message: &[u8]) {
-        let _ = self.stream.write(message);
+        let _ = self.transport.send(message);
     }
 }
 
 impl Display for Player {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.stream.peer_addr().unwrap())
+        write!(f, "{} ({})", self.player_name, self.transport)
     }
 }
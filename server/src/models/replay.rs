@@ -0,0 +1,147 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+
+use crate::gamemode::gamemode::GameState;
+
+/// Every replay file starts with this, followed by the format version
+pub const REPLAY_MAGIC: &[u8; 4] = b"PPRP";
+pub const REPLAY_VERSION: u8 = 4;
+
+/// The state of the game after a single tick, as it is stored on disk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayFrame {
+    pub player_1_pos: u8,
+    pub player_2_pos: u8,
+    pub ball_x: u8,
+    pub ball_y: u8,
+    pub player_1_score: u8,
+    pub player_2_score: u8,
+    /// A series has the players at the other ends every second game
+    pub ends_swapped: bool,
+}
+
+impl ReplayFrame {
+    /// Only the first two players and teams and the first ball make it in, see
+    /// `ReplayRecorder::create`
+    pub fn from_state(gamestate: &GameState) -> Self {
+        let position = |idx: usize| {
+            gamestate
+                .players
+                .get(idx)
+                .map_or(0, |player| player.player_pos)
+        };
+        let score = |idx: usize| gamestate.scores.get(idx).copied().unwrap_or(0);
+        Self {
+            player_1_pos: position(0),
+            player_2_pos: position(1),
+            ball_x: gamestate.balls[0].x,
+            ball_y: gamestate.balls[0].y,
+            player_1_score: score(0),
+            player_2_score: score(1),
+            ends_swapped: gamestate.ends_swapped,
+        }
+    }
+
+    pub fn as_bytes(&self) -> [u8; 7] {
+        [
+            self.player_1_pos,
+            self.player_2_pos,
+            self.ball_x,
+            self.ball_y,
+            self.player_1_score,
+            self.player_2_score,
+            self.ends_swapped as u8,
+        ]
+    }
+}
+
+/// Writes one game to a replay file, a header with the map and players and then
+/// a fixed size frame per tick so the client can seek straight to any point.
+#[derive(Debug)]
+pub struct ReplayRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    frames: u32,
+}
+
+impl ReplayRecorder {
+    pub fn create(
+        directory: &Path,
+        gamestate: &GameState,
+        tick_interval: Duration,
+    ) -> io::Result<Self> {
+        // The format has room for two paddles and two scores
+        if gamestate.players.len() != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only two player games can be recorded",
+            ));
+        }
+        fs::create_dir_all(directory)?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = directory.join(format!(
+            "game_{}_{}.replay",
+            gamestate.session.game_id, started
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(REPLAY_MAGIC);
+        header.push(REPLAY_VERSION);
+        header.extend_from_slice(&(tick_interval.as_millis() as u16).to_le_bytes());
+        header.extend_from_slice(&gamestate.seed.to_le_bytes());
+        header.push(gamestate.map_width);
+        header.push(gamestate.map_height);
+        header.push(gamestate.paddle_size);
+        // The arena as it goes in the snapshot, `[goal width, count, (x, y, width, height)..]`
+        header.push(gamestate.goal_width);
+        header.push(gamestate.obstacles.len() as u8);
+        for obstacle in &gamestate.obstacles {
+            header.extend_from_slice(&[obstacle.x, obstacle.y, obstacle.width, obstacle.height]);
+        }
+        for player_name in gamestate.players.iter().map(|player| &player.player_name) {
+            header.push(player_name.len() as u8);
+            header.extend_from_slice(player_name.as_bytes());
+        }
+        writer.write_all(&header)?;
+
+        info!(
+            "Recording game {} to {}",
+            gamestate.session.game_id,
+            path.display()
+        );
+        Ok(Self {
+            path,
+            writer,
+            frames: 0,
+        })
+    }
+
+    pub fn record(&mut self, frame: ReplayFrame) -> io::Result<()> {
+        self.writer.write_all(&frame.as_bytes())?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+impl Drop for ReplayRecorder {
+    fn drop(&mut self) {
+        match self.writer.flush() {
+            Ok(_) => info!(
+                "Saved replay {} ({} frames)",
+                self.path.display(),
+                self.frames
+            ),
+            Err(e) => warn!("Failed to save replay {}: {:?}", self.path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{collections::HashMap, fs, io, ops::RangeInclusive, path::Path, time::Duration};
+
+use log::info;
+use thiserror::Error;
+
+use crate::gamemode::gamemode::Gamemodes;
+
+/// Maps have to fit a terminal and leave room for the paddles
+pub const WIDTH_RANGE: RangeInclusive<u8> = 40..=120;
+pub const HEIGHT_RANGE: RangeInclusive<u8> = 16..=50;
+const BALL_SPEED_RANGE: RangeInclusive<u8> = 1..=6;
+const MAX_BEST_OF: u8 = 9;
+const GAME_POINTS_RANGE: RangeInclusive<u8> = 1..=21;
+/// The countdown players are shown is a single byte of seconds
+const RECONNECT_RANGE: RangeInclusive<u64> = 5..=255;
+
+#[derive(Error, Debug)]
+pub enum RulesError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+
+    #[error("{mode:?}: {reason}")]
+    Invalid {
+        mode: Gamemodes,
+        reason: &'static str,
+    },
+}
+
+/// What a game is played with, every mode has its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchRules {
+    pub map_width: u8,
+    pub map_height: u8,
+    /// How far a paddle reaches either side of its middle
+    pub paddle_size: u8,
+    /// Cells the ball moves every two ticks
+    pub ball_speed: u8,
+    /// How long a game waits for a player who dropped out before the others win
+    pub reconnect_timeout: Duration,
+    /// Games in a series, a single game has no score cap
+    pub best_of: u8,
+    /// Goals it takes to win a game of a series
+    pub game_points: u8,
+}
+
+impl Default for MatchRules {
+    /// The standard map, paddles and ball
+    fn default() -> Self {
+        Self {
+            map_width: 80,
+            map_height: 30,
+            paddle_size: 4,
+            ball_speed: 3,
+            reconnect_timeout: Duration::from_secs(30),
+            best_of: 1,
+            game_points: 5,
+        }
+    }
+}
+
+impl MatchRules {
+    /// The standard rules on a map of another size
+    pub fn sized(map_width: u8, map_height: u8) -> Self {
+        Self {
+            map_width,
+            map_height,
+            ..Self::default()
+        }
+    }
+
+    /// Paddles can't take up more than half their wall, even with a power up
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !WIDTH_RANGE.contains(&self.map_width) || !HEIGHT_RANGE.contains(&self.map_height) {
+            return Err("size must be 40-120 wide and 16-50 high");
+        }
+        if self.paddle_size == 0 || self.paddle_size > self.map_height / 4 {
+            return Err("paddle must be between 1 and a quarter of the map's height");
+        }
+        if !BALL_SPEED_RANGE.contains(&self.ball_speed) {
+            return Err("speed must be between 1 and 6");
+        }
+        if !RECONNECT_RANGE.contains(&self.reconnect_timeout.as_secs()) {
+            return Err("reconnect must be between 5 and 255 seconds");
+        }
+        // An even number of games could end in a draw
+        if self.best_of.is_multiple_of(2) || self.best_of > MAX_BEST_OF {
+            return Err("bestof must be an odd number up to 9");
+        }
+        if !GAME_POINTS_RANGE.contains(&self.game_points) {
+            return Err("points must be between 1 and 21");
+        }
+        Ok(())
+    }
+}
+
+/// One line of a rules file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Setting {
+    Size(u8, u8),
+    Paddle(u8),
+    Speed(u8),
+    Reconnect(u64),
+    BestOf(u8),
+    Points(u8),
+}
+
+/// Changes to the rules every mode starts with, read from a file like this one:
+///
+/// ```text
+/// # Everything before the first [mode] goes for every mode
+/// paddle 3
+/// speed 4
+///
+/// [practice]
+/// paddle 6
+/// reconnect 60
+///
+/// [standard]
+/// bestof 3
+/// ```
+///
+/// `size` takes a width and height, `reconnect` is in seconds, `points` is what it takes
+/// to win a game of a series. Modes are named the way
+/// clients ask for them.
+#[derive(Debug, Default)]
+pub struct RulesFile {
+    every_mode: Vec<Setting>,
+    modes: HashMap<Gamemodes, Vec<Setting>>,
+}
+
+impl RulesFile {
+    /// No file means nothing changes
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RulesError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let rules_file = Self::parse(&contents)?;
+                info!("Loaded rules for {} modes", rules_file.modes.len());
+                Ok(rules_file)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, RulesError> {
+        let mut rules_file = Self::default();
+        let mut section: Option<Gamemodes> = None;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let malformed = |reason| RulesError::Malformed {
+                line: idx + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                let mode = Gamemodes::from_name(name.trim()).ok_or(malformed("unknown mode"))?;
+                rules_file.modes.entry(mode).or_default();
+                section = Some(mode);
+                continue;
+            }
+
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let numbers: Option<Vec<u64>> = rest
+                .split_whitespace()
+                .map(|number| number.parse().ok())
+                .collect();
+            let byte = |number: u64| u8::try_from(number).ok();
+            let setting = match (key, numbers.as_deref()) {
+                ("size", Some(&[width, height])) => byte(width)
+                    .zip(byte(height))
+                    .map(|(width, height)| Setting::Size(width, height)),
+                ("paddle", Some(&[paddle_size])) => byte(paddle_size).map(Setting::Paddle),
+                ("speed", Some(&[ball_speed])) => byte(ball_speed).map(Setting::Speed),
+                ("reconnect", Some(&[seconds])) => Some(Setting::Reconnect(seconds)),
+                ("bestof", Some(&[best_of])) => byte(best_of).map(Setting::BestOf),
+                ("points", Some(&[game_points])) => byte(game_points).map(Setting::Points),
+                ("size" | "paddle" | "speed" | "reconnect" | "bestof" | "points", _) => None,
+                _ => return Err(malformed("unknown setting")),
+            }
+            .ok_or(malformed("values missing or out of range"))?;
+
+            match section {
+                Some(mode) => rules_file.modes.entry(mode).or_default().push(setting),
+                None => rules_file.every_mode.push(setting),
+            }
+        }
+        Ok(rules_file)
+    }
+
+    /// A mode's own rules with the file's changes on top, the ones for every mode first
+    pub fn apply(&self, mode: Gamemodes, rules: MatchRules) -> Result<MatchRules, RulesError> {
+        let settings = self
+            .every_mode
+            .iter()
+            .chain(self.modes.get(&mode).into_iter().flatten());
+        let rules = settings.fold(rules, |mut rules, setting| {
+            match *setting {
+                Setting::Size(width, height) => {
+                    (rules.map_width, rules.map_height) = (width, height)
+                }
+                Setting::Paddle(paddle_size) => rules.paddle_size = paddle_size,
+                Setting::Speed(ball_speed) => rules.ball_speed = ball_speed,
+                Setting::Reconnect(seconds) => {
+                    rules.reconnect_timeout = Duration::from_secs(seconds)
+                }
+                Setting::BestOf(best_of) => rules.best_of = best_of,
+                Setting::Points(game_points) => rules.game_points = game_points,
+            }
+            rules
+        });
+        rules
+            .validate()
+            .map_err(|reason| RulesError::Invalid { mode, reason })?;
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemode::{modes::ModeRegistry, series::SeriesGame};
+
+    #[test]
+    fn rules_files_change_every_mode_then_their_own() {
+        let rules_file = RulesFile::parse(
+            "
+    # For everyone
+    paddle 3
+
+    [practice]
+    size 60 20
+    speed 5
+    ",
+        )
+        .unwrap();
+        let mut modes = ModeRegistry::with_default_modes();
+        modes.apply_rules(&rules_file).unwrap();
+
+        let standard = modes.get(Gamemodes::Standard).unwrap().rules;
+        assert_eq!(
+            standard,
+            MatchRules {
+                paddle_size: 3,
+                ..MatchRules::default()
+            }
+        );
+        let practice = modes.get(Gamemodes::Practice).unwrap().rules;
+        assert_eq!(
+            (
+                practice.map_width,
+                practice.map_height,
+                practice.paddle_size
+            ),
+            (60, 20, 3)
+        );
+        assert_eq!(practice.ball_speed, 5);
+        // Free for all keeps its own map
+        let free_for_all = modes.get(Gamemodes::FreeForAll).unwrap().rules;
+        assert_eq!((free_for_all.map_width, free_for_all.paddle_size), (60, 3));
+
+        for (contents, line) in [("[tennis]", 1), ("paddle", 1), ("# Too fast\nspeed 900", 2)] {
+            assert!(
+                matches!(
+                    RulesFile::parse(contents),
+                    Err(RulesError::Malformed { line: error_line, .. }) if error_line == line
+                ),
+                "{:?} should be malformed",
+                contents
+            );
+        }
+        // Only caught once applied, a big paddle is fine on a tall enough map
+        let rules_file = RulesFile::parse("[doubles]\npaddle 8").unwrap();
+        let mut modes = ModeRegistry::with_default_modes();
+        assert!(matches!(
+            modes.apply_rules(&rules_file),
+            Err(RulesError::Invalid {
+                mode: Gamemodes::Doubles,
+                ..
+            })
+        ));
+        let rules_file = RulesFile::parse("[doubles]\nsize 80 40\npaddle 8").unwrap();
+        assert!(modes.apply_rules(&rules_file).is_ok());
+    }
+
+    #[test]
+    fn series_need_an_odd_number_of_games() {
+        let rules_file = RulesFile::parse("[series]\nbestof 4\n").unwrap();
+        assert!(matches!(
+            rules_file.apply(Gamemodes::Series, SeriesGame::default_rules()),
+            Err(RulesError::Invalid { .. })
+        ));
+        let rules_file = RulesFile::parse("[series]\nbestof 5\n").unwrap();
+        let rules = rules_file.apply(Gamemodes::Series, SeriesGame::default_rules());
+        assert_eq!(rules.unwrap().best_of, 5);
+    }
+}
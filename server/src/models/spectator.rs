@@ -0,0 +1,72 @@
+use std::fmt::Display;
+
+use log::{debug, warn};
+
+use crate::models::{
+    packet::ServerPacket,
+    player::{MessageType, PlayerMessage},
+    transport::Transport,
+};
+
+/// A read-only connection that gets the same snapshots and updates as the players
+#[derive(Debug)]
+pub struct Spectator {
+    pub transport: Box<dyn Transport>,
+    pub connected: bool,
+}
+
+impl Spectator {
+    pub fn new(transport: Box<dyn Transport>) -> Self {
+        Self {
+            transport,
+            connected: true,
+        }
+    }
+
+    /// Spectators never block the game, a spectator that can't keep up is dropped instead
+    pub fn prepare_stream(&mut self) {
+        if let Err(e) = self.transport.set_nonblocking(true) {
+            warn!("Failed to make spectator stream non blocking: {:?}", e);
+            self.connected = false;
+        }
+    }
+
+    pub fn send_bytes(&mut self, message: &[u8]) {
+        if !self.connected {
+            return;
+        }
+        if let Err(e) = self.transport.send(message) {
+            debug!("Dropping spectator {}: {:?}", self, e);
+            self.connected = false;
+        }
+    }
+
+    /// Spectators only ever send Shutdown, anything else is ignored. Nobody checked who
+    /// they are, so empty frames are skipped rather than trusted.
+    pub fn poll(&mut self) {
+        loop {
+            match self.transport.receive() {
+                Ok(None) => return,
+                Ok(Some(frame)) => {
+                    if let Some(&message_id) = frame.first()
+                        && let MessageType::Shutdown = PlayerMessage::decode_message(message_id)
+                    {
+                        self.send_bytes(&[ServerPacket::ShutdownAck as u8]);
+                        self.connected = false;
+                        return;
+                    }
+                }
+                Err(_) => {
+                    self.connected = false;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Display for Spectator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.transport)
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fmt::{Debug, Display},
+    io::{self, ErrorKind},
+    net::TcpStream,
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError, channel},
+    time::Duration,
+};
+
+use crate::{
+    metrics::METRICS,
+    models::packet::{FrameReader, write_frame},
+    supervisor,
+};
+
+/// Whatever carries messages between the server and one client. Games only ever talk
+/// through this, so they can be driven by in-memory players as well as real connections.
+pub trait Transport: Debug + Display + Send {
+    /// Returns the next whole message, `Ok(None)` if nothing arrived before the read
+    /// timeout, and `UnexpectedEof` once the other side is gone.
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()>;
+
+    /// `None` blocks until a message arrives
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+}
+
+/// A client connected over TCP, using the length prefixed framing from `packet`
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+    frame_reader: FrameReader,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> Self {
+        METRICS.connection_opened();
+        Self {
+            stream,
+            frame_reader: FrameReader::new(),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let frame = self.frame_reader.read_frame(&mut self.stream)?;
+        if let Some(message) = &frame {
+            // Counted with the length prefix, as it went over the wire
+            METRICS.bytes_received(message.len() + 2);
+        }
+        Ok(frame)
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        write_frame(&mut self.stream, message)?;
+        METRICS.bytes_sent(message.len() + 2);
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+}
+
+impl Display for TcpTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.stream.peer_addr() {
+            Ok(address) => write!(f, "{}", address),
+            Err(_) => write!(f, "disconnected"),
+        }
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        supervisor::notify_if_panicking(self);
+        METRICS.connection_closed();
+    }
+}
+
+/// One end of an in-memory connection, whatever is sent comes out of the other end
+#[derive(Debug)]
+pub struct MemoryTransport {
+    name: String,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    read_timeout: Option<Duration>,
+    nonblocking: bool,
+}
+
+impl MemoryTransport {
+    /// Two connected ends, one for the server side and one for whoever drives it
+    pub fn pair(name: &str) -> (Self, Self) {
+        let (server_tx, client_rx) = channel::<Vec<u8>>();
+        let (client_tx, server_rx) = channel::<Vec<u8>>();
+        (
+            Self::new(name, server_tx, server_rx),
+            Self::new(name, client_tx, client_rx),
+        )
+    }
+
+    fn new(name: &str, tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            name: name.to_string(),
+            tx,
+            rx,
+            read_timeout: None,
+            nonblocking: false,
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let disconnected = || io::Error::from(ErrorKind::UnexpectedEof);
+        if self.nonblocking {
+            return match self.rx.try_recv() {
+                Ok(message) => Ok(Some(message)),
+                Err(TryRecvError::Empty) => Ok(None),
+                Err(TryRecvError::Disconnected) => Err(disconnected()),
+            };
+        }
+        match self.read_timeout {
+            Some(read_timeout) => match self.rx.recv_timeout(read_timeout) {
+                Ok(message) => Ok(Some(message)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err(disconnected()),
+            },
+            None => self.rx.recv().map(Some).map_err(|_| disconnected()),
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.tx
+            .send(message.to_vec())
+            .map_err(|_| ErrorKind::BrokenPipe.into())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        supervisor::notify_if_panicking(self);
+    }
+}
+
+impl Display for MemoryTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "in memory: {}", self.name)
+    }
+}
+
+/// The side of a game with nobody behind it, like the wall in practice mode.
+/// Nothing ever arrives and everything sent to it is thrown away.
+#[derive(Debug)]
+pub struct NullTransport;
+
+impl Transport for NullTransport {
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn send(&mut self, _message: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Display for NullTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "nobody")
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+
+use crate::{
+    auth::sessions::SessionToken,
+    models::{packet::ServerPacket, player::NewPlayer, spectator::Spectator},
+};
+
+/// Connections handed to a running game from outside its thread, and what the admin
+/// console asks of it
+#[derive(Debug)]
+pub enum SessionRequest {
+    Reconnect(NewPlayer),
+    Spectate(Spectator),
+    /// Takes the player with this name out, the other team wins
+    Kick(String),
+    /// Stops the game without a winner
+    End,
+    /// Shows a message to everyone in the game
+    Message(String),
+}
+
+/// What other threads can see of a running game
+#[derive(Clone, Debug)]
+pub struct GameSummary {
+    pub game_id: u32,
+    pub player_names: Vec<String>,
+    /// One per team
+    pub scores: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct GameHandle {
+    summary: GameSummary,
+    player_tokens: Vec<SessionToken>,
+    request_tx: Sender<SessionRequest>,
+}
+
+/// How often a shutdown checks whether the games are done yet
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Every game that is currently running, so a dropped player can find their way back
+/// and spectators can find something to watch
+#[derive(Debug, Default)]
+pub struct GameRegistry {
+    games: Mutex<HashMap<u32, GameHandle>>,
+    next_game_id: AtomicU32,
+    /// The threads games are played on, joined when the server shuts down
+    game_threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl GameRegistry {
+    pub fn new() -> Self {
+        Self {
+            games: Mutex::new(HashMap::new()),
+            next_game_id: AtomicU32::new(1),
+            game_threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Keeps hold of a game's thread, letting go of the ones that are already done
+    pub fn add_thread(&self, game_thread: JoinHandle<()>) {
+        let mut game_threads = self.game_threads.lock().unwrap();
+        game_threads.retain(|game_thread| !game_thread.is_finished());
+        game_threads.push(game_thread);
+    }
+
+    /// Tells everyone playing, gives the games until the grace period is up to finish,
+    /// then ends whatever is left and waits for every game thread
+    pub fn shut_down(&self, grace_period: Duration) {
+        let text = if grace_period.is_zero() {
+            "The server is shutting down".to_string()
+        } else {
+            format!("The server is shutting down in {}s", grace_period.as_secs())
+        };
+        self.send_message(&text);
+
+        let deadline = Instant::now() + grace_period;
+        while !self.is_empty() && Instant::now() < deadline {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        for summary in self.list_games() {
+            info!("Ending game {} for the shutdown", summary.game_id);
+            self.end_game(summary.game_id);
+        }
+        let game_threads = mem::take(&mut *self.game_threads.lock().unwrap());
+        for game_thread in game_threads {
+            let _ = game_thread.join();
+        }
+    }
+
+    /// Registers a new game for these players, it stays listed until the session is dropped
+    pub fn create_session(self: &Arc<Self>, players: &[&NewPlayer]) -> GameSession {
+        let game_id = self.next_game_id.fetch_add(1, Ordering::Relaxed);
+        let (request_tx, request_rx) = channel::<SessionRequest>();
+        let player_tokens = players
+            .iter()
+            .filter_map(|player| player.session_token)
+            .collect();
+        let player_names = players
+            .iter()
+            .map(|player| player.player_name.clone().unwrap_or_default())
+            .collect();
+
+        self.games.lock().unwrap().insert(
+            game_id,
+            GameHandle {
+                summary: GameSummary {
+                    game_id,
+                    player_names,
+                    scores: Vec::new(),
+                },
+                player_tokens,
+                request_tx,
+            },
+        );
+
+        GameSession {
+            game_id,
+            request_rx,
+            registry: Arc::clone(self),
+        }
+    }
+
+    /// Hands the connection to the game its session belongs to, giving it back if there is none
+    pub fn try_reconnect(&self, player: NewPlayer) -> Result<u32, NewPlayer> {
+        let Some(token) = player.session_token else {
+            return Err(player);
+        };
+        let games = self.games.lock().unwrap();
+        let Some((game_id, handle)) = games
+            .iter()
+            .find(|(_, handle)| handle.player_tokens.contains(&token))
+        else {
+            return Err(player);
+        };
+
+        match handle.request_tx.send(SessionRequest::Reconnect(player)) {
+            Ok(_) => Ok(*game_id),
+            Err(e) => match e.0 {
+                SessionRequest::Reconnect(player) => Err(player),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Adds a spectator to a running game, giving it back if the game doesn't exist
+    pub fn try_spectate(&self, game_id: u32, spectator: Spectator) -> Result<(), Spectator> {
+        let games = self.games.lock().unwrap();
+        let Some(handle) = games.get(&game_id) else {
+            return Err(spectator);
+        };
+
+        match handle.request_tx.send(SessionRequest::Spectate(spectator)) {
+            Ok(_) => Ok(()),
+            Err(e) => match e.0 {
+                SessionRequest::Spectate(spectator) => Err(spectator),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Asks the game this player is in to kick them, returns the game's id if there is one
+    pub fn kick(&self, player_name: &str) -> Option<u32> {
+        let games = self.games.lock().unwrap();
+        let (game_id, handle) = games.iter().find(|(_, handle)| {
+            handle
+                .summary
+                .player_names
+                .iter()
+                .any(|name| name == player_name)
+        })?;
+        let request = SessionRequest::Kick(player_name.to_string());
+        handle.request_tx.send(request).ok().map(|_| *game_id)
+    }
+
+    /// Asks a game to stop, returns false if there is no such game
+    pub fn end_game(&self, game_id: u32) -> bool {
+        let games = self.games.lock().unwrap();
+        games
+            .get(&game_id)
+            .is_some_and(|handle| handle.request_tx.send(SessionRequest::End).is_ok())
+    }
+
+    /// Shows the message in every running game, returns how many got it
+    pub fn send_message(&self, text: &str) -> usize {
+        let games = self.games.lock().unwrap();
+        games
+            .values()
+            .filter(|handle| {
+                let request = SessionRequest::Message(text.to_string());
+                handle.request_tx.send(request).is_ok()
+            })
+            .count()
+    }
+
+    pub fn list_games(&self) -> Vec<GameSummary> {
+        let mut summaries: Vec<GameSummary> = self
+            .games
+            .lock()
+            .unwrap()
+            .values()
+            .map(|handle| handle.summary.clone())
+            .collect();
+        summaries.sort_by_key(|summary| summary.game_id);
+        summaries
+    }
+
+    /// `[id, count, (game id u32, score count, scores.., name count, (name length, name)..)..]`
+    pub fn create_game_list_packet(&self) -> Vec<u8> {
+        let summaries = self.list_games();
+        let mut v_data: Vec<u8> = vec![
+            ServerPacket::GameList as u8,
+            summaries.len().min(u8::MAX as usize) as u8,
+        ];
+        for summary in summaries.iter().take(u8::MAX as usize) {
+            v_data.extend_from_slice(&summary.game_id.to_le_bytes());
+            v_data.push(summary.scores.len() as u8);
+            v_data.extend_from_slice(&summary.scores);
+            v_data.push(summary.player_names.len() as u8);
+            for name in &summary.player_names {
+                v_data.push(name.len() as u8);
+                v_data.extend_from_slice(name.as_bytes());
+            }
+        }
+        v_data
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn update_scores(&self, game_id: u32, scores: &[u8]) {
+        if let Some(handle) = self.games.lock().unwrap().get_mut(&game_id) {
+            handle.summary.scores = scores.to_vec();
+        }
+    }
+
+    fn remove(&self, game_id: u32) {
+        debug!("Removing game {} from the registry", game_id);
+        self.games.lock().unwrap().remove(&game_id);
+    }
+}
+
+/// Owned by the game thread, removes the game from the registry when it goes away
+#[derive(Debug)]
+pub struct GameSession {
+    pub game_id: u32,
+    pub request_rx: Receiver<SessionRequest>,
+    registry: Arc<GameRegistry>,
+}
+
+impl GameSession {
+    pub fn report_scores(&self, scores: &[u8]) {
+        self.registry.update_scores(self.game_id, scores);
+    }
+}
+
+impl Drop for GameSession {
+    fn drop(&mut self) {
+        self.registry.remove(self.game_id);
+    }
+}
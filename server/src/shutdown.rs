@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{info, warn};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ctrl-C / SIGTERM stop the server taking new players and wind down the games it has,
+/// instead of killing it with everyone still connected
+pub fn install_handlers() {
+    if let Err(e) = ctrlc::set_handler(request_shutdown) {
+        warn!("Failed to install signal handler: {:?}", e);
+    }
+}
+
+pub fn request_shutdown() {
+    if !SHUTDOWN_REQUESTED.swap(true, Ordering::SeqCst) {
+        info!("Shutdown requested");
+    }
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{info, warn};
+
+/// What is kept for every player who has played something worth remembering
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub practice_best: u32,
+    pub series_won: u32,
+    pub series_lost: u32,
+}
+
+/// Personal bests in practice mode and series records, persisted one per line as
+/// `username best_streak series_won series_lost`. Older files only have the first two.
+#[derive(Debug)]
+pub struct StatsStore {
+    path: PathBuf,
+    players: Mutex<HashMap<String, PlayerStats>>,
+}
+
+impl StatsStore {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut players = HashMap::new();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match Self::parse_line(&line) {
+                        Some((username, stats)) => {
+                            players.insert(username, stats);
+                        }
+                        None => warn!("Skipping malformed stats entry: {:?}", line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        info!("Loaded stats for {} players", players.len());
+        Ok(Self {
+            path,
+            players: Mutex::new(players),
+        })
+    }
+
+    pub fn player(&self, username: &str) -> PlayerStats {
+        self.players
+            .lock()
+            .unwrap()
+            .get(username)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn practice_best(&self, username: &str) -> u32 {
+        self.player(username).practice_best
+    }
+
+    /// Keeps the streak if it beats the player's best, returns whether it did
+    pub fn record_practice(&self, username: &str, streak: u32) -> io::Result<bool> {
+        let mut players = self.players.lock().unwrap();
+        let stats = players.entry(username.to_string()).or_default();
+        if streak <= stats.practice_best {
+            return Ok(false);
+        }
+        stats.practice_best = streak;
+        self.save(&players)?;
+        Ok(true)
+    }
+
+    pub fn record_series(&self, winner: &str, loser: &str) -> io::Result<()> {
+        let mut players = self.players.lock().unwrap();
+        players.entry(winner.to_string()).or_default().series_won += 1;
+        players.entry(loser.to_string()).or_default().series_lost += 1;
+        self.save(&players)
+    }
+
+    /// Small enough to rewrite whole, a result only ever replaces the line before it
+    fn save(&self, players: &HashMap<String, PlayerStats>) -> io::Result<()> {
+        let contents: String = players
+            .iter()
+            .map(|(username, stats)| {
+                format!(
+                    "{} {} {} {}\n",
+                    username, stats.practice_best, stats.series_won, stats.series_lost
+                )
+            })
+            .collect();
+        fs::write(&self.path, contents)
+    }
+
+    /// Writes everything out again, in case a save along the way failed
+    pub fn flush(&self) -> io::Result<()> {
+        let players = self.players.lock().unwrap();
+        if players.is_empty() {
+            return Ok(());
+        }
+        self.save(&players)
+    }
+
+    fn parse_line(line: &str) -> Option<(String, PlayerStats)> {
+        let mut parts = line.split_whitespace();
+        let username = parts.next()?.to_string();
+        let practice_best = parts.next()?.parse().ok()?;
+        let series_won = parts.next().map_or(Some(0), |won| won.parse().ok())?;
+        let series_lost = parts.next().map_or(Some(0), |lost| lost.parse().ok())?;
+        Some((
+            username,
+            PlayerStats {
+                practice_best,
+                series_won,
+                series_lost,
+            },
+        ))
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::Duration,
+};
+
+use log::error;
+
+use crate::models::{packet::message_packet, transport::Transport};
+
+/// What a connection hears when the thread holding it panics
+pub const CRASH_NOTICE: &str = "The server ran into a problem, please connect again";
+
+/// What a panic was about, for the log
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// Called as a connection is dropped. If that's because its thread is panicking, whoever
+/// was handling it can't say goodbye anymore, so the connection says it instead.
+pub fn notify_if_panicking(transport: &mut impl Transport) {
+    if thread::panicking() {
+        let _ = transport.send(&message_packet(CRASH_NOTICE));
+    }
+}
+
+/// Runs `work` again whenever it panics, until it returns on its own. Whatever it works
+/// on has to survive being interrupted half way, since it carries on from there.
+pub fn restart_on_panic(name: &str, restart_delay: Duration, mut work: impl FnMut()) {
+    while let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut work)) {
+        error!(
+            "{} panicked: {}, restarting in {:?}",
+            name,
+            panic_message(&*payload),
+            restart_delay
+        );
+        thread::sleep(restart_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transport::MemoryTransport;
+
+    #[test]
+    fn restarts_until_the_work_is_done() {
+        let mut runs = 0;
+        restart_on_panic("Test", Duration::ZERO, || {
+            runs += 1;
+            if runs < 3 {
+                panic!("run {}", runs);
+            }
+        });
+        assert_eq!(runs, 3);
+    }
+
+    #[test]
+    fn connections_dropped_by_a_panic_are_told_why() {
+        let (server_end, mut client_end) = MemoryTransport::pair("alice");
+        let mut queued = Some(server_end);
+        restart_on_panic("Test", Duration::ZERO, || {
+            if let Some(player) = queued.take() {
+                let _handling = player;
+                panic!("lost the queue");
+            }
+        });
+        assert_eq!(
+            client_end.receive().unwrap(),
+            Some(message_packet(CRASH_NOTICE))
+        );
+
+        // Nothing to say when the connection is just done with
+        let (server_end, mut client_end) = MemoryTransport::pair("bobby");
+        drop(server_end);
+        assert!(client_end.receive().is_err());
+    }
+
+    #[test]
+    fn panic_messages_are_read_either_way() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "static");
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "formatted 1");
+    }
+}
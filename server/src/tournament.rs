@@ -0,0 +1,647 @@
+use std::{collections::HashSet, fs, io, path::Path, sync::Mutex};
+
+use log::info;
+use thiserror::Error;
+
+use crate::{auth::Authenticator, models::packet::ServerPacket};
+
+/// Brackets are sent with a byte per entrant
+pub const MAX_ENTRANTS: usize = 64;
+/// Stands in for an entrant in the bracket packet
+const BYE: u8 = 0xFE;
+const UNDECIDED: u8 = 0xFF;
+
+#[derive(Error, Debug)]
+pub enum TournamentError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+
+    #[error("A tournament needs 2 to {} players", MAX_ENTRANTS)]
+    WrongPlayerCount,
+
+    #[error("{0} doesn't have an account")]
+    UnknownPlayer(String),
+
+    #[error("{0} is entered twice")]
+    DuplicatePlayer(String),
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TournamentFormat {
+    /// One loss and you're out
+    Single = 0,
+    /// Losing once drops you into the losers' bracket, losing twice is out
+    Double = 1,
+}
+
+impl TournamentFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "single" => Some(Self::Single),
+            "double" => Some(Self::Double),
+            _ => None,
+        }
+    }
+}
+
+/// Sent with every tournament packet, only `Bracket` and `Waiting` come with the bracket
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TournamentStatus {
+    Bracket = 0,
+    /// Entered and still in, the next match starts once both players are here
+    Waiting = 1,
+    /// Not entered, or already knocked out
+    Out = 2,
+    NoTournament = 3,
+}
+
+/// Which part of the bracket a match is in
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BracketSide {
+    Winners = 0,
+    Losers = 1,
+    /// The grand final, and the rematch if the losers' bracket champion wins it
+    Final = 2,
+}
+
+/// Where a side of a match gets its player from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    Seed(usize),
+    Winner(usize),
+    Loser(usize),
+}
+
+/// Someone in a match, by seed, or nobody when the bracket isn't full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entrant {
+    Player(usize),
+    Bye,
+}
+
+#[derive(Debug)]
+pub struct BracketMatch {
+    pub side: BracketSide,
+    /// Counted from 1 on each side of the bracket
+    pub round: u8,
+    sources: [Source; 2],
+    pub winner: Option<Entrant>,
+    /// Being played right now, so it isn't started twice
+    pub in_progress: bool,
+    /// Only played if whoever came up through the losers' bracket won this match
+    rematch_of: Option<usize>,
+}
+
+impl BracketMatch {
+    fn new(side: BracketSide, round: u8, sources: [Source; 2]) -> Self {
+        Self {
+            side,
+            round,
+            sources,
+            winner: None,
+            in_progress: false,
+            rematch_of: None,
+        }
+    }
+}
+
+/// Every match of a tournament, earlier rounds first. Matches only ever take their players
+/// from ones before them, byes are walked over as soon as they turn up.
+#[derive(Debug)]
+pub struct Bracket {
+    pub format: TournamentFormat,
+    /// In seed order, the first is the top seed
+    pub players: Vec<String>,
+    pub matches: Vec<BracketMatch>,
+}
+
+impl Bracket {
+    pub fn seed(format: TournamentFormat, players: Vec<String>) -> Result<Self, TournamentError> {
+        if !(2..=MAX_ENTRANTS).contains(&players.len()) {
+            return Err(TournamentError::WrongPlayerCount);
+        }
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = players.iter().find(|player| !seen.insert(*player)) {
+            return Err(TournamentError::DuplicatePlayer(duplicate.clone()));
+        }
+
+        let size = players.len().next_power_of_two();
+        let mut bracket = Self {
+            format,
+            players,
+            matches: Vec::new(),
+        };
+        let winners_rounds = bracket.seed_winners(size);
+        let champion = Source::Winner(*winners_rounds.last().unwrap().last().unwrap());
+        if format == TournamentFormat::Double {
+            bracket.seed_losers_and_final(&winners_rounds, champion);
+        }
+        bracket.settle_walkovers();
+        Ok(bracket)
+    }
+
+    /// Top seeds meet bottom seeds, so the best two can only meet in the final and the
+    /// byes go to whoever is seeded highest
+    fn seed_winners(&mut self, size: usize) -> Vec<Vec<usize>> {
+        let mut order = vec![0];
+        while order.len() < size {
+            let length = order.len() * 2;
+            order = order
+                .into_iter()
+                .flat_map(|seed| [seed, length - 1 - seed])
+                .collect();
+        }
+
+        let mut rounds: Vec<Vec<usize>> = Vec::new();
+        let first_round = order
+            .chunks(2)
+            .map(|pair| {
+                self.add_match(BracketMatch::new(
+                    BracketSide::Winners,
+                    1,
+                    [Source::Seed(pair[0]), Source::Seed(pair[1])],
+                ))
+            })
+            .collect();
+        rounds.push(first_round);
+        while rounds.last().unwrap().len() > 1 {
+            let round = rounds.len() as u8 + 1;
+            let next_round = rounds
+                .last()
+                .unwrap()
+                .clone()
+                .chunks(2)
+                .map(|pair| {
+                    self.add_match(BracketMatch::new(
+                        BracketSide::Winners,
+                        round,
+                        [Source::Winner(pair[0]), Source::Winner(pair[1])],
+                    ))
+                })
+                .collect();
+            rounds.push(next_round);
+        }
+        rounds
+    }
+
+    /// The first losers' round pairs up everyone who lost straight away, after that each
+    /// winners' round drops its losers in against whoever is left, in reverse order so
+    /// they don't meet the same player again too soon
+    fn seed_losers_and_final(&mut self, winners_rounds: &[Vec<usize>], champion: Source) {
+        let mut round = 1;
+        let mut survivors: Vec<Source> = winners_rounds[0]
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| {
+                Source::Winner(self.add_match(BracketMatch::new(
+                    BracketSide::Losers,
+                    round,
+                    [Source::Loser(pair[0]), Source::Loser(pair[1])],
+                )))
+            })
+            .collect();
+        if survivors.is_empty() {
+            // Only two players, whoever loses the first match goes straight to the final
+            survivors.push(Source::Loser(winners_rounds[0][0]));
+        }
+
+        for winners_round in &winners_rounds[1..] {
+            round += 1;
+            let dropped = winners_round.iter().rev().map(|idx| Source::Loser(*idx));
+            survivors = survivors
+                .clone()
+                .into_iter()
+                .zip(dropped)
+                .map(|(survivor, dropped)| {
+                    Source::Winner(self.add_match(BracketMatch::new(
+                        BracketSide::Losers,
+                        round,
+                        [survivor, dropped],
+                    )))
+                })
+                .collect();
+            if survivors.len() > 1 {
+                round += 1;
+                survivors = survivors
+                    .clone()
+                    .chunks(2)
+                    .map(|pair| {
+                        Source::Winner(self.add_match(BracketMatch::new(
+                            BracketSide::Losers,
+                            round,
+                            [pair[0], pair[1]],
+                        )))
+                    })
+                    .collect();
+            }
+        }
+
+        let sources = [champion, survivors[0]];
+        let final_idx = self.add_match(BracketMatch::new(BracketSide::Final, 1, sources));
+        let mut rematch = BracketMatch::new(BracketSide::Final, 2, sources);
+        rematch.rematch_of = Some(final_idx);
+        self.add_match(rematch);
+    }
+
+    fn add_match(&mut self, bracket_match: BracketMatch) -> usize {
+        self.matches.push(bracket_match);
+        self.matches.len() - 1
+    }
+
+    fn entrant(&self, source: Source) -> Option<Entrant> {
+        match source {
+            Source::Seed(seed) if seed < self.players.len() => Some(Entrant::Player(seed)),
+            Source::Seed(_) => Some(Entrant::Bye),
+            Source::Winner(idx) => self.matches[idx].winner,
+            Source::Loser(idx) => {
+                let winner = self.matches[idx].winner?;
+                let [first, second] = self.sides(idx);
+                if Some(winner) == first { second } else { first }
+            }
+        }
+    }
+
+    /// Who plays in a match, as far as the bracket has got
+    pub fn sides(&self, match_idx: usize) -> [Option<Entrant>; 2] {
+        self.matches[match_idx]
+            .sources
+            .map(|source| self.entrant(source))
+    }
+
+    /// Byes and rematches nobody needs are decided without playing them
+    fn settle_walkovers(&mut self) {
+        for match_idx in 0..self.matches.len() {
+            if self.matches[match_idx].winner.is_some() {
+                continue;
+            }
+            let [Some(first), Some(second)] = self.sides(match_idx) else {
+                continue;
+            };
+            let walkover = match (first, second, self.matches[match_idx].rematch_of) {
+                (_, _, Some(final_idx)) => match self.matches[final_idx].winner {
+                    // The winners' bracket champion hasn't lost yet, one final is enough
+                    Some(winner) if winner == first => Some(first),
+                    _ => None,
+                },
+                (Entrant::Bye, other, _) | (other, Entrant::Bye, _) => Some(other),
+                _ => None,
+            };
+            self.matches[match_idx].winner = walkover;
+        }
+    }
+
+    /// The players of a match that can start, `None` if it can't yet
+    pub fn playable(&self, match_idx: usize) -> Option<[&str; 2]> {
+        let bracket_match = &self.matches[match_idx];
+        if bracket_match.winner.is_some() || bracket_match.in_progress {
+            return None;
+        }
+        match self.sides(match_idx) {
+            [Some(Entrant::Player(first)), Some(Entrant::Player(second))] => {
+                Some([&self.players[first], &self.players[second]])
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the player still has a match to play, even if their opponent isn't known yet
+    pub fn still_in(&self, player_name: &str) -> bool {
+        let Some(seed) = self.players.iter().position(|player| player == player_name) else {
+            return false;
+        };
+        let player = Some(Entrant::Player(seed));
+        let losses = (0..self.matches.len())
+            .filter(|idx| {
+                let winner = self.matches[*idx].winner;
+                winner.is_some() && winner != player && self.sides(*idx).contains(&player)
+            })
+            .count();
+        let allowed = match self.format {
+            TournamentFormat::Single => 1,
+            TournamentFormat::Double => 2,
+        };
+        self.champion().is_none() && losses < allowed
+    }
+
+    /// Whoever won the last match, once it has been played
+    pub fn champion(&self) -> Option<&str> {
+        match self.matches.last()?.winner? {
+            Entrant::Player(seed) => Some(&self.players[seed]),
+            Entrant::Bye => None,
+        }
+    }
+
+    /// `side` is which of the match's players won, with none the match is played again
+    pub fn report(&mut self, match_idx: usize, side: Option<usize>) {
+        self.matches[match_idx].in_progress = false;
+        if let Some(side) = side
+            && let Some(winner) = self.sides(match_idx)[side]
+        {
+            self.matches[match_idx].winner = Some(winner);
+            self.settle_walkovers();
+        }
+    }
+
+    /// `[format, player count, (name length, name)..,
+    /// match count, (side, round, first, second, winner)..]`, with entrants by seed
+    fn as_bytes(&self) -> Vec<u8> {
+        let entrant_byte = |entrant: Option<Entrant>| match entrant {
+            Some(Entrant::Player(seed)) => seed as u8,
+            Some(Entrant::Bye) => BYE,
+            None => UNDECIDED,
+        };
+        let mut v_data: Vec<u8> = vec![self.format as u8, self.players.len() as u8];
+        for player in &self.players {
+            v_data.push(player.len() as u8);
+            v_data.extend_from_slice(player.as_bytes());
+        }
+        v_data.push(self.matches.len() as u8);
+        for (match_idx, bracket_match) in self.matches.iter().enumerate() {
+            let [first, second] = self.sides(match_idx);
+            v_data.extend_from_slice(&[
+                bracket_match.side as u8,
+                bracket_match.round,
+                entrant_byte(first),
+                entrant_byte(second),
+                entrant_byte(bracket_match.winner),
+            ]);
+        }
+        v_data
+    }
+}
+
+/// The server's tournament, shared between the matchmaker that starts its matches, the
+/// games that finish them and anyone asking how it's going
+#[derive(Debug, Default)]
+pub struct Tournaments {
+    bracket: Mutex<Option<Bracket>>,
+}
+
+impl Tournaments {
+    pub fn new() -> Self {
+        Self {
+            bracket: Mutex::new(None),
+        }
+    }
+
+    /// No file means no tournament. Everyone entered has to have an account already.
+    pub fn load(
+        path: impl AsRef<Path>,
+        authenticator: &Authenticator,
+    ) -> Result<Self, TournamentError> {
+        let tournaments = Self::new();
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let (format, players) = Self::parse(&contents)?;
+                if let Some(unknown) = players
+                    .iter()
+                    .find(|player| !authenticator.is_registered(player))
+                {
+                    return Err(TournamentError::UnknownPlayer(unknown.clone()));
+                }
+                tournaments.start(Bracket::seed(format, players)?);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(tournaments)
+    }
+
+    /// The format on the first line, then a player per line, top seed first:
+    ///
+    /// ```text
+    /// # single or double elimination
+    /// double
+    /// alice
+    /// bobby
+    /// carol
+    /// ```
+    pub fn parse(contents: &str) -> Result<(TournamentFormat, Vec<String>), TournamentError> {
+        let mut format: Option<TournamentFormat> = None;
+        let mut players: Vec<String> = Vec::new();
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if format.is_none() {
+                format = Some(TournamentFormat::from_name(line).ok_or(
+                    TournamentError::Malformed {
+                        line: idx + 1,
+                        reason: "expected single or double",
+                    },
+                )?);
+                continue;
+            }
+            players.push(line.to_string());
+        }
+        let format = format.ok_or(TournamentError::WrongPlayerCount)?;
+        Ok((format, players))
+    }
+
+    /// Replaces whatever tournament was running
+    pub fn start(&self, bracket: Bracket) {
+        info!(
+            "Starting a {:?} elimination tournament for {}",
+            bracket.format,
+            bracket.players.join(", ")
+        );
+        *self.bracket.lock().unwrap() = Some(bracket);
+    }
+
+    pub fn still_in(&self, player_name: &str) -> bool {
+        self.bracket
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|bracket| bracket.still_in(player_name))
+    }
+
+    /// Marks the first match that can start with both players here as being played,
+    /// returning it and its players in seed order
+    pub fn start_match(&self, present: &[&str]) -> Option<(usize, [String; 2])> {
+        let mut bracket = self.bracket.lock().unwrap();
+        let bracket = bracket.as_mut()?;
+        let (match_idx, players) = (0..bracket.matches.len()).find_map(|idx| {
+            let players = bracket.playable(idx)?;
+            players
+                .iter()
+                .all(|player| present.contains(player))
+                .then(|| (idx, players.map(str::to_string)))
+        })?;
+        bracket.matches[match_idx].in_progress = true;
+        Some((match_idx, players))
+    }
+
+    /// Called once a match's game is over, team 1 is the first player. A game nobody won
+    /// is played again.
+    pub fn report(&self, match_idx: usize, winner: u8) {
+        let mut bracket = self.bracket.lock().unwrap();
+        let Some(bracket) = bracket.as_mut() else {
+            return;
+        };
+        let side = (winner as usize).checked_sub(1).filter(|side| *side < 2);
+        bracket.report(match_idx, side);
+        match bracket.champion() {
+            Some(champion) => info!("{} won the tournament", champion),
+            None => info!(
+                "Tournament match {} over, winner: team {}",
+                match_idx, winner
+            ),
+        }
+    }
+
+    /// `[id, status, bracket..]`, see `Bracket::as_bytes`. Without a tournament, or with
+    /// one of the other statuses, it's just `[id, status]`.
+    pub fn packet(&self, status: TournamentStatus) -> Vec<u8> {
+        let bracket = self.bracket.lock().unwrap();
+        let mut v_data: Vec<u8> = vec![ServerPacket::Tournament as u8];
+        match bracket.as_ref() {
+            Some(bracket)
+                if matches!(
+                    status,
+                    TournamentStatus::Bracket | TournamentStatus::Waiting
+                ) =>
+            {
+                v_data.push(status as u8);
+                v_data.extend_from_slice(&bracket.as_bytes());
+            }
+            Some(_) => v_data.push(status as u8),
+            None => v_data.push(TournamentStatus::NoTournament as u8),
+        }
+        v_data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(count: usize) -> Vec<String> {
+        (1..=count).map(|seed| format!("player{}", seed)).collect()
+    }
+
+    /// Plays the first match that can be played, the higher seed winning it
+    fn play_next(bracket: &mut Bracket) -> bool {
+        let Some(match_idx) =
+            (0..bracket.matches.len()).find(|idx| bracket.playable(*idx).is_some())
+        else {
+            return false;
+        };
+        let side = match bracket.sides(match_idx) {
+            [Some(Entrant::Player(first)), Some(Entrant::Player(second))] if second < first => 1,
+            _ => 0,
+        };
+        bracket.report(match_idx, Some(side));
+        true
+    }
+
+    fn play_out(bracket: &mut Bracket) -> usize {
+        let mut played = 0;
+        while play_next(bracket) {
+            played += 1;
+        }
+        played
+    }
+
+    #[test]
+    fn top_seeds_get_the_byes() {
+        let bracket = Bracket::seed(TournamentFormat::Single, names(6)).unwrap();
+        assert_eq!(bracket.matches.len(), 7);
+        // Seeds 1 and 2 would have played 8 and 7, who aren't there
+        assert_eq!(bracket.matches[0].winner, Some(Entrant::Player(0)));
+        assert_eq!(bracket.playable(1), Some(["player4", "player5"]));
+        assert_eq!(bracket.matches[2].winner, Some(Entrant::Player(1)));
+        assert_eq!(bracket.playable(3), Some(["player3", "player6"]));
+    }
+
+    #[test]
+    fn single_elimination_plays_every_match_once() {
+        let mut bracket = Bracket::seed(TournamentFormat::Single, names(8)).unwrap();
+        assert_eq!(play_out(&mut bracket), 7);
+        assert_eq!(bracket.champion(), Some("player1"));
+        assert!(!bracket.still_in("player1"));
+    }
+
+    #[test]
+    fn double_elimination_takes_two_losses() {
+        let mut bracket = Bracket::seed(TournamentFormat::Double, names(4)).unwrap();
+        // The first round, then seed 4 loses a second time before seed 3 does
+        bracket.report(0, Some(1));
+        assert!(bracket.still_in("player1"));
+        bracket.report(1, Some(0));
+        let losers_first = (0..bracket.matches.len())
+            .find(|idx| {
+                bracket.playable(*idx).is_some()
+                    && bracket.matches[*idx].side == BracketSide::Losers
+            })
+            .unwrap();
+        assert_eq!(bracket.playable(losers_first), Some(["player1", "player3"]));
+        bracket.report(losers_first, Some(1));
+        assert!(!bracket.still_in("player1"));
+        assert!(bracket.still_in("player3"));
+    }
+
+    #[test]
+    fn the_final_is_replayed_when_the_losers_bracket_wins_it() {
+        for (final_winner, games) in [(0, 6), (1, 7)] {
+            let mut bracket = Bracket::seed(TournamentFormat::Double, names(4)).unwrap();
+            let final_idx = bracket.matches.len() - 2;
+            let mut played = 0;
+            while bracket.playable(final_idx).is_none() {
+                assert!(play_next(&mut bracket));
+                played += 1;
+            }
+            assert_eq!(bracket.playable(final_idx), Some(["player1", "player2"]));
+            bracket.report(final_idx, Some(final_winner));
+            played += 1 + play_out(&mut bracket);
+            assert_eq!(played, games);
+            assert_eq!(bracket.champion(), Some("player1"));
+        }
+    }
+
+    #[test]
+    fn two_players_can_have_a_double_elimination() {
+        let mut bracket = Bracket::seed(TournamentFormat::Double, names(2)).unwrap();
+        bracket.report(0, Some(1));
+        assert!(bracket.still_in("player1"));
+        assert_eq!(bracket.playable(1), Some(["player2", "player1"]));
+    }
+
+    #[test]
+    fn games_without_a_winner_are_played_again() {
+        let mut bracket = Bracket::seed(TournamentFormat::Single, names(2)).unwrap();
+        bracket.matches[0].in_progress = true;
+        assert_eq!(bracket.playable(0), None);
+        bracket.report(0, None);
+        assert_eq!(bracket.playable(0), Some(["player1", "player2"]));
+    }
+
+    #[test]
+    fn tournament_files_need_a_format_and_players() {
+        let (format, players) = Tournaments::parse("# office\ndouble\nalice\n\nbobby\n").unwrap();
+        assert_eq!(format, TournamentFormat::Double);
+        assert_eq!(players, ["alice", "bobby"]);
+        assert!(matches!(
+            Tournaments::parse("triple\nalice\n"),
+            Err(TournamentError::Malformed { line: 1, .. })
+        ));
+        assert!(matches!(
+            Bracket::seed(TournamentFormat::Single, names(1)),
+            Err(TournamentError::WrongPlayerCount)
+        ));
+        assert!(matches!(
+            Bracket::seed(
+                TournamentFormat::Single,
+                vec!["alice".into(), "alice".into()]
+            ),
+            Err(TournamentError::DuplicatePlayer(_))
+        ));
+    }
+}
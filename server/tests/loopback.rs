@@ -0,0 +1,751 @@
+//! Starts the real server binary on a free port and plays a game against it over TCP
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Message and packet ids, as laid out in `MessageType` and `ServerPacket`
+const PLAYER_POS: u8 = 0;
+const LOGIN: u8 = 2;
+const REGISTER: u8 = 3;
+const FORFEIT: u8 = 7;
+const SELECT_MODE: u8 = 11;
+const CREATE_LOBBY: u8 = 12;
+const JOIN_LOBBY: u8 = 13;
+const LOBBY_RULES: u8 = 14;
+const READY: u8 = 15;
+const BRACKET: u8 = 16;
+const JOIN_TOURNAMENT: u8 = 17;
+const SNAPSHOT: u8 = 0;
+const UPDATE: u8 = 1;
+const AUTH_RESULT: u8 = 2;
+const GAME_OVER: u8 = 4;
+const LOBBY: u8 = 10;
+const TOURNAMENT: u8 = 11;
+const MESSAGE: u8 = 13;
+const STANDARD_MODE: u8 = 0;
+const PRACTICE_MODE: u8 = 1;
+const DOUBLES_MODE: u8 = 2;
+const SERIES_MODE: u8 = 6;
+
+const MAP_WIDTH: u8 = 80;
+const MAP_HEIGHT: u8 = 30;
+const PADDLE_SIZE: u8 = 4;
+const BALL_SPEED: u8 = 3;
+const BEST_OF: u8 = 1;
+
+/// The server's standard input and whatever it prints after saying where it listens
+struct Console {
+    input: ChildStdin,
+    lines: Receiver<String>,
+}
+
+/// Kills the server when the test ends, passing or not
+struct ServerProcess {
+    child: Child,
+    address: String,
+    data_dir: PathBuf,
+    /// Passed on every start, on top of where to bind and keep the data
+    args: Vec<&'static str>,
+    console: Console,
+}
+
+impl ServerProcess {
+    fn start(name: &str) -> Self {
+        Self::start_with_rules(name, None)
+    }
+
+    /// Writes the rules file into the data directory before the server reads it
+    fn start_with_rules(name: &str, rules: Option<&str>) -> Self {
+        Self::start_with(name, rules, Vec::new())
+    }
+
+    fn start_with(name: &str, rules: Option<&str>, args: Vec<&'static str>) -> Self {
+        let data_dir = env::temp_dir().join(format!("ping-pong-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        if let Some(rules) = rules {
+            fs::create_dir_all(&data_dir).unwrap();
+            fs::write(data_dir.join("rules.txt"), rules).unwrap();
+        }
+
+        let (child, address, console) = Self::spawn(&data_dir, &args);
+        Self {
+            child,
+            address,
+            data_dir,
+            args,
+            console,
+        }
+    }
+
+    fn spawn(data_dir: &PathBuf, args: &[&str]) -> (Child, String, Console) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--bind", "127.0.0.1:0", "--data-dir"])
+            .arg(data_dir)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start the server");
+
+        let stdout = child.stdout.take().unwrap();
+        let mut lines = BufReader::new(stdout).lines().map_while(Result::ok);
+        let address = lines
+            .find_map(|line| line.strip_prefix("Listening on ").map(str::to_string))
+            .expect("server never said where it is listening");
+        // Read on their own thread, so a test waiting on an answer can give up
+        let (line_tx, line_rx) = mpsc::channel();
+        thread::spawn(move || lines.try_for_each(|line| line_tx.send(line)));
+        let console = Console {
+            input: child.stdin.take().unwrap(),
+            lines: line_rx,
+        };
+        (child, address, console)
+    }
+
+    /// Starts over on the same data directory, for files that are only read on startup
+    fn restart(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        (self.child, self.address, self.console) = Self::spawn(&self.data_dir, &self.args);
+    }
+
+    /// Waits for the server to exit by itself, returns whether it was happy about it
+    fn wait_for_exit(&mut self) -> bool {
+        let deadline = Instant::now() + READ_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status.success();
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("server is still running");
+    }
+
+    /// Types a command into the admin console, returns the first line of the answer that
+    /// has `expected` in it
+    fn admin(&mut self, command: &str, expected: &str) -> String {
+        writeln!(self.console.input, "{}", command).unwrap();
+        self.printed(expected)
+            .unwrap_or_else(|| panic!("{:?} never answered with {:?}", command, expected))
+    }
+
+    /// The next line the server prints with `expected` in it
+    fn printed(&mut self, expected: &str) -> Option<String> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            match self.console.lines.recv_timeout(timeout) {
+                Ok(line) if line.contains(expected) => return Some(line),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// Speaks the length prefixed protocol the same way the client does
+struct ProtocolClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl ProtocolClient {
+    fn connect(address: &str) -> Self {
+        let stream = TcpStream::connect(address).expect("failed to connect");
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, payload: &[u8]) {
+        let mut frame = (payload.len() as u16).to_le_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn receive(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if self.buffer.len() >= 2 {
+                let length = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+                if self.buffer.len() >= length + 2 {
+                    let frame = self.buffer[2..length + 2].to_vec();
+                    self.buffer.drain(..length + 2);
+                    return Ok(frame);
+                }
+            }
+            let mut buf = [0; 1024];
+            match self.stream.read(&mut buf)? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n => self.buffer.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Skips everything until a packet of this type shows up
+    fn receive_packet(&mut self, packet_id: u8) -> Vec<u8> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+        while Instant::now() < deadline {
+            let packet = self.receive().expect("connection closed early");
+            if packet[0] == packet_id {
+                return packet;
+            }
+        }
+        panic!("no packet {} arrived", packet_id);
+    }
+
+    fn register(address: &str, username: &str) -> Self {
+        Self::register_for_mode(address, username, STANDARD_MODE)
+    }
+
+    fn register_for_mode(address: &str, username: &str, mode: u8) -> Self {
+        Self::register_with(address, username, &[SELECT_MODE, mode])
+    }
+
+    /// Sends a request ahead of registering, the way the client picks a mode or lobby
+    fn register_with(address: &str, username: &str, request: &[u8]) -> Self {
+        let mut client = Self::connect(address);
+        client.send(request);
+        let mut register = vec![REGISTER, username.len() as u8];
+        register.extend_from_slice(username.as_bytes());
+        register.extend_from_slice(b"hunter22");
+        client.send(&register);
+
+        let auth_result = client.receive_packet(AUTH_RESULT);
+        assert_eq!(auth_result[1], 0, "registering {} failed", username);
+        assert_eq!(auth_result.len(), 2 + 16, "expected a session token");
+        client
+    }
+
+    /// Logs in to an account registered earlier, the password is always the same one
+    fn login_with(address: &str, username: &str, request: &[u8]) -> Self {
+        let mut client = Self::connect(address);
+        client.send(request);
+        let mut login = vec![LOGIN, username.len() as u8];
+        login.extend_from_slice(username.as_bytes());
+        login.extend_from_slice(b"hunter22");
+        client.send(&login);
+
+        let auth_result = client.receive_packet(AUTH_RESULT);
+        assert_eq!(auth_result[1], 0, "logging in as {} failed", username);
+        assert_eq!(auth_result.len(), 2 + 16, "expected a session token");
+        client
+    }
+}
+
+#[test]
+fn two_players_play_a_match_to_completion() {
+    let server = ServerProcess::start("match");
+    let mut player_1 = ProtocolClient::register(&server.address, "alice");
+    let mut player_2 = ProtocolClient::register(&server.address, "bobby");
+
+    // [id, player, own paddle, other paddle, ball x, ball y, width, height, paddle size, seed, mode]
+    let snapshot_1 = player_1.receive_packet(SNAPSHOT);
+    let snapshot_2 = player_2.receive_packet(SNAPSHOT);
+    let center = MAP_HEIGHT / 2;
+    assert_eq!(
+        snapshot_1[..9],
+        [
+            SNAPSHOT,
+            1,
+            center,
+            center,
+            MAP_WIDTH / 2,
+            center,
+            MAP_WIDTH,
+            MAP_HEIGHT,
+            PADDLE_SIZE
+        ]
+    );
+    // Player 2 sees the map mirrored, so they are on the left as well
+    assert_eq!(
+        snapshot_2[..9],
+        [
+            SNAPSHOT,
+            2,
+            center,
+            center,
+            MAP_WIDTH - MAP_WIDTH / 2 - 1,
+            center,
+            MAP_WIDTH,
+            MAP_HEIGHT,
+            PADDLE_SIZE
+        ]
+    );
+    // Then every paddle as [player, team, side, line, position], in each player's own view,
+    // no balls besides the first, an empty arena with the whole end walls as goals and the
+    // ball's speed
+    assert_eq!(snapshot_1.len(), 9 + 8 + 1 + 1 + 2 * 5 + 1 + 2 + 1);
+    assert_eq!(snapshot_1[18], 2);
+    assert_eq!(
+        snapshot_1[19..],
+        [
+            1,
+            1,
+            0,
+            2,
+            center,
+            2,
+            2,
+            1,
+            MAP_WIDTH - 3,
+            center,
+            0,
+            MAP_HEIGHT - 2,
+            0,
+            BALL_SPEED
+        ]
+    );
+    assert_eq!(
+        snapshot_2[19..],
+        [
+            1,
+            1,
+            1,
+            MAP_WIDTH - 3,
+            center,
+            2,
+            2,
+            0,
+            2,
+            center,
+            0,
+            MAP_HEIGHT - 2,
+            0,
+            BALL_SPEED
+        ]
+    );
+    assert_eq!(
+        snapshot_1[9..17],
+        snapshot_2[9..17],
+        "players got different seeds"
+    );
+    assert_eq!(snapshot_1[17], STANDARD_MODE);
+
+    // Each player moves their paddle and should see it reflected in the updates
+    player_1.send(&[PLAYER_POS, 10]);
+    player_2.send(&[PLAYER_POS, 20]);
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let (update_1, update_2) = loop {
+        assert!(Instant::now() < deadline, "paddle moves never showed up");
+        // [id, player, player 1 paddle, player 2 paddle, ball x, ball y, no more paddles,
+        // no more balls]
+        let update_1 = player_1.receive_packet(UPDATE);
+        let update_2 = player_2.receive_packet(UPDATE);
+        assert_eq!(update_1[6..], [0, 0]);
+        assert_eq!(update_2[6..], [0, 0]);
+        assert_eq!((update_1[1], update_2[1]), (1, 2));
+        if update_1[2..4] == [10, 20] && update_2[2..4] == [10, 20] {
+            break (update_1, update_2);
+        }
+    };
+    // Both are sent on the same tick, player 2's ball is mirrored
+    assert_eq!(update_1[4] + update_2[4], MAP_WIDTH - 1);
+    assert_eq!(update_1[5], update_2[5]);
+
+    // There is no score cap yet, the match ends with player 1 giving up
+    player_1.send(&[FORFEIT, 0]);
+    for player in [&mut player_1, &mut player_2] {
+        let game_over = player.receive_packet(GAME_OVER);
+        assert_eq!(game_over[..2], [GAME_OVER, 2]);
+        assert_eq!(game_over[4], 1, "expected a forfeit");
+        assert_eq!(
+            player.receive().map_err(|e| e.kind()),
+            Err(ErrorKind::UnexpectedEof)
+        );
+    }
+
+    let replays = fs::read_dir(server.data_dir.join("replays"))
+        .expect("no replays directory")
+        .count();
+    assert_eq!(replays, 1);
+}
+
+#[test]
+fn modes_are_queued_separately() {
+    let server = ServerProcess::start("modes");
+    // Waits in the standard queue, the practice player shouldn't be paired with them
+    let mut waiting = ProtocolClient::register(&server.address, "alice");
+    let mut practice = ProtocolClient::register_for_mode(&server.address, "carol", PRACTICE_MODE);
+
+    let snapshot = practice.receive_packet(SNAPSHOT);
+    assert_eq!(snapshot[1], 1);
+    assert_eq!(snapshot[17], PRACTICE_MODE);
+    // Streak, best and personal best after the two paddles, the extra ball count, the
+    // arena and the ball's speed, all still empty
+    assert_eq!(snapshot[19 + 2 * 5 + 1 + 2 + 1..], [0, 0, 0]);
+
+    waiting
+        .stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert!(
+        waiting.receive().is_err(),
+        "the standard player was put into a game"
+    );
+
+    practice.send(&[FORFEIT, 0]);
+    let game_over = practice.receive_packet(GAME_OVER);
+    assert_eq!(game_over[..2], [GAME_OVER, 0], "nobody wins practice");
+    // A first practice is always a personal best
+    assert_eq!(game_over[2], game_over[3]);
+    assert_eq!(game_over[4], 1, "expected a forfeit");
+}
+
+#[test]
+fn unknown_modes_are_turned_away() {
+    let server = ServerProcess::start("unknown-mode");
+    let mut client = ProtocolClient::connect(&server.address);
+    client.send(&[SELECT_MODE, 42]);
+    let mut register = vec![REGISTER, 5];
+    register.extend_from_slice(b"alicehunter22");
+    client.send(&register);
+
+    let auth_result = client.receive_packet(AUTH_RESULT);
+    assert_eq!(
+        auth_result,
+        [AUTH_RESULT, 8],
+        "expected an unknown mode status"
+    );
+}
+
+#[test]
+fn rules_file_changes_the_game() {
+    let server = ServerProcess::start_with_rules(
+        "rules",
+        Some("speed 4\n[practice]\nsize 60 20\npaddle 2\n"),
+    );
+    let mut practice = ProtocolClient::register_for_mode(&server.address, "carol", PRACTICE_MODE);
+
+    let snapshot = practice.receive_packet(SNAPSHOT);
+    assert_eq!(snapshot[6..9], [60, 20, 2]);
+    // The speed comes right before the practice scores
+    assert_eq!(snapshot[snapshot.len() - 4..], [4, 0, 0, 0]);
+    practice.send(&[FORFEIT, 0]);
+    practice.receive_packet(GAME_OVER);
+}
+
+/// `[id, status, code length, code.., width, height, paddle, speed, best of, member count,
+/// (ready, name length, name)..]`, returns the code, rules and everyone's ready flag
+fn read_lobby(packet: &[u8]) -> (String, [u8; 5], Vec<bool>) {
+    assert_eq!(packet[..2], [LOBBY, 0], "expected an open lobby");
+    let code_end = 3 + packet[2] as usize;
+    let code = String::from_utf8(packet[3..code_end].to_vec()).unwrap();
+    let rules = packet[code_end..code_end + 5].try_into().unwrap();
+    let mut members = &packet[code_end + 6..];
+    let mut ready = Vec::new();
+    while let [flag, name_length, rest @ ..] = members {
+        ready.push(*flag == 1);
+        members = &rest[*name_length as usize..];
+    }
+    assert_eq!(ready.len(), packet[code_end + 5] as usize);
+    (code, rules, ready)
+}
+
+#[test]
+fn friends_play_each_other_through_a_lobby() {
+    let server = ServerProcess::start("lobby");
+    let mut host = ProtocolClient::register_with(&server.address, "alice", &[CREATE_LOBBY]);
+    let (code, rules, ready) = read_lobby(&host.receive_packet(LOBBY));
+    assert_eq!(code.len(), 5);
+    assert_eq!(
+        rules,
+        [MAP_WIDTH, MAP_HEIGHT, PADDLE_SIZE, BALL_SPEED, BEST_OF]
+    );
+    assert_eq!(ready, [false]);
+
+    // Someone in the public queue doesn't get pulled into the lobby
+    let mut stranger = ProtocolClient::register(&server.address, "carol");
+    let mut lost = ProtocolClient::register_with(&server.address, "erin", &[JOIN_LOBBY, b'Z']);
+    assert_eq!(
+        lost.receive_packet(LOBBY),
+        [LOBBY, 1],
+        "no lobby has that code"
+    );
+
+    let mut join = vec![JOIN_LOBBY];
+    join.extend_from_slice(code.to_lowercase().as_bytes());
+    let mut guest = ProtocolClient::register_with(&server.address, "bobby", &join);
+    let (_, _, ready) = read_lobby(&guest.receive_packet(LOBBY));
+    assert_eq!(ready, [false, false]);
+    read_lobby(&host.receive_packet(LOBBY));
+
+    // The guest is ready first, the host changing the rules makes them agree again
+    guest.send(&[READY, 1]);
+    let (_, _, ready) = read_lobby(&host.receive_packet(LOBBY));
+    assert_eq!(ready, [false, true]);
+    guest.receive_packet(LOBBY);
+    host.send(&[LOBBY_RULES, 60, 20, 2, 5, 3]);
+    let (_, rules, ready) = read_lobby(&guest.receive_packet(LOBBY));
+    assert_eq!((rules, ready), ([60, 20, 2, 5, 3], vec![false, false]));
+    host.receive_packet(LOBBY);
+    // Out of range, the old rules stand
+    host.send(&[LOBBY_RULES, 60, 20, 9, 5, 3]);
+    let rejected = host.receive_packet(LOBBY);
+    assert_eq!(rejected[1], 4);
+
+    host.send(&[READY, 1]);
+    guest.send(&[READY, 1]);
+    let snapshot_1 = host.receive_packet(SNAPSHOT);
+    let snapshot_2 = guest.receive_packet(SNAPSHOT);
+    assert_eq!((snapshot_1[1], snapshot_2[1]), (1, 2));
+    assert_eq!(snapshot_1[6..9], [60, 20, 2]);
+    // Best of three, so a series: the ball's speed, then best of, points to win a game and
+    // the games and points so far
+    assert_eq!(snapshot_1[17], SERIES_MODE);
+    assert_eq!(snapshot_1[snapshot_1.len() - 7..], [5, 3, 5, 0, 0, 0, 0]);
+
+    // The public queue carries on as before
+    let mut other = ProtocolClient::register(&server.address, "dave");
+    assert_eq!(
+        stranger.receive_packet(SNAPSHOT)[6..9],
+        [MAP_WIDTH, MAP_HEIGHT, PADDLE_SIZE]
+    );
+    other.receive_packet(SNAPSHOT);
+}
+
+/// `[id, status, format, player count, (name length, name).., match count,
+/// (side, round, first, second, winner)..]`, returns the status and each match's winner
+fn read_bracket(packet: &[u8]) -> (u8, Vec<u8>) {
+    let mut offset = 4;
+    for _ in 0..packet[3] {
+        offset += 1 + packet[offset] as usize;
+    }
+    let winners = packet[offset + 1..]
+        .chunks(5)
+        .map(|entry| entry[4])
+        .collect();
+    (packet[1], winners)
+}
+
+#[test]
+fn tournament_winners_move_on_through_the_bracket() {
+    let mut server = ServerProcess::start("tournament");
+    for username in ["alice", "bobby", "carol"] {
+        ProtocolClient::register(&server.address, username);
+    }
+    // Nobody can play the third player yet, a bye puts alice straight into the final
+    fs::write(
+        server.data_dir.join("tournament.txt"),
+        "single\nalice\nbobby\ncarol\n",
+    )
+    .unwrap();
+    server.restart();
+
+    let mut watcher = ProtocolClient::connect(&server.address);
+    watcher.send(&[BRACKET]);
+    let (status, winners) = read_bracket(&watcher.receive_packet(TOURNAMENT));
+    assert_eq!(status, 0);
+    // Seed 1 got the bye, bobby and carol haven't played, the final waits on them
+    assert_eq!(winners, [0, 0xFF, 0xFF]);
+
+    let mut bobby = ProtocolClient::login_with(&server.address, "bobby", &[JOIN_TOURNAMENT]);
+    assert_eq!(
+        bobby.receive_packet(TOURNAMENT)[1],
+        1,
+        "bobby should be waiting"
+    );
+    let mut carol = ProtocolClient::login_with(&server.address, "carol", &[JOIN_TOURNAMENT]);
+    assert_eq!(
+        carol.receive_packet(TOURNAMENT)[1],
+        1,
+        "carol should be waiting"
+    );
+    // Bobby is seeded higher, so they are player 1
+    assert_eq!(bobby.receive_packet(SNAPSHOT)[1], 1);
+    carol.receive_packet(SNAPSHOT);
+    bobby.send(&[FORFEIT, 0]);
+    assert_eq!(carol.receive_packet(GAME_OVER)[1], 2);
+
+    // Carol moves on, bobby is out
+    let deadline = Instant::now() + READ_TIMEOUT;
+    loop {
+        watcher.send(&[BRACKET]);
+        let (_, winners) = read_bracket(&watcher.receive_packet(TOURNAMENT));
+        if winners[1] == 2 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "the bracket never moved on");
+    }
+    let mut bobby = ProtocolClient::login_with(&server.address, "bobby", &[JOIN_TOURNAMENT]);
+    assert_eq!(bobby.receive_packet(TOURNAMENT), [TOURNAMENT, 2]);
+
+    let mut alice = ProtocolClient::login_with(&server.address, "alice", &[JOIN_TOURNAMENT]);
+    let mut carol = ProtocolClient::login_with(&server.address, "carol", &[JOIN_TOURNAMENT]);
+    alice.receive_packet(SNAPSHOT);
+    carol.receive_packet(SNAPSHOT);
+    carol.send(&[FORFEIT, 0]);
+    assert_eq!(alice.receive_packet(GAME_OVER)[1], 1);
+}
+
+#[test]
+fn admin_console_sees_and_kicks_players() {
+    let mut server = ServerProcess::start("admin");
+    let mut player_1 = ProtocolClient::register(&server.address, "alice");
+    let mut player_2 = ProtocolClient::register(&server.address, "bobby");
+    let snapshot = player_1.receive_packet(SNAPSHOT);
+    player_2.receive_packet(SNAPSHOT);
+    // Doubles needs four, so carol waits
+    let mut waiting = ProtocolClient::register_for_mode(&server.address, "carol", DOUBLES_MODE);
+
+    server.admin("games", "alice vs bobby");
+    server.admin("queue", "carol");
+    server.admin("say Pizza is here", "Sent to 1 games and 1 waiting players");
+    for player in [&mut player_1, &mut waiting] {
+        let message = player.receive_packet(MESSAGE);
+        assert_eq!(&message[1..], b"Pizza is here");
+    }
+
+    // Whoever is left in the game wins it
+    server.admin("kick alice", "Kicked alice from game");
+    for player in [&mut player_1, &mut player_2] {
+        let game_over = player.receive_packet(GAME_OVER);
+        assert_eq!(game_over[1], 3 - snapshot[1]);
+        assert_eq!(game_over[4], 3, "expected a kick");
+    }
+    // The game only leaves the list once its thread has saved everything and stopped
+    let deadline = Instant::now() + READ_TIMEOUT;
+    while !server.admin("games", "").contains("No games running") {
+        assert!(Instant::now() < deadline, "the kicked game never went away");
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Waiting players are told, then let go
+    server.admin("kick carol", "Kicked carol while they were waiting");
+    waiting.receive_packet(MESSAGE);
+    assert_eq!(
+        waiting.receive().map_err(|e| e.kind()),
+        Err(ErrorKind::UnexpectedEof)
+    );
+    server.admin("kick carol", "Nobody called carol");
+    server.admin("reload", "Reloaded the rules and 0 arenas");
+}
+
+#[test]
+fn shutting_down_ends_games_and_sends_everyone_away() {
+    let mut server = ServerProcess::start("shutdown");
+    let mut player_1 = ProtocolClient::register(&server.address, "alice");
+    let mut player_2 = ProtocolClient::register(&server.address, "bobby");
+    player_1.receive_packet(SNAPSHOT);
+    player_2.receive_packet(SNAPSHOT);
+    let mut waiting = ProtocolClient::register_for_mode(&server.address, "carol", DOUBLES_MODE);
+    server.admin("queue", "carol");
+
+    server.admin("shutdown", "Shutting down");
+    for player in [&mut player_1, &mut player_2, &mut waiting] {
+        let message = player.receive_packet(MESSAGE);
+        assert_eq!(&message[1..], b"The server is shutting down");
+    }
+    // Nobody wins a game the server ended
+    for player in [&mut player_1, &mut player_2] {
+        let game_over = player.receive_packet(GAME_OVER);
+        assert_eq!(game_over[1], 0);
+        assert_eq!(game_over[4], 4, "expected the server to end it");
+    }
+    assert_eq!(
+        waiting.receive().map_err(|e| e.kind()),
+        Err(ErrorKind::UnexpectedEof)
+    );
+    assert!(server.wait_for_exit(), "server didn't exit cleanly");
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_shuts_the_server_down() {
+    let mut server = ServerProcess::start("sigterm");
+    let status = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(server.wait_for_exit(), "server didn't exit cleanly");
+    assert!(TcpStream::connect(&server.address).is_err());
+}
+
+/// Scrapes the metrics endpoint the way Prometheus would, returns the body
+fn fetch_metrics(address: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: {}\r\n\r\n", address).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "got {:?}", head);
+    body.to_string()
+}
+
+fn metric(metrics: &str, name: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {} in the metrics", name))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn metrics_count_connections_games_and_disconnects() {
+    let mut server = ServerProcess::start_with("metrics", None, vec!["--metrics", "127.0.0.1:0"]);
+    let metrics_address = server
+        .printed("Metrics on http://")
+        .expect("server never said where its metrics are");
+    let metrics_address = metrics_address
+        .strip_prefix("Metrics on http://")
+        .and_then(|url| url.strip_suffix("/metrics"))
+        .unwrap()
+        .to_string();
+
+    let mut player_1 = ProtocolClient::register(&server.address, "alice");
+    let mut player_2 = ProtocolClient::register(&server.address, "bobby");
+    player_1.receive_packet(SNAPSHOT);
+    player_2.receive_packet(SNAPSHOT);
+    // Tick rates are worked out once a second
+    thread::sleep(Duration::from_millis(1500));
+
+    let metrics = fetch_metrics(&metrics_address);
+    assert_eq!(metric(&metrics, "pingpong_connections_total"), 2.0);
+    assert_eq!(metric(&metrics, "pingpong_active_games"), 1.0);
+    assert!(metric(&metrics, "pingpong_received_bytes_total") > 0.0);
+    assert!(metric(&metrics, "pingpong_sent_bytes_total") > 0.0);
+    assert!(metric(&metrics, "pingpong_tick_duration_seconds_count") > 0.0);
+    let ticks_per_second = metrics
+        .lines()
+        .find(|line| line.starts_with("pingpong_game_ticks_per_second{game="))
+        .expect("no tick rate for the game");
+    let (_, rate) = ticks_per_second.split_once("} ").unwrap();
+    assert!(rate.parse::<f64>().unwrap() > 0.0);
+
+    drop(player_1);
+    let deadline = Instant::now() + READ_TIMEOUT;
+    while metric(
+        &fetch_metrics(&metrics_address),
+        "pingpong_disconnects_total{cause=\"connection_lost\"}",
+    ) < 1.0
+    {
+        assert!(
+            Instant::now() < deadline,
+            "the dropped player wasn't counted"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}